@@ -0,0 +1,421 @@
+use anyhow::Result;
+use bson::{doc, oid::ObjectId, Document};
+use mongodb::Database;
+
+/// A rule that could not be parsed; offsets are byte offsets into the rule.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum RuleError {
+    #[error("empty rule")]
+    Empty,
+    #[error("expected {expected} at offset {pos}")]
+    Expected { expected: &'static str, pos: usize },
+    #[error("unterminated string at offset {pos}")]
+    Unterminated { pos: usize },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Field {
+    Body,
+    Header,
+    Title,
+    Banner,
+    Icon,
+    Cert,
+    Protocol,
+    Server,
+    Port,
+    /// keys this scanner does not collect; such conditions never match
+    Other(String),
+}
+
+impl Field {
+    fn from_key(key: &str) -> Field {
+        match key.to_ascii_lowercase().as_str() {
+            "body" => Field::Body,
+            "header" => Field::Header,
+            "title" => Field::Title,
+            "banner" => Field::Banner,
+            "icon" | "icon_hash" => Field::Icon,
+            "cert" => Field::Cert,
+            "protocol" => Field::Protocol,
+            "server" => Field::Server,
+            "port" => Field::Port,
+            _ => Field::Other(key.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    /// `=`: case-insensitive substring (exact match for icon/protocol/port)
+    Contains,
+    /// `==`: case-insensitive equality
+    Equals,
+    /// `!=`: negation of `=`
+    NotContains,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Match { field: Field, op: Op, value: String },
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// What a rule is evaluated against. Values are compared case-insensitively.
+#[derive(Debug, Clone, Default)]
+pub struct MatchTarget {
+    pub body: String,
+    /// status line plus response headers
+    pub header: String,
+    pub title: String,
+    /// raw service banner; for web assets this is the response header
+    pub banner: String,
+    /// certificate subject, issuer and SANs
+    pub cert: String,
+    /// favicon mmh3 hash
+    pub icon: String,
+    pub protocol: String,
+    /// `Server` header
+    pub server: String,
+    pub port: String,
+}
+
+impl MatchTarget {
+    fn lowercase(mut self) -> Self {
+        for s in [&mut self.body, &mut self.header, &mut self.title, &mut self.banner, &mut self.cert, &mut self.icon, &mut self.protocol, &mut self.server, &mut self.port] {
+            *s = s.to_lowercase();
+        }
+        self
+    }
+
+    fn get(&self, field: &Field) -> Option<&str> {
+        Some(match field {
+            Field::Body => &self.body,
+            Field::Header => &self.header,
+            Field::Title => &self.title,
+            Field::Banner => &self.banner,
+            Field::Icon => &self.icon,
+            Field::Cert => &self.cert,
+            Field::Protocol => &self.protocol,
+            Field::Server => &self.server,
+            Field::Port => &self.port,
+            Field::Other(_) => return None,
+        })
+    }
+}
+
+impl Expr {
+    /// Evaluate against a target whose fields are already lower-cased.
+    fn eval(&self, t: &MatchTarget) -> bool {
+        match self {
+            Expr::And(a, b) => a.eval(t) && b.eval(t),
+            Expr::Or(a, b) => a.eval(t) || b.eval(t),
+            Expr::Match { field, op, value } => {
+                let Some(have) = t.get(field) else { return false; };
+                let exact = matches!(field, Field::Icon | Field::Protocol | Field::Port);
+                let hit = match op {
+                    Op::Equals => have == value,
+                    _ if exact => have == value,
+                    _ => have.contains(value.as_str()),
+                };
+                if *op == Op::NotContains { !hit } else { hit }
+            }
+        }
+    }
+}
+
+/// Parse a rule such as `body="x" || (header="y" && title!="z")`.
+///
+/// `&&` binds tighter than `||`. Values may be bare words or double-quoted;
+/// quoted values often contain unescaped quotes (`header="realm="Router"`), so
+/// a quote only closes the value when it is followed by `)`s and then `&&`,
+/// `||` or the end of the rule. `\"`, `\(`, `\)` and `\\` are unescaped.
+pub fn parse_rule(rule: &str) -> Result<Expr, RuleError> {
+    if rule.trim().is_empty() { return Err(RuleError::Empty); }
+    let mut p = Parser { src: rule, pos: 0 };
+    let expr = p.or()?;
+    p.skip_ws();
+    if p.pos < rule.len() {
+        return Err(RuleError::Expected { expected: "`&&` or `||`", pos: p.pos });
+    }
+    Ok(expr)
+}
+
+struct Parser<'a> {
+    src: &'a str,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn rest(&self) -> &str { &self.src[self.pos..] }
+
+    fn skip_ws(&mut self) {
+        let trimmed = self.rest().trim_start();
+        self.pos = self.src.len() - trimmed.len();
+    }
+
+    fn eat(&mut self, tok: &str) -> bool {
+        self.skip_ws();
+        if self.rest().starts_with(tok) { self.pos += tok.len(); true } else { false }
+    }
+
+    fn or(&mut self) -> Result<Expr, RuleError> {
+        let mut lhs = self.and()?;
+        while self.eat("||") {
+            lhs = Expr::Or(Box::new(lhs), Box::new(self.and()?));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> Result<Expr, RuleError> {
+        let mut lhs = self.atom()?;
+        while self.eat("&&") {
+            lhs = Expr::And(Box::new(lhs), Box::new(self.atom()?));
+        }
+        Ok(lhs)
+    }
+
+    fn atom(&mut self) -> Result<Expr, RuleError> {
+        if self.eat("(") {
+            let e = self.or()?;
+            if !self.eat(")") { return Err(RuleError::Expected { expected: "`)`", pos: self.pos }); }
+            return Ok(e);
+        }
+        self.skip_ws();
+        let key_len = self.rest().find(|c: char| !(c.is_alphanumeric() || c == '_' || c == '.')).unwrap_or(self.rest().len());
+        if key_len == 0 { return Err(RuleError::Expected { expected: "field name", pos: self.pos }); }
+        let field = Field::from_key(&self.rest()[..key_len]);
+        self.pos += key_len;
+
+        let op = if self.eat("==") { Op::Equals } else if self.eat("!=") { Op::NotContains } else if self.eat("=") { Op::Contains } else {
+            return Err(RuleError::Expected { expected: "`=`, `==` or `!=`", pos: self.pos });
+        };
+        self.skip_ws();
+        let value = if self.rest().starts_with('"') { self.quoted()? } else { self.bare()? };
+        Ok(Expr::Match { field, op, value: unescape(&value).to_lowercase() })
+    }
+
+    fn quoted(&mut self) -> Result<String, RuleError> {
+        let start = self.pos;
+        let bytes = self.src.as_bytes();
+        let mut i = start + 1;
+        while i < bytes.len() {
+            match bytes[i] {
+                b'\\' => i += 1,
+                b'"' if closes(&self.src[i + 1..]) => {
+                    self.pos = i + 1;
+                    return Ok(self.src[start + 1..i].to_string());
+                }
+                _ => {}
+            }
+            i += 1;
+        }
+        Err(RuleError::Unterminated { pos: start })
+    }
+
+    fn bare(&mut self) -> Result<String, RuleError> {
+        let rest = self.rest();
+        let len = rest.find(|c: char| c.is_whitespace() || c == ')' || c == '&' || c == '|').unwrap_or(rest.len());
+        if len == 0 { return Err(RuleError::Expected { expected: "value", pos: self.pos }); }
+        let value = rest[..len].to_string();
+        self.pos += len;
+        Ok(value)
+    }
+}
+
+/// Whether a quote followed by `rest` ends a value.
+fn closes(rest: &str) -> bool {
+    let rest = rest.trim_start_matches(|c: char| c == ')' || c.is_whitespace());
+    rest.is_empty() || rest.starts_with("&&") || rest.starts_with("||")
+}
+
+fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('\\', Some(&n @ ('"' | '(' | ')' | '\\'))) => { out.push(n); chars.next(); }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone)]
+pub struct FingerprintRule {
+    pub id: ObjectId,
+    pub name: String,
+    pub category: String,
+    pub parent_category: String,
+    pub expr: Expr,
+}
+
+/// Enabled rules from the `FingerprintRules` collection.
+#[derive(Debug, Clone, Default)]
+pub struct FingerprintRules {
+    pub rules: Vec<FingerprintRule>,
+}
+
+impl FingerprintRules {
+    /// Load rules with `state: true`; rules that fail to parse are logged and skipped.
+    pub async fn load(db: &Database) -> Result<Self> {
+        let mut rules = vec![];
+        let mut cursor = db.collection::<Document>("FingerprintRules").find(doc!{"state": true}).await?;
+        while cursor.advance().await? {
+            let d = cursor.deserialize_current()?;
+            let (Ok(id), Ok(name), Ok(rule)) = (d.get_object_id("_id"), d.get_str("name"), d.get_str("rule")) else { continue; };
+            match parse_rule(rule) {
+                Ok(expr) => rules.push(FingerprintRule {
+                    id,
+                    name: name.to_string(),
+                    category: d.get_str("category").unwrap_or_default().to_string(),
+                    parent_category: d.get_str("parent_category").unwrap_or_default().to_string(),
+                    expr,
+                }),
+                Err(e) => tracing::warn!("fingerprint rule {} ({}): {}", name, id, e),
+            }
+        }
+        Ok(FingerprintRules { rules })
+    }
+
+    pub fn matches(&self, target: MatchTarget) -> Vec<&FingerprintRule> {
+        let target = target.lowercase();
+        self.rules.iter().filter(|r| r.expr.eval(&target)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn m(field: Field, op: Op, value: &str) -> Expr {
+        Expr::Match { field, op, value: value.to_string() }
+    }
+
+    fn and(a: Expr, b: Expr) -> Expr { Expr::And(Box::new(a), Box::new(b)) }
+
+    fn or(a: Expr, b: Expr) -> Expr { Expr::Or(Box::new(a), Box::new(b)) }
+
+    fn target() -> MatchTarget {
+        MatchTarget {
+            body: "<html><title>Router Login</title>Powered by ThinkPHP</html>".to_string(),
+            header: "HTTP/1.1 401 Unauthorized\r\nWWW-Authenticate: Basic realm=\"Router\"\r\nServer: nginx".to_string(),
+            title: "Router Login".to_string(),
+            icon: "-1293291467".to_string(),
+            protocol: "http".to_string(),
+            server: "nginx".to_string(),
+            port: "8080".to_string(),
+            ..Default::default()
+        }.lowercase()
+    }
+
+    fn eval(rule: &str) -> bool {
+        parse_rule(rule).unwrap().eval(&target())
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        assert_eq!(parse_rule(r#"body="a" || title="b" && header="c""#).unwrap(),
+            or(m(Field::Body, Op::Contains, "a"), and(m(Field::Title, Op::Contains, "b"), m(Field::Header, Op::Contains, "c"))));
+        assert_eq!(parse_rule(r#"body="a" && title="b" || header="c""#).unwrap(),
+            or(and(m(Field::Body, Op::Contains, "a"), m(Field::Title, Op::Contains, "b")), m(Field::Header, Op::Contains, "c")));
+        // chains are left-associative
+        assert_eq!(parse_rule(r#"body="a" || body="b" || body="c""#).unwrap(),
+            or(or(m(Field::Body, Op::Contains, "a"), m(Field::Body, Op::Contains, "b")), m(Field::Body, Op::Contains, "c")));
+    }
+
+    #[test]
+    fn parentheses_override_precedence() {
+        assert_eq!(parse_rule(r#"(body="a" || title="b") && header="c""#).unwrap(),
+            and(or(m(Field::Body, Op::Contains, "a"), m(Field::Title, Op::Contains, "b")), m(Field::Header, Op::Contains, "c")));
+        assert_eq!(parse_rule(r#"((body="a")) && ( title="b" || (header="c" && port=80) )"#).unwrap(),
+            and(m(Field::Body, Op::Contains, "a"),
+                or(m(Field::Title, Op::Contains, "b"), and(m(Field::Header, Op::Contains, "c"), m(Field::Port, Op::Contains, "80")))));
+        // the grouping decides the result
+        assert!(eval(r#"title="missing" && body="thinkphp" || server="nginx""#));
+        assert!(!eval(r#"title="missing" && (body="thinkphp" || server="nginx")"#));
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(parse_rule(r#"title!="admin""#).unwrap(), m(Field::Title, Op::NotContains, "admin"));
+        assert_eq!(parse_rule(r#"server=="nginx""#).unwrap(), m(Field::Server, Op::Equals, "nginx"));
+        assert_eq!(parse_rule("icon_hash = -1293291467").unwrap(), m(Field::Icon, Op::Contains, "-1293291467"));
+        assert!(eval(r#"title!="admin""#));
+        assert!(!eval(r#"title!="router""#));
+        assert!(eval(r#"title="ROUTER""#));
+        assert!(!eval(r#"title=="router""#));
+        assert!(eval(r#"title=="Router Login""#));
+        // icon, protocol and port never match on a substring
+        assert!(eval("icon_hash=-1293291467 && protocol=http && port=8080"));
+        assert!(!eval("icon_hash=-129329 || protocol=htt || port=80"));
+        assert!(eval("port!=80"));
+    }
+
+    #[test]
+    fn unknown_fields_never_match() {
+        assert_eq!(parse_rule(r#"cname="cdn""#).unwrap(), m(Field::Other("cname".to_string()), Op::Contains, "cdn"));
+        assert!(!eval(r#"cname="cdn""#));
+        assert!(!eval(r#"cname!="cdn""#));
+        assert!(eval(r#"cname="cdn" || title="router""#));
+    }
+
+    #[test]
+    fn escaped_quotes() {
+        assert_eq!(parse_rule(r#"body="say \"hi\"""#).unwrap(), m(Field::Body, Op::Contains, r#"say "hi""#));
+        assert_eq!(parse_rule(r#"body="f\(x\) \\ y""#).unwrap(), m(Field::Body, Op::Contains, r"f(x) \ y"));
+        // an escaped quote followed by `&&` does not close the value
+        assert_eq!(parse_rule(r#"body="a\" && b" && title="c""#).unwrap(),
+            and(m(Field::Body, Op::Contains, r#"a" && b"#), m(Field::Title, Op::Contains, "c")));
+    }
+
+    #[test]
+    fn embedded_quotes() {
+        assert_eq!(parse_rule(r#"header="realm="X"""#).unwrap(), m(Field::Header, Op::Contains, r#"realm="x""#));
+        assert_eq!(parse_rule(r#"header="realm="Router"" && title="login""#).unwrap(),
+            and(m(Field::Header, Op::Contains, r#"realm="router""#), m(Field::Title, Op::Contains, "login")));
+        assert_eq!(parse_rule(r#"(body="<a href="/x">" || header="realm="X"") && port=80"#).unwrap(),
+            and(or(m(Field::Body, Op::Contains, r#"<a href="/x">"#), m(Field::Header, Op::Contains, r#"realm="x""#)), m(Field::Port, Op::Contains, "80")));
+        assert!(eval(r#"header="realm="Router"" && title="login""#));
+        assert!(!eval(r#"header="realm="Camera"""#));
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        assert_eq!(parse_rule("  "), Err(RuleError::Empty));
+        assert_eq!(parse_rule(r#"body="x"#), Err(RuleError::Unterminated { pos: 5 }));
+        assert_eq!(parse_rule("body"), Err(RuleError::Expected { expected: "`=`, `==` or `!=`", pos: 4 }));
+        assert_eq!(parse_rule("body="), Err(RuleError::Expected { expected: "value", pos: 5 }));
+        assert_eq!(parse_rule(r#"(body="x""#), Err(RuleError::Expected { expected: "`)`", pos: 9 }));
+        assert_eq!(parse_rule(r#"body=x title="y""#), Err(RuleError::Expected { expected: "`&&` or `||`", pos: 7 }));
+        // a quote that is not followed by `&&`, `||` or the end is part of the value
+        assert_eq!(parse_rule(r#"body="x" title="y""#).unwrap(), m(Field::Body, Op::Contains, r#"x" title="y"#));
+        assert_eq!(parse_rule(r#"&& body="x""#), Err(RuleError::Expected { expected: "field name", pos: 0 }));
+        assert_eq!(parse_rule(r#"body="x" ||"#), Err(RuleError::Expected { expected: "field name", pos: 11 }));
+    }
+
+    #[test]
+    fn rule_set_matches() {
+        let rule = |name: &str, src: &str| FingerprintRule {
+            id: ObjectId::new(),
+            name: name.to_string(),
+            category: String::new(),
+            parent_category: String::new(),
+            expr: parse_rule(src).unwrap(),
+        };
+        let rules = FingerprintRules { rules: vec![
+            rule("ThinkPHP", r#"body="thinkphp" || header="X-Powered-By: ThinkPHP""#),
+            rule("Router", r#"header="realm="Router"" && title!="camera""#),
+            rule("Apache", r#"server="apache""#),
+        ] };
+        let target = MatchTarget {
+            body: "Powered by ThinkPHP".to_string(),
+            header: "WWW-Authenticate: Basic realm=\"Router\"".to_string(),
+            server: "nginx".to_string(),
+            ..Default::default()
+        };
+        let names: Vec<&str> = rules.matches(target).iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, ["ThinkPHP", "Router"]);
+    }
+}
@@ -2,4 +2,8 @@ pub mod settings;
 pub mod mongo;
 pub mod rds;
 pub mod models;
-pub mod util;
\ No newline at end of file
+pub mod util;
+pub mod fingerprint;
+pub mod sensitive;
+pub mod poc;
+pub mod template;
\ No newline at end of file
@@ -1,3 +1,7 @@
+//! Request bodies and Redis payloads. Field names follow the Python
+//! backend's JSON, hence the camel and Pascal case.
+#![allow(non_snake_case)]
+
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -15,7 +19,9 @@ pub struct TaskAddRequest {
     pub scheduledTasks: bool,
     pub template: String,
     #[serde(default)]
-    pub duplicates: bool,
+    pub duplicates: Duplicates,
+    #[serde(default)]
+    pub distribution: Distribution,
     #[serde(default)]
     pub cycleType: Option<String>,
     #[serde(default)]
@@ -24,6 +30,80 @@ pub struct TaskAddRequest {
     pub minute: Option<u32>,
     #[serde(default)]
     pub day: Option<u32>,
+    #[serde(default)]
+    pub week: Option<u32>,
+    #[serde(default)]
+    pub cron: Option<String>,
+}
+
+fn default_page_index() -> u64 { 1 }
+fn default_page_size() -> u64 { 10 }
+
+/// Most rows one page returns; larger `pageSize`s are cut to this.
+pub const MAX_PAGE_SIZE: u64 = 1000;
+
+/// `(skip, limit)` of a 1-based page, its size clamped to
+/// `1..=MAX_PAGE_SIZE`. `None` for a `pageIndex` below 1.
+pub fn page_bounds(page_index: u64, page_size: u64) -> Option<(u64, u64)> {
+    if page_index < 1 { return None; }
+    let size = page_size.clamp(1, MAX_PAGE_SIZE);
+    // MongoDB takes skip as an i64
+    let skip = (page_index - 1).saturating_mul(size).min(i64::MAX as u64);
+    Some((skip, size))
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskDataRequest {
+    #[serde(default)]
+    pub search: String,
+    #[serde(default = "default_page_index")]
+    pub pageIndex: u64,
+    #[serde(default = "default_page_size")]
+    pub pageSize: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskIdRequest {
+    pub id: String,
+    #[serde(default = "default_page_index")]
+    pub pageIndex: u64,
+    #[serde(default = "default_page_size")]
+    pub pageSize: u64,
+}
+
+impl TaskDataRequest {
+    /// See [`page_bounds`].
+    pub fn page(&self) -> Option<(u64, u64)> { page_bounds(self.pageIndex, self.pageSize) }
+}
+
+impl TaskIdRequest {
+    /// See [`page_bounds`].
+    pub fn page(&self) -> Option<(u64, u64)> { page_bounds(self.pageIndex, self.pageSize) }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskDeleteRequest {
+    #[serde(default)]
+    pub ids: Vec<String>,
+    #[serde(default)]
+    pub delA: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TaskIdsRequest {
+    #[serde(default)]
+    pub ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LoginRequest {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChangePasswordRequest {
+    pub newPassword: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -36,20 +116,229 @@ pub struct TemplateDoc {
     pub vullist: Vec<String>,
 }
 
+/// What a task does with subdomains that are already stored. Stored as
+/// `None`/`subdomain`/`task` like the web UI sends it; the booleans older
+/// tasks carry read as `None` and `subdomain`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(from = "DuplicatesRepr", into = "String")]
+pub enum Duplicates {
+    /// known hosts are scanned again and their documents updated
+    #[default]
+    None,
+    /// hosts found by any earlier task are skipped
+    Subdomain,
+    /// hosts already found by this task are skipped
+    Task,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DuplicatesRepr {
+    Bool(bool),
+    Str(String),
+}
+
+impl From<DuplicatesRepr> for Duplicates {
+    fn from(r: DuplicatesRepr) -> Self {
+        match r {
+            DuplicatesRepr::Bool(true) => Duplicates::Subdomain,
+            DuplicatesRepr::Bool(false) => Duplicates::None,
+            DuplicatesRepr::Str(s) => match s.to_lowercase().as_str() {
+                "subdomain" | "global" => Duplicates::Subdomain,
+                "task" => Duplicates::Task,
+                _ => Duplicates::None,
+            },
+        }
+    }
+}
+
+impl From<Duplicates> for String {
+    fn from(d: Duplicates) -> Self {
+        match d {
+            Duplicates::None => "None",
+            Duplicates::Subdomain => "subdomain",
+            Duplicates::Task => "task",
+        }.to_string()
+    }
+}
+
+/// How a task's targets are spread over its nodes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Distribution {
+    /// one queue every node pulls from
+    #[default]
+    Shared,
+    /// targets dealt to the nodes in turn
+    RoundRobin,
+    /// targets dealt in proportion to each node's reported free capacity
+    Weighted,
+    /// each target hashed to a node, so the same host lands on the same node
+    /// across tasks
+    Sticky,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DispatchTemplate {
     pub Parameters: HashMap<String, HashMap<String, String>>, // resolved
     pub TaskName: String,
     pub ignore: String,
-    pub duplicates: bool,
+    #[serde(default)]
+    pub duplicates: Duplicates,
     pub ID: String,
     pub r#type: String,
     #[serde(default)]
     pub IsStart: bool,
+    /// `PocList` ids to run in the vulnerability scan, or `All Poc`
+    #[serde(default)]
+    pub vullist: Vec<String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct NodeLogPayload<'a> {
     pub name: &'a str,
     pub log: &'a str,
-}
\ No newline at end of file
+}
+/// Lifecycle of a scanner node, kept in its `node:{name}` hash both as
+/// `status` and as the numeric `state` the web UI reads (`1` running, `2`
+/// stopped, `3` error).
+///
+/// A node registers `Online`, is `Busy` while it runs a task, turns
+/// `Draining` when asked to stop (it takes no new work but finishes what it
+/// holds) and ends `Offline`, either on shutdown or when the scheduler sees
+/// its heartbeat go stale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeState {
+    Online,
+    Busy,
+    Draining,
+    Offline,
+}
+
+impl NodeState {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            NodeState::Online => "online",
+            NodeState::Busy => "busy",
+            NodeState::Draining => "draining",
+            NodeState::Offline => "offline",
+        }
+    }
+
+    /// The `state` code of the Python backend and web UI.
+    pub fn code(self) -> &'static str {
+        match self {
+            NodeState::Online | NodeState::Busy => "1",
+            NodeState::Draining => "2",
+            NodeState::Offline => "3",
+        }
+    }
+
+    /// State from a node hash; nodes that only write `state` map by code.
+    pub fn from_fields(status: Option<&str>, code: Option<&str>) -> Self {
+        match status {
+            Some("online") => NodeState::Online,
+            Some("busy") => NodeState::Busy,
+            Some("draining") => NodeState::Draining,
+            Some("offline") => NodeState::Offline,
+            _ => match code {
+                Some("1") => NodeState::Online,
+                Some("2") => NodeState::Draining,
+                _ => NodeState::Offline,
+            },
+        }
+    }
+
+    /// Whether the node takes new tasks.
+    pub fn accepts_tasks(self) -> bool {
+        matches!(self, NodeState::Online | NodeState::Busy)
+    }
+
+    /// Whether a node in this state may move to `next`.
+    pub fn can_become(self, next: NodeState) -> bool {
+        use NodeState::*;
+        match (self, next) {
+            (a, b) if a == b => true,
+            (_, Offline) => true,
+            (Offline, Online) => true,
+            (Online, Busy) | (Busy, Online) => true,
+            (Online | Busy, Draining) => true,
+            (Draining, Online) => true,
+            _ => false,
+        }
+    }
+}
+
+/// Node-wide caps on what templates ask for; `None` leaves the template's
+/// value alone.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct NodeLimits {
+    /// most concurrent requests/checks a module may run (its `-t`)
+    #[serde(default)]
+    pub concurrency: Option<usize>,
+    /// most packets per second a port scan may send (its `-r`)
+    #[serde(default)]
+    pub rate: Option<u64>,
+}
+
+/// A command for a scanner node, queued on `NodeControl:{name}` as
+/// `{"type": "pause"}`, `{"type": "set_limits", "concurrency": 10}`, ...
+///
+/// Commands never drop work a node holds: pausing and stopping let the
+/// target in progress finish, and whatever is still claimed afterwards goes
+/// back to the queues.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum NodeCommand {
+    /// stop taking targets after the current one (the node shows `draining`)
+    Pause,
+    /// take targets again after a pause
+    Resume,
+    /// finish the current target, hand back the rest and start again
+    Restart,
+    /// finish the current target, hand back the rest, deregister and exit
+    Uninstall,
+    /// replace the node's [`NodeLimits`]
+    SetLimits(NodeLimits),
+    /// re-read the node's resolver list and drop cached rules, dictionaries
+    /// and POCs
+    ReloadConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeControlRequest {
+    /// node names, or `all`
+    pub names: Vec<String>,
+    pub command: NodeCommand,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeNameRequest {
+    #[serde(default)]
+    pub name: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn page_bounds_clamp_and_saturate() {
+        assert_eq!(page_bounds(1, 10), Some((0, 10)));
+        assert_eq!(page_bounds(3, 20), Some((40, 20)));
+        assert_eq!(page_bounds(0, 10), None);
+        assert_eq!(page_bounds(2, 0), Some((1, 1)));
+        assert_eq!(page_bounds(2, u64::MAX), Some((MAX_PAGE_SIZE, MAX_PAGE_SIZE)));
+        assert_eq!(page_bounds(u64::MAX, u64::MAX), Some((i64::MAX as u64, MAX_PAGE_SIZE)));
+        assert_eq!(page_bounds(u64::MAX / 10, 100), Some((i64::MAX as u64, 100)));
+    }
+
+    #[test]
+    fn page_defaults() {
+        let req: TaskDataRequest = serde_json::from_str("{}").unwrap();
+        assert_eq!(req.page(), Some((0, 10)));
+        let req: TaskIdRequest = serde_json::from_str(r#"{"id": "x", "pageIndex": 0}"#).unwrap();
+        assert_eq!(req.page(), None);
+    }
+}
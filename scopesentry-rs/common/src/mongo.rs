@@ -1,4 +1,5 @@
 use anyhow::Result;
+use futures_util::AsyncReadExt;
 use mongodb::{options::ClientOptions, Client, Database};
 use crate::settings::AppConfig;
 
@@ -18,4 +19,13 @@ pub async fn connect_mongo(cfg: &AppConfig) -> Result<Client> {
 
 pub fn db(client: &Client, cfg: &AppConfig) -> Database {
     client.database(&cfg.mongodb.mongodb_database)
+}
+
+/// Contents of a GridFS file. Dictionaries are stored under their `_id` hex
+/// as the filename, which is what `{dict.*}` placeholders resolve to.
+pub async fn read_gridfs(db: &Database, filename: &str) -> Result<Vec<u8>> {
+    let mut stream = db.gridfs_bucket(None).open_download_stream_by_name(filename).await?;
+    let mut buf = vec![];
+    stream.read_to_end(&mut buf).await?;
+    Ok(buf)
 }
\ No newline at end of file
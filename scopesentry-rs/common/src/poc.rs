@@ -0,0 +1,1368 @@
+use std::{collections::HashMap, fmt};
+
+use anyhow::Result;
+use async_trait::async_trait;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bson::{doc, oid::ObjectId, Document};
+use mongodb::Database;
+use regex::Regex;
+use serde::Deserialize;
+use serde_yaml::{Mapping, Value as Yaml};
+use url::{Position, Url};
+
+/// `vullist` entry that selects every POC.
+pub const ALL_POC: &str = "All Poc";
+
+/// Redirects followed when a template sets `redirects` without `max-redirects`.
+const DEFAULT_MAX_REDIRECTS: usize = 10;
+
+/// Top-level keys of protocols other than HTTP, and of nuclei features the
+/// engine does not implement.
+const UNSUPPORTED_KEYS: [&str; 12] = [
+    "dns", "network", "tcp", "file", "headless", "ssl", "websocket", "whois", "code", "javascript", "flow", "workflows",
+];
+
+/// A template the engine cannot run.
+#[derive(Debug, thiserror::Error)]
+pub enum PocError {
+    #[error("invalid yaml: {0}")]
+    Yaml(#[from] serde_yaml::Error),
+    #[error("no http requests")]
+    NoRequests,
+    #[error("unsupported {0}")]
+    Unsupported(String),
+    #[error("invalid regex: {0}")]
+    Regex(#[from] regex::Error),
+    #[error("invalid dsl `{expr}`: {msg}")]
+    Dsl { expr: String, msg: String },
+}
+
+// ---- template model ------------------------------------------------------
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Condition { And, Or }
+
+impl Condition {
+    fn parse(s: &str) -> Result<Condition, PocError> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "" | "or" => Ok(Condition::Or),
+            "and" => Ok(Condition::And),
+            other => Err(PocError::Unsupported(format!("condition `{}`", other))),
+        }
+    }
+
+    fn combine(self, mut hits: impl Iterator<Item = bool>) -> bool {
+        match self {
+            Condition::And => hits.all(|h| h),
+            Condition::Or => hits.any(|h| h),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MatcherKind {
+    Status(Vec<u16>),
+    Size(Vec<usize>),
+    Words { words: Vec<String>, case_insensitive: bool },
+    Regex(Vec<Regex>),
+    Dsl(Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    pub kind: MatcherKind,
+    /// response variable the matcher looks at (`body`, `header`, `all`, a header name, ...)
+    pub part: String,
+    pub condition: Condition,
+    pub negative: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum ExtractorKind {
+    Regex { regex: Vec<Regex>, group: usize },
+    /// header values by name
+    Kval(Vec<String>),
+    Dsl(Vec<Expr>),
+}
+
+#[derive(Debug, Clone)]
+pub struct Extractor {
+    pub kind: ExtractorKind,
+    pub name: String,
+    pub part: String,
+    /// internal extractors only feed variables to later requests
+    pub internal: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum RequestSpec {
+    /// `method` + `path` request; `{{...}}` markers are resolved at run time
+    Path { method: String, path: String, headers: Vec<(String, String)>, body: String },
+    /// raw HTTP request text
+    Raw(String),
+}
+
+/// One entry of the template's `http` list: the requests it sends and how
+/// their responses are judged.
+#[derive(Debug, Clone)]
+pub struct HttpBlock {
+    pub requests: Vec<RequestSpec>,
+    pub matchers: Vec<Matcher>,
+    pub condition: Condition,
+    pub extractors: Vec<Extractor>,
+    /// 0 when redirects are not followed
+    pub max_redirects: usize,
+    /// evaluate the matchers once, after the last request, over all responses
+    pub req_condition: bool,
+}
+
+/// A POC from the `PocList` collection.
+#[derive(Debug, Clone)]
+pub struct Poc {
+    pub id: ObjectId,
+    /// `id` inside the template
+    pub template_id: String,
+    pub name: String,
+    pub severity: String,
+    pub variables: Vec<(String, String)>,
+    pub blocks: Vec<HttpBlock>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawTemplate {
+    id: String,
+    info: RawInfo,
+    variables: Mapping,
+    #[serde(alias = "requests")]
+    http: Vec<RawRequest>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawInfo {
+    name: String,
+    severity: String,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case")]
+struct RawRequest {
+    method: String,
+    path: Vec<String>,
+    raw: Vec<String>,
+    headers: Mapping,
+    body: String,
+    matchers: Vec<RawMatcher>,
+    matchers_condition: String,
+    extractors: Vec<RawExtractor>,
+    redirects: bool,
+    host_redirects: bool,
+    max_redirects: Option<usize>,
+    req_condition: bool,
+    payloads: Option<Yaml>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, rename_all = "kebab-case")]
+struct RawMatcher {
+    #[serde(rename = "type")]
+    kind: String,
+    part: String,
+    /// scalars; templates list numbers as words too
+    words: Vec<Yaml>,
+    regex: Vec<String>,
+    status: Vec<u16>,
+    size: Vec<usize>,
+    dsl: Vec<String>,
+    condition: String,
+    negative: bool,
+    case_insensitive: bool,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RawExtractor {
+    #[serde(rename = "type")]
+    kind: String,
+    name: String,
+    part: String,
+    regex: Vec<String>,
+    group: usize,
+    kval: Vec<String>,
+    dsl: Vec<String>,
+    internal: bool,
+}
+
+fn scalar(v: &Yaml) -> Option<String> {
+    match v {
+        Yaml::String(s) => Some(s.clone()),
+        Yaml::Number(n) => Some(n.to_string()),
+        Yaml::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+fn pairs(map: &Mapping) -> Vec<(String, String)> {
+    map.iter().filter_map(|(k, v)| Some((scalar(k)?, scalar(v)?))).collect()
+}
+
+fn part_or_body(part: &str) -> String {
+    if part.is_empty() { "body".to_string() } else { part.to_string() }
+}
+
+fn parse_exprs(exprs: &[String]) -> Result<Vec<Expr>, PocError> {
+    exprs.iter().map(|e| parse_dsl(e).map_err(|msg| PocError::Dsl { expr: e.clone(), msg })).collect()
+}
+
+impl Matcher {
+    fn from_raw(m: RawMatcher) -> Result<Matcher, PocError> {
+        let kind = match m.kind.as_str() {
+            "status" => MatcherKind::Status(m.status),
+            "size" => MatcherKind::Size(m.size),
+            "word" => MatcherKind::Words { words: m.words.iter().filter_map(scalar).collect(), case_insensitive: m.case_insensitive },
+            "regex" => MatcherKind::Regex(m.regex.iter().map(|r| Regex::new(r)).collect::<Result<_, _>>()?),
+            "dsl" => MatcherKind::Dsl(parse_exprs(&m.dsl)?),
+            other => return Err(PocError::Unsupported(format!("matcher type `{}`", other))),
+        };
+        Ok(Matcher { kind, part: part_or_body(&m.part), condition: Condition::parse(&m.condition)?, negative: m.negative })
+    }
+
+    fn matches(&self, vars: &Vars) -> bool {
+        let part = || vars.get(&self.part).map(|v| v.to_string()).unwrap_or_default();
+        let hit = match &self.kind {
+            MatcherKind::Status(codes) => vars.get("status_code")
+                .and_then(Value::as_num)
+                .map(|s| codes.iter().any(|c| f64::from(*c) == s))
+                .unwrap_or(false),
+            MatcherKind::Size(sizes) => sizes.contains(&part().len()),
+            MatcherKind::Words { words, case_insensitive } => {
+                let text = if *case_insensitive { part().to_lowercase() } else { part() };
+                self.condition.combine(words.iter().map(|w| {
+                    let w = substitute(w, vars).unwrap_or_else(|| w.clone());
+                    if *case_insensitive { text.contains(&w.to_lowercase()) } else { text.contains(&w) }
+                }))
+            }
+            MatcherKind::Regex(res) => {
+                let text = part();
+                self.condition.combine(res.iter().map(|r| r.is_match(&text)))
+            }
+            MatcherKind::Dsl(exprs) => self.condition.combine(exprs.iter().map(|e| e.eval(vars).map(|v| v.truthy()).unwrap_or(false))),
+        };
+        hit != self.negative
+    }
+}
+
+impl Extractor {
+    /// `None` for extractor types the engine does not run.
+    fn from_raw(e: RawExtractor) -> Result<Option<Extractor>, PocError> {
+        let kind = match e.kind.as_str() {
+            "regex" => ExtractorKind::Regex { regex: e.regex.iter().map(|r| Regex::new(r)).collect::<Result<_, _>>()?, group: e.group },
+            "kval" => ExtractorKind::Kval(e.kval.iter().map(|k| k.to_lowercase().replace('-', "_")).collect()),
+            "dsl" => ExtractorKind::Dsl(parse_exprs(&e.dsl)?),
+            _ => return Ok(None),
+        };
+        Ok(Some(Extractor { kind, name: e.name, part: part_or_body(&e.part), internal: e.internal }))
+    }
+
+    fn extract(&self, vars: &Vars) -> Vec<String> {
+        let mut out: Vec<String> = vec![];
+        match &self.kind {
+            ExtractorKind::Regex { regex, group } => {
+                let text = vars.get(&self.part).map(|v| v.to_string()).unwrap_or_default();
+                for re in regex {
+                    out.extend(re.captures_iter(&text).filter_map(|c| c.get(*group)).map(|m| m.as_str().to_string()));
+                }
+            }
+            ExtractorKind::Kval(keys) => out.extend(keys.iter().filter_map(|k| vars.get(k)).map(|v| v.to_string())),
+            ExtractorKind::Dsl(exprs) => out.extend(exprs.iter().filter_map(|e| e.eval(vars).ok()).map(|v| v.to_string())),
+        }
+        out.retain(|v| !v.is_empty());
+        out.dedup();
+        out
+    }
+}
+
+impl Poc {
+    /// Parse a nuclei-style template. Only HTTP requests are supported;
+    /// templates using payloads, interactsh, other protocols or unknown
+    /// matcher types are rejected. Unknown extractor types are dropped unless
+    /// they are internal, since later requests would depend on them.
+    pub fn parse(id: ObjectId, content: &str) -> Result<Poc, PocError> {
+        if content.contains("interactsh") {
+            return Err(PocError::Unsupported("interactsh".to_string()));
+        }
+        let value: Yaml = serde_yaml::from_str(content)?;
+        if let Some(map) = value.as_mapping() {
+            if let Some(key) = UNSUPPORTED_KEYS.iter().find(|k| map.contains_key(**k)) {
+                return Err(PocError::Unsupported(format!("`{}`", key)));
+            }
+        }
+        let raw: RawTemplate = serde_yaml::from_value(value)?;
+
+        let mut blocks = vec![];
+        for r in raw.http {
+            if r.payloads.is_some() {
+                return Err(PocError::Unsupported("payloads".to_string()));
+            }
+            let method = if r.method.is_empty() { "GET".to_string() } else { r.method.to_uppercase() };
+            let headers = pairs(&r.headers);
+            let mut requests: Vec<RequestSpec> = r.path.iter()
+                .map(|p| RequestSpec::Path { method: method.clone(), path: p.clone(), headers: headers.clone(), body: r.body.clone() })
+                .collect();
+            requests.extend(r.raw.into_iter().map(RequestSpec::Raw));
+            if requests.is_empty() { continue; }
+
+            let mut extractors = vec![];
+            for e in r.extractors {
+                let internal = e.internal;
+                match Extractor::from_raw(e)? {
+                    Some(x) => extractors.push(x),
+                    None if internal => return Err(PocError::Unsupported("internal extractor type".to_string())),
+                    None => {}
+                }
+            }
+            let follow = r.redirects || r.host_redirects;
+            blocks.push(HttpBlock {
+                requests,
+                matchers: r.matchers.into_iter().map(Matcher::from_raw).collect::<Result<_, _>>()?,
+                condition: Condition::parse(&r.matchers_condition)?,
+                extractors,
+                max_redirects: if follow { r.max_redirects.unwrap_or(DEFAULT_MAX_REDIRECTS) } else { 0 },
+                req_condition: r.req_condition,
+            });
+        }
+        if blocks.is_empty() { return Err(PocError::NoRequests); }
+
+        Ok(Poc {
+            id,
+            template_id: raw.id,
+            name: raw.info.name,
+            severity: raw.info.severity.to_lowercase(),
+            variables: pairs(&raw.variables),
+            blocks,
+        })
+    }
+
+    /// Run the POC against `target`. `extra` adds variables such as
+    /// `randstr`. Blocks run in order and the first matching request is
+    /// returned; a request that cannot be built or sent is skipped.
+    pub async fn run(&self, sender: &dyn HttpSender, target: &Url, extra: &HashMap<String, String>) -> Option<Finding> {
+        let mut vars: Vars = target_vars(target).into_iter().chain(extra.clone()).map(|(k, v)| (k, Value::Str(v))).collect();
+        for (k, v) in &self.variables {
+            let v = substitute(v, &vars)?;
+            vars.insert(k.clone(), Value::Str(v));
+        }
+
+        for block in &self.blocks {
+            let mut extracted: Vec<String> = vec![];
+            for (i, spec) in block.requests.iter().enumerate() {
+                let Some(req) = spec.build(&vars) else { continue; };
+                let Ok(resp) = sender.send(&req, block.max_redirects).await else { continue; };
+                for (k, v) in response_vars(&resp) {
+                    vars.insert(format!("{}_{}", k, i + 1), v.clone());
+                    vars.insert(k, v);
+                }
+                for e in &block.extractors {
+                    let values = e.extract(&vars);
+                    if let (false, Some(first)) = (e.name.is_empty(), values.first()) {
+                        vars.insert(e.name.clone(), Value::Str(first.clone()));
+                    }
+                    if !e.internal {
+                        for v in values {
+                            if !extracted.contains(&v) { extracted.push(v); }
+                        }
+                    }
+                }
+
+                if block.req_condition && i + 1 < block.requests.len() { continue; }
+                let matched = if block.matchers.is_empty() {
+                    !extracted.is_empty()
+                } else {
+                    block.condition.combine(block.matchers.iter().map(|m| m.matches(&vars)))
+                };
+                if matched {
+                    return Some(Finding { matched: req.url.clone(), request: req.raw(), response: resp.raw(), extracted });
+                }
+            }
+        }
+        None
+    }
+}
+
+/// Templates of the `PocList` collection the engine can run.
+#[derive(Debug, Clone, Default)]
+pub struct PocList {
+    pub pocs: Vec<Poc>,
+}
+
+impl PocList {
+    /// Load all POCs. The collection mixes in templates for protocols and
+    /// features the engine does not implement; those are skipped and counted.
+    pub async fn load(db: &Database) -> Result<Self> {
+        let mut pocs = vec![];
+        let mut skipped = 0;
+        let mut cursor = db.collection::<Document>("PocList").find(doc!{}).await?;
+        while cursor.advance().await? {
+            let d = cursor.deserialize_current()?;
+            let (Ok(id), Ok(content)) = (d.get_object_id("_id"), d.get_str("content")) else { continue; };
+            let name = d.get_str("name").unwrap_or_default();
+            match Poc::parse(id, content) {
+                Ok(mut poc) => {
+                    if !name.is_empty() { poc.name = name.to_string(); }
+                    if poc.severity.is_empty() { poc.severity = d.get_str("level").unwrap_or("unknown").to_lowercase(); }
+                    pocs.push(poc);
+                }
+                Err(e) => {
+                    tracing::debug!("poc {} ({}): {}", name, id, e);
+                    skipped += 1;
+                }
+            }
+        }
+        tracing::info!("loaded {} pocs, skipped {} the engine cannot run", pocs.len(), skipped);
+        Ok(PocList { pocs })
+    }
+
+    /// POCs listed in a template's `vullist` (PocList ids, or [`ALL_POC`]).
+    pub fn select(&self, vullist: &[String]) -> Vec<&Poc> {
+        if vullist.iter().any(|v| v == ALL_POC || v == "*") { return self.pocs.iter().collect(); }
+        self.pocs.iter().filter(|p| vullist.contains(&p.id.to_hex())).collect()
+    }
+}
+
+// ---- requests and responses ----------------------------------------------
+
+#[derive(Debug, Clone, Default)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl HttpRequest {
+    /// The request as sent on the wire, for the report.
+    pub fn raw(&self) -> String {
+        let (target, host) = match Url::parse(&self.url) {
+            Ok(u) => (u[Position::BeforePath..].to_string(), u[Position::BeforeHost..Position::AfterPort].to_string()),
+            Err(_) => (self.url.clone(), String::new()),
+        };
+        let mut out = format!("{} {} HTTP/1.1\r\nHost: {}\r\n", self.method, target, host);
+        for (k, v) in &self.headers {
+            out.push_str(&format!("{}: {}\r\n", k, v));
+        }
+        out.push_str("\r\n");
+        out.push_str(&self.body);
+        out
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HttpResponse {
+    pub status: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+    /// seconds from sending the request to reading the body
+    pub duration: f64,
+}
+
+impl HttpResponse {
+    /// Status line plus headers.
+    pub fn header_text(&self) -> String {
+        let mut out = format!("HTTP/1.1 {}\r\n", self.status);
+        for (k, v) in &self.headers {
+            out.push_str(&format!("{}: {}\r\n", k, v));
+        }
+        out
+    }
+
+    pub fn raw(&self) -> String {
+        format!("{}\r\n{}", self.header_text(), self.body)
+    }
+}
+
+/// How the engine talks HTTP; implemented by the scanner.
+#[async_trait]
+pub trait HttpSender: Sync {
+    /// Send `req`, following at most `max_redirects` redirects.
+    async fn send(&self, req: &HttpRequest, max_redirects: usize) -> Result<HttpResponse>;
+}
+
+/// A matched POC: the request that matched and what it returned.
+#[derive(Debug, Clone)]
+pub struct Finding {
+    pub matched: String,
+    pub request: String,
+    pub response: String,
+    /// values of non-internal extractors
+    pub extracted: Vec<String>,
+}
+
+type Vars = HashMap<String, Value>;
+
+/// `{{BaseURL}}`, `{{RootURL}}`, `{{Hostname}}`, ... for a target URL.
+pub fn target_vars(target: &Url) -> HashMap<String, String> {
+    let host = target.host_str().unwrap_or_default().to_string();
+    let port = target.port_or_known_default().unwrap_or_default();
+    let hostname = match target.port() {
+        Some(p) => format!("{}:{}", host, p),
+        None => host.clone(),
+    };
+    let path = target.path().to_string();
+    let file = path.rsplit('/').next().unwrap_or_default().to_string();
+    HashMap::from([
+        ("BaseURL".to_string(), target.as_str().trim_end_matches('/').to_string()),
+        ("RootURL".to_string(), target.origin().ascii_serialization()),
+        ("Hostname".to_string(), hostname),
+        ("Host".to_string(), host),
+        ("Port".to_string(), port.to_string()),
+        ("Path".to_string(), path.trim_end_matches('/').to_string()),
+        ("File".to_string(), file),
+        ("Scheme".to_string(), target.scheme().to_string()),
+    ])
+}
+
+/// Variables a response exposes to matchers, extractors and later requests.
+/// Header names are lower-cased with `-` replaced by `_`.
+fn response_vars(resp: &HttpResponse) -> Vars {
+    let mut vars = Vars::new();
+    for (k, v) in &resp.headers {
+        vars.insert(k.to_lowercase().replace('-', "_"), Value::Str(v.clone()));
+    }
+    let header = resp.header_text();
+    let content_length = vars.get("content_length").and_then(Value::as_num).unwrap_or(resp.body.len() as f64);
+    vars.insert("status_code".to_string(), Value::Num(f64::from(resp.status)));
+    vars.insert("content_length".to_string(), Value::Num(content_length));
+    vars.insert("duration".to_string(), Value::Num(resp.duration));
+    vars.insert("body".to_string(), Value::Str(resp.body.clone()));
+    for key in ["response", "raw", "all"] {
+        vars.insert(key.to_string(), Value::Str(resp.raw()));
+    }
+    for key in ["header", "all_headers"] {
+        vars.insert(key.to_string(), Value::Str(header.clone()));
+    }
+    vars
+}
+
+/// Resolve `{{name}}` and `{{expression}}` markers. `None` when a marker
+/// names an unknown variable or does not evaluate.
+fn substitute(text: &str, vars: &Vars) -> Option<String> {
+    let mut out = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(len) = rest[start + 2..].find("}}") else { break; };
+        out.push_str(&rest[..start]);
+        let inner = rest[start + 2..start + 2 + len].trim();
+        let value = match vars.get(inner) {
+            Some(v) => v.to_string(),
+            None => parse_dsl(inner).ok()?.eval(vars).ok()?.to_string(),
+        };
+        out.push_str(&value);
+        rest = &rest[start + 4 + len..];
+    }
+    out.push_str(rest);
+    Some(out)
+}
+
+impl RequestSpec {
+    fn build(&self, vars: &Vars) -> Option<HttpRequest> {
+        match self {
+            RequestSpec::Path { method, path, headers, body } => Some(HttpRequest {
+                method: method.clone(),
+                url: substitute(path, vars)?,
+                headers: headers.iter().map(|(k, v)| Some((k.clone(), substitute(v, vars)?))).collect::<Option<_>>()?,
+                body: substitute(body, vars)?,
+            }),
+            RequestSpec::Raw(raw) => {
+                let raw = substitute(raw, vars)?.replace("\r\n", "\n");
+                let raw = raw.trim_start();
+                let (head, body) = raw.split_once("\n\n").unwrap_or((raw, ""));
+                let mut lines = head.lines();
+                let mut first = lines.next()?.split_whitespace();
+                let method = first.next()?.to_uppercase();
+                let target = first.next()?;
+                let url = if target.starts_with("http://") || target.starts_with("https://") {
+                    target.to_string()
+                } else {
+                    format!("{}/{}", vars.get("RootURL")?, target.trim_start_matches('/'))
+                };
+                let headers = lines
+                    .filter_map(|l| l.split_once(':'))
+                    .map(|(k, v)| (k.trim().to_string(), v.trim().to_string()))
+                    .filter(|(k, _)| !k.eq_ignore_ascii_case("host") && !k.eq_ignore_ascii_case("content-length"))
+                    .collect();
+                Some(HttpRequest { method, url, headers, body: body.trim_end_matches('\n').to_string() })
+            }
+        }
+    }
+}
+
+// ---- dsl -----------------------------------------------------------------
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Str(String),
+    Num(f64),
+    Bool(bool),
+}
+
+impl Value {
+    fn truthy(&self) -> bool {
+        match self {
+            Value::Bool(b) => *b,
+            Value::Num(n) => *n != 0.0,
+            Value::Str(s) => !s.is_empty(),
+        }
+    }
+
+    fn as_num(&self) -> Option<f64> {
+        match self {
+            Value::Num(n) => Some(*n),
+            Value::Str(s) => s.trim().parse().ok(),
+            Value::Bool(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Str(s) => f.write_str(s),
+            Value::Num(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Value::Num(n) => write!(f, "{}", n),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CmpOp { Eq, Ne, Lt, Le, Gt, Ge }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Func {
+    Contains, ContainsAny, ContainsAll, StartsWith, EndsWith,
+    ToLower, ToUpper, Len, TrimSpace, Replace, Concat,
+    Base64, Base64Decode, UrlEncode, UrlDecode, Regex,
+}
+
+impl Func {
+    fn from_name(name: &str) -> Option<Func> {
+        Some(match name {
+            "contains" => Func::Contains,
+            "contains_any" => Func::ContainsAny,
+            "contains_all" => Func::ContainsAll,
+            "starts_with" => Func::StartsWith,
+            "ends_with" => Func::EndsWith,
+            "tolower" | "to_lower" => Func::ToLower,
+            "toupper" | "to_upper" => Func::ToUpper,
+            "len" => Func::Len,
+            "trim_space" => Func::TrimSpace,
+            "replace" => Func::Replace,
+            "concat" => Func::Concat,
+            "base64" => Func::Base64,
+            "base64_decode" => Func::Base64Decode,
+            "url_encode" => Func::UrlEncode,
+            "url_decode" => Func::UrlDecode,
+            "regex" => Func::Regex,
+            _ => return None,
+        })
+    }
+
+    /// Minimum and maximum argument count.
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Func::Contains | Func::Regex => (2, 2),
+            Func::ContainsAny | Func::ContainsAll | Func::StartsWith | Func::EndsWith => (2, usize::MAX),
+            Func::Replace => (3, 3),
+            Func::Concat => (1, usize::MAX),
+            _ => (1, 1),
+        }
+    }
+}
+
+/// A parsed DSL expression: the subset of nuclei's helper language that
+/// matchers commonly use (comparisons, `&&`/`||`/`!`, `+` and string helpers).
+#[derive(Debug, Clone)]
+pub enum Expr {
+    Lit(Value),
+    Var(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Cmp(CmpOp, Box<Expr>, Box<Expr>),
+    Add(Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+    /// `regex("literal", s)`, compiled once
+    Regex(Regex, Box<Expr>),
+}
+
+impl Expr {
+    pub fn eval(&self, vars: &HashMap<String, Value>) -> Result<Value, String> {
+        Ok(match self {
+            Expr::Lit(v) => v.clone(),
+            Expr::Var(name) => vars.get(name).cloned().ok_or_else(|| format!("unknown variable `{}`", name))?,
+            Expr::Not(e) => Value::Bool(!e.eval(vars)?.truthy()),
+            Expr::And(a, b) => Value::Bool(a.eval(vars)?.truthy() && b.eval(vars)?.truthy()),
+            Expr::Or(a, b) => Value::Bool(a.eval(vars)?.truthy() || b.eval(vars)?.truthy()),
+            Expr::Cmp(op, a, b) => Value::Bool(compare(*op, &a.eval(vars)?, &b.eval(vars)?)?),
+            Expr::Add(a, b) => match (a.eval(vars)?, b.eval(vars)?) {
+                (Value::Num(x), Value::Num(y)) => Value::Num(x + y),
+                (x, y) => Value::Str(format!("{}{}", x, y)),
+            },
+            Expr::Regex(re, e) => Value::Bool(re.is_match(&e.eval(vars)?.to_string())),
+            Expr::Call(func, args) => {
+                let args = args.iter().map(|a| a.eval(vars).map(|v| v.to_string())).collect::<Result<Vec<_>, _>>()?;
+                call(*func, &args)?
+            }
+        })
+    }
+}
+
+fn compare(op: CmpOp, a: &Value, b: &Value) -> Result<bool, String> {
+    let numeric = matches!((a, b), (Value::Num(_), _) | (_, Value::Num(_)));
+    if let (true, Some(x), Some(y)) = (numeric, a.as_num(), b.as_num()) {
+        return Ok(match op {
+            CmpOp::Eq => x == y,
+            CmpOp::Ne => x != y,
+            CmpOp::Lt => x < y,
+            CmpOp::Le => x <= y,
+            CmpOp::Gt => x > y,
+            CmpOp::Ge => x >= y,
+        });
+    }
+    match op {
+        CmpOp::Eq => Ok(a.to_string() == b.to_string()),
+        CmpOp::Ne => Ok(a.to_string() != b.to_string()),
+        _ => Err(format!("cannot order `{}` and `{}`", a, b)),
+    }
+}
+
+fn call(func: Func, args: &[String]) -> Result<Value, String> {
+    let s = &args[0];
+    Ok(match func {
+        Func::Contains => Value::Bool(s.contains(args[1].as_str())),
+        Func::ContainsAny => Value::Bool(args[1..].iter().any(|a| s.contains(a.as_str()))),
+        Func::ContainsAll => Value::Bool(args[1..].iter().all(|a| s.contains(a.as_str()))),
+        Func::StartsWith => Value::Bool(args[1..].iter().any(|a| s.starts_with(a.as_str()))),
+        Func::EndsWith => Value::Bool(args[1..].iter().any(|a| s.ends_with(a.as_str()))),
+        Func::ToLower => Value::Str(s.to_lowercase()),
+        Func::ToUpper => Value::Str(s.to_uppercase()),
+        Func::Len => Value::Num(s.len() as f64),
+        Func::TrimSpace => Value::Str(s.trim().to_string()),
+        Func::Replace => Value::Str(s.replace(args[1].as_str(), &args[2])),
+        Func::Concat => Value::Str(args.concat()),
+        Func::Base64 => Value::Str(STANDARD.encode(s)),
+        Func::Base64Decode => Value::Str(String::from_utf8_lossy(&STANDARD.decode(s).map_err(|e| e.to_string())?).into_owned()),
+        Func::UrlEncode => Value::Str(urlencoding::encode(s).into_owned()),
+        Func::UrlDecode => Value::Str(urlencoding::decode(s).map_err(|e| e.to_string())?.into_owned()),
+        Func::Regex => Value::Bool(Regex::new(s).map_err(|e| e.to_string())?.is_match(&args[1])),
+    })
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Tok {
+    Ident(String),
+    Str(String),
+    Num(f64),
+    Op(&'static str),
+    LParen,
+    RParen,
+    Comma,
+}
+
+const OPS: [&str; 10] = ["&&", "||", "==", "!=", "<=", ">=", "<", ">", "!", "+"];
+
+fn tokenize(src: &str) -> Result<Vec<Tok>, String> {
+    let chars: Vec<char> = src.chars().collect();
+    let mut toks = vec![];
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            c if c.is_whitespace() => i += 1,
+            '(' => { toks.push(Tok::LParen); i += 1; }
+            ')' => { toks.push(Tok::RParen); i += 1; }
+            ',' => { toks.push(Tok::Comma); i += 1; }
+            '"' | '\'' => {
+                // only the quote and backslash are unescaped; regex escapes pass through
+                let mut s = String::new();
+                i += 1;
+                loop {
+                    match chars.get(i) {
+                        None => return Err("unterminated string".to_string()),
+                        Some(&q) if q == c => { i += 1; break; }
+                        Some(&'\\') if matches!(chars.get(i + 1), Some(&n) if n == c || n == '\\') => { s.push(chars[i + 1]); i += 2; }
+                        Some(&ch) => { s.push(ch); i += 1; }
+                    }
+                }
+                toks.push(Tok::Str(s));
+            }
+            c if c.is_ascii_digit() => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') { i += 1; }
+                let text: String = chars[start..i].iter().collect();
+                toks.push(Tok::Num(text.parse().map_err(|_| format!("bad number `{}`", text))?));
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let start = i;
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') { i += 1; }
+                toks.push(Tok::Ident(chars[start..i].iter().collect()));
+            }
+            _ => {
+                let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+                let op = OPS.iter().find(|op| rest.starts_with(**op)).ok_or_else(|| format!("unexpected `{}`", c))?;
+                toks.push(Tok::Op(op));
+                i += op.len();
+            }
+        }
+    }
+    Ok(toks)
+}
+
+/// Parse a DSL expression; errors are human-readable messages.
+pub fn parse_dsl(src: &str) -> Result<Expr, String> {
+    let mut p = DslParser { toks: tokenize(src)?, pos: 0 };
+    let expr = p.or()?;
+    match p.toks.get(p.pos) {
+        None => Ok(expr),
+        Some(t) => Err(format!("unexpected {:?}", t)),
+    }
+}
+
+struct DslParser {
+    toks: Vec<Tok>,
+    pos: usize,
+}
+
+impl DslParser {
+    fn peek_op(&self) -> Option<&'static str> {
+        match self.toks.get(self.pos) {
+            Some(Tok::Op(op)) => Some(op),
+            _ => None,
+        }
+    }
+
+    fn next(&mut self) -> Option<Tok> {
+        let t = self.toks.get(self.pos).cloned();
+        self.pos += 1;
+        t
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.peek_op() == Some("||") {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.cmp()?;
+        while self.peek_op() == Some("&&") {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.cmp()?));
+        }
+        Ok(left)
+    }
+
+    fn cmp(&mut self) -> Result<Expr, String> {
+        let left = self.add()?;
+        let op = match self.peek_op() {
+            Some("==") => CmpOp::Eq,
+            Some("!=") => CmpOp::Ne,
+            Some("<") => CmpOp::Lt,
+            Some("<=") => CmpOp::Le,
+            Some(">") => CmpOp::Gt,
+            Some(">=") => CmpOp::Ge,
+            _ => return Ok(left),
+        };
+        self.pos += 1;
+        Ok(Expr::Cmp(op, Box::new(left), Box::new(self.add()?)))
+    }
+
+    fn add(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while self.peek_op() == Some("+") {
+            self.pos += 1;
+            left = Expr::Add(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.peek_op() == Some("!") {
+            self.pos += 1;
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Tok::Num(n)) => Ok(Expr::Lit(Value::Num(n))),
+            Some(Tok::Str(s)) => Ok(Expr::Lit(Value::Str(s))),
+            Some(Tok::LParen) => {
+                let e = self.or()?;
+                match self.next() {
+                    Some(Tok::RParen) => Ok(e),
+                    _ => Err("expected `)`".to_string()),
+                }
+            }
+            Some(Tok::Ident(name)) if name == "true" || name == "false" => Ok(Expr::Lit(Value::Bool(name == "true"))),
+            Some(Tok::Ident(name)) if self.toks.get(self.pos) == Some(&Tok::LParen) => {
+                self.pos += 1;
+                let func = Func::from_name(&name).ok_or_else(|| format!("unsupported function `{}`", name))?;
+                let mut args = vec![];
+                if self.toks.get(self.pos) == Some(&Tok::RParen) {
+                    self.pos += 1;
+                } else {
+                    loop {
+                        args.push(self.or()?);
+                        match self.next() {
+                            Some(Tok::Comma) => continue,
+                            Some(Tok::RParen) => break,
+                            _ => return Err(format!("expected `,` or `)` in `{}`", name)),
+                        }
+                    }
+                }
+                let (min, max) = func.arity();
+                if args.len() < min || args.len() > max {
+                    return Err(format!("`{}` takes {} argument(s), got {}", name, min, args.len()));
+                }
+                if let (Func::Regex, Expr::Lit(Value::Str(pattern))) = (func, &args[0]) {
+                    let re = Regex::new(pattern).map_err(|e| e.to_string())?;
+                    return Ok(Expr::Regex(re, Box::new(args.remove(1))));
+                }
+                Ok(Expr::Call(func, args))
+            }
+            Some(Tok::Ident(name)) => Ok(Expr::Var(name)),
+            Some(t) => Err(format!("unexpected {:?}", t)),
+            None => Err("unexpected end".to_string()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+
+    const GIT_CONFIG: &str = r#"
+id: git-config
+
+info:
+  name: Git Configuration - Detect
+  author: Ice3man,DhiyaneshDK
+  severity: Medium
+  tags: config,git,exposure
+
+http:
+  - method: GET
+    path:
+      - "{{BaseURL}}/.git/config"
+
+    matchers-condition: and
+    matchers:
+      - type: word
+        words:
+          - "[core]"
+      - type: dsl
+        dsl:
+          - '!contains(tolower(body), "<html")'
+          - '!contains(tolower(body), "<body")'
+        condition: and
+      - type: status
+        status:
+          - 200
+"#;
+
+    const TOMCAT_VERSION: &str = r#"
+id: tomcat-detect
+
+info:
+  name: Apache Tomcat - Detect
+  severity: info
+
+http:
+  - method: GET
+    path:
+      - "{{BaseURL}}"
+      - "{{RootURL}}/docs/"
+    stop-at-first-match: true
+
+    matchers-condition: or
+    matchers:
+      - type: word
+        part: header
+        words:
+          - "Apache-Coyote"
+      - type: regex
+        part: body
+        regex:
+          - 'Apache Tomcat/([0-9.]+)'
+
+    extractors:
+      - type: regex
+        name: version
+        group: 1
+        regex:
+          - 'Apache Tomcat/([0-9.]+)'
+"#;
+
+    const TOKEN_LOGIN: &str = r#"
+id: token-login
+
+info:
+  name: Token Reuse
+  severity: high
+
+variables:
+  user: "admin"
+
+http:
+  - raw:
+      - |
+        GET /api/token HTTP/1.1
+        Host: {{Hostname}}
+
+      - |
+        POST /api/login?user={{user}} HTTP/1.1
+        Host: {{Hostname}}
+        X-Token: {{token}}
+        Content-Length: 999
+        Content-Type: application/json
+
+        {"token":"{{token}}"}
+
+    req-condition: true
+    redirects: true
+    max-redirects: 3
+    matchers:
+      - type: dsl
+        dsl:
+          - 'status_code_1 == 200 && status_code_2 == 200 && contains(body_2, "welcome")'
+
+    extractors:
+      - type: regex
+        name: token
+        part: body
+        internal: true
+        group: 1
+        regex:
+          - '"token":"([a-z0-9]+)"'
+      - type: kval
+        kval:
+          - X-Session
+"#;
+
+    fn oid() -> ObjectId { ObjectId::new() }
+
+    fn resp(status: u16, headers: &[(&str, &str)], body: &str) -> Vars {
+        response_vars(&HttpResponse {
+            status,
+            headers: headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect(),
+            body: body.to_string(),
+            duration: 0.5,
+        })
+    }
+
+    fn matcher(yaml: &str) -> Matcher {
+        Matcher::from_raw(serde_yaml::from_str(yaml).unwrap()).unwrap()
+    }
+
+    fn extractor(yaml: &str) -> Extractor {
+        Extractor::from_raw(serde_yaml::from_str(yaml).unwrap()).unwrap().unwrap()
+    }
+
+    fn eval(src: &str, vars: &Vars) -> Value {
+        parse_dsl(src).unwrap().eval(vars).unwrap()
+    }
+
+    // ---- parsing ----
+
+    #[test]
+    fn parses_path_template() {
+        let poc = Poc::parse(oid(), GIT_CONFIG).unwrap();
+        assert_eq!(poc.template_id, "git-config");
+        assert_eq!(poc.name, "Git Configuration - Detect");
+        assert_eq!(poc.severity, "medium");
+        let block = &poc.blocks[0];
+        assert_eq!(block.condition, Condition::And);
+        assert_eq!(block.max_redirects, 0);
+        assert!(matches!(&block.requests[..], [RequestSpec::Path { method, path, .. }] if method == "GET" && path == "{{BaseURL}}/.git/config"));
+        assert!(matches!(block.matchers[0].kind, MatcherKind::Words { .. }));
+        assert!(matches!(&block.matchers[1].kind, MatcherKind::Dsl(e) if e.len() == 2));
+        assert_eq!(block.matchers[1].condition, Condition::And);
+        assert!(matches!(&block.matchers[2].kind, MatcherKind::Status(s) if s == &[200]));
+    }
+
+    #[test]
+    fn parses_raw_template() {
+        let poc = Poc::parse(oid(), TOKEN_LOGIN).unwrap();
+        assert_eq!(poc.variables, [("user".to_string(), "admin".to_string())]);
+        let block = &poc.blocks[0];
+        assert_eq!(block.requests.len(), 2);
+        assert!(block.req_condition);
+        assert_eq!(block.max_redirects, 3);
+        assert!(block.extractors[0].internal);
+        assert!(matches!(&block.extractors[1].kind, ExtractorKind::Kval(k) if k == &["x_session"]));
+    }
+
+    #[test]
+    fn builds_raw_requests() {
+        let poc = Poc::parse(oid(), TOKEN_LOGIN).unwrap();
+        let mut vars: Vars = target_vars(&Url::parse("http://10.0.0.1:8080/app/").unwrap())
+            .into_iter().map(|(k, v)| (k, Value::Str(v))).collect();
+        vars.insert("user".to_string(), Value::Str("admin".to_string()));
+        vars.insert("token".to_string(), Value::Str("abc123".to_string()));
+
+        let req = poc.blocks[0].requests[1].build(&vars).unwrap();
+        assert_eq!(req.method, "POST");
+        assert_eq!(req.url, "http://10.0.0.1:8080/api/login?user=admin");
+        // Host and Content-Length are the client's business
+        assert_eq!(req.headers, [("X-Token".to_string(), "abc123".to_string()), ("Content-Type".to_string(), "application/json".to_string())]);
+        assert_eq!(req.body, r#"{"token":"abc123"}"#);
+
+        // unknown variable: the request is not sent
+        vars.remove("token");
+        assert!(poc.blocks[0].requests[1].build(&vars).is_none());
+    }
+
+    #[test]
+    fn target_variables() {
+        let vars = target_vars(&Url::parse("https://example.com:8443/app/index.php").unwrap());
+        assert_eq!(vars["BaseURL"], "https://example.com:8443/app/index.php");
+        assert_eq!(vars["RootURL"], "https://example.com:8443");
+        assert_eq!(vars["Hostname"], "example.com:8443");
+        assert_eq!(vars["Host"], "example.com");
+        assert_eq!(vars["Port"], "8443");
+        assert_eq!(vars["Path"], "/app/index.php");
+        assert_eq!(vars["File"], "index.php");
+        assert_eq!(target_vars(&Url::parse("http://example.com/").unwrap())["Port"], "80");
+    }
+
+    #[test]
+    fn rejects_what_the_engine_cannot_run() {
+        let payloads = "id: x\nhttp:\n  - path: ['{{BaseURL}}/{{p}}']\n    payloads:\n      p: [a, b]\n";
+        assert!(matches!(Poc::parse(oid(), payloads), Err(PocError::Unsupported(s)) if s == "payloads"));
+        let dns = "id: x\ndns:\n  - name: '{{FQDN}}'\n";
+        assert!(matches!(Poc::parse(oid(), dns), Err(PocError::Unsupported(s)) if s == "`dns`"));
+        let oast = "id: x\nhttp:\n  - path: ['{{BaseURL}}/?u={{interactsh-url}}']\n";
+        assert!(matches!(Poc::parse(oid(), oast), Err(PocError::Unsupported(s)) if s == "interactsh"));
+        let xpath = "id: x\nhttp:\n  - path: ['{{BaseURL}}']\n    matchers:\n      - type: xpath\n";
+        assert!(matches!(Poc::parse(oid(), xpath), Err(PocError::Unsupported(_))));
+        let bad_dsl = "id: x\nhttp:\n  - path: ['{{BaseURL}}']\n    matchers:\n      - type: dsl\n        dsl: ['md5(body) == \"x\"']\n";
+        assert!(matches!(Poc::parse(oid(), bad_dsl), Err(PocError::Dsl { .. })));
+        assert!(matches!(Poc::parse(oid(), "id: x\ninfo:\n  name: y\n"), Err(PocError::NoRequests)));
+        // an unknown extractor type is dropped, unless later requests need it
+        let xpath_extractor = "id: x\nhttp:\n  - path: ['{{BaseURL}}']\n    extractors:\n      - type: xpath\n";
+        assert!(Poc::parse(oid(), xpath_extractor).unwrap().blocks[0].extractors.is_empty());
+        let internal = "id: x\nhttp:\n  - path: ['{{BaseURL}}']\n    extractors:\n      - type: json\n        internal: true\n";
+        assert!(Poc::parse(oid(), internal).is_err());
+    }
+
+    // ---- matchers ----
+
+    #[test]
+    fn word_matcher_conditions() {
+        let vars = resp(200, &[], "alpha beta");
+        assert!(matcher("type: word\nwords: [alpha, gamma]").matches(&vars));
+        assert!(!matcher("type: word\nwords: [alpha, gamma]\ncondition: and").matches(&vars));
+        assert!(matcher("type: word\nwords: [alpha, beta]\ncondition: and").matches(&vars));
+        assert!(!matcher("type: word\nwords: [ALPHA]").matches(&vars));
+        assert!(matcher("type: word\nwords: [ALPHA]\ncase-insensitive: true").matches(&vars));
+        // numbers listed as words
+        assert!(matcher("type: word\nwords: [200]\npart: header").matches(&vars));
+    }
+
+    #[test]
+    fn negative_matcher() {
+        let vars = resp(200, &[], "<html>login</html>");
+        assert!(!matcher("type: word\nwords: ['<html>']\nnegative: true").matches(&vars));
+        assert!(matcher("type: word\nwords: ['[core]']\nnegative: true").matches(&vars));
+        assert!(matcher("type: status\nstatus: [404]\nnegative: true").matches(&vars));
+    }
+
+    #[test]
+    fn matcher_parts() {
+        let vars = resp(200, &[("Server", "Apache-Coyote/1.1"), ("Content-Type", "text/html")], "Apache Tomcat/9.0.1");
+        let header = matcher("type: word\npart: header\nwords: [Apache-Coyote]");
+        let body = matcher("type: word\npart: body\nwords: [Apache-Coyote]");
+        assert!(header.matches(&vars));
+        assert!(!body.matches(&vars));
+        // the default part is the body
+        assert!(matcher("type: word\nwords: ['Tomcat/9']").matches(&vars));
+        assert!(!matcher("type: word\npart: header\nwords: ['Tomcat/9']").matches(&vars));
+        // `all` covers both, single headers by their variable name
+        assert!(matcher("type: word\npart: all\nwords: [Apache-Coyote, 'Tomcat/9']\ncondition: and").matches(&vars));
+        assert!(matcher("type: word\npart: content_type\nwords: [text/html]").matches(&vars));
+    }
+
+    #[test]
+    fn status_size_regex_and_dsl_matchers() {
+        let vars = resp(403, &[("Content-Length", "12")], "Forbidden!!!");
+        assert!(matcher("type: status\nstatus: [401, 403]").matches(&vars));
+        assert!(!matcher("type: status\nstatus: [200]").matches(&vars));
+        assert!(matcher("type: size\nsize: [12]").matches(&vars));
+        assert!(matcher("type: regex\nregex: ['^Forb[a-z]+', 'nothing']").matches(&vars));
+        assert!(!matcher("type: regex\nregex: ['^Forb[a-z]+', 'nothing']\ncondition: and").matches(&vars));
+        assert!(matcher("type: dsl\ndsl: ['status_code == 403 && content_length == 12']").matches(&vars));
+        // an expression over a missing variable is a miss, not an error
+        assert!(!matcher("type: dsl\ndsl: ['missing == 1']").matches(&vars));
+        assert!(matcher("type: dsl\ndsl: ['missing == 1', 'len(body) > 5']").matches(&vars));
+    }
+
+    // ---- extractors ----
+
+    #[test]
+    fn regex_extractor_groups() {
+        let vars = resp(200, &[], "v=1.2.3; v=1.2.3; v=2.0");
+        assert_eq!(extractor("type: regex\nregex: ['v=([0-9.]+)']\ngroup: 1").extract(&vars), ["1.2.3", "2.0"]);
+        assert_eq!(extractor("type: regex\nregex: ['v=2\\.[0-9]']").extract(&vars), ["v=2.0"]);
+        assert!(extractor("type: regex\nregex: ['v=([0-9.]+)']\npart: header").extract(&vars).is_empty());
+    }
+
+    #[test]
+    fn kval_and_dsl_extractors() {
+        let vars = resp(200, &[("X-Powered-By", "PHP/8.1"), ("Set-Cookie", "sid=1")], "hello");
+        assert_eq!(extractor("type: kval\nkval: [X-Powered-By, x_missing, set_cookie]").extract(&vars), ["PHP/8.1", "sid=1"]);
+        assert_eq!(extractor("type: dsl\ndsl: ['len(body)', 'toupper(body)', 'missing']").extract(&vars), ["5", "HELLO"]);
+    }
+
+    // ---- dsl ----
+
+    #[test]
+    fn dsl_precedence() {
+        let vars = Vars::new();
+        // && binds tighter than ||
+        assert_eq!(eval("true || false && false", &vars), Value::Bool(true));
+        assert_eq!(eval("(true || false) && false", &vars), Value::Bool(false));
+        assert_eq!(eval("!false && !(1 == 2)", &vars), Value::Bool(true));
+        // comparison below + : 1 + 2 == 3
+        assert_eq!(eval("1 + 2 == 3", &vars), Value::Bool(true));
+        assert_eq!(eval("'a' + 1 + 2", &vars), Value::Str("a12".to_string()));
+    }
+
+    #[test]
+    fn dsl_comparisons() {
+        let mut vars = resp(200, &[("Content-Length", "1024")], "body");
+        vars.insert("version".to_string(), Value::Str("10".to_string()));
+        assert_eq!(eval("status_code == 200", &vars), Value::Bool(true));
+        assert_eq!(eval("status_code != 200", &vars), Value::Bool(false));
+        assert_eq!(eval("content_length >= 1000 && duration < 1", &vars), Value::Bool(true));
+        // a number on either side compares numerically
+        assert_eq!(eval("version > 9", &vars), Value::Bool(true));
+        assert_eq!(eval("version == '10'", &vars), Value::Bool(true));
+        assert!(parse_dsl("body < 'x'").unwrap().eval(&vars).is_err());
+    }
+
+    #[test]
+    fn dsl_functions_and_strings() {
+        let mut vars = Vars::new();
+        vars.insert("body".to_string(), Value::Str("  Hello World  ".to_string()));
+        assert_eq!(eval("contains(tolower(body), 'hello')", &vars), Value::Bool(true));
+        assert_eq!(eval("contains_all(body, 'Hello', 'World')", &vars), Value::Bool(true));
+        assert_eq!(eval("contains_any(body, 'x', 'World')", &vars), Value::Bool(true));
+        assert_eq!(eval("starts_with(trim_space(body), 'Hello')", &vars), Value::Bool(true));
+        assert_eq!(eval("len(trim_space(body))", &vars), Value::Num(11.0));
+        assert_eq!(eval("replace(trim_space(body), 'World', 'there')", &vars), Value::Str("Hello there".to_string()));
+        assert_eq!(eval("base64_decode(base64('a:b'))", &vars), Value::Str("a:b".to_string()));
+        assert_eq!(eval("url_encode('a b&c')", &vars), Value::Str("a%20b%26c".to_string()));
+        assert_eq!(eval(r#"regex("W[a-z]+d", body)"#, &vars), Value::Bool(true));
+        assert_eq!(eval(r#"regex('\\d+', '42')"#, &vars), Value::Bool(true));
+        // escaped quotes inside strings
+        assert_eq!(eval(r#"concat("say \"hi\"", 'it\'s')"#, &vars), Value::Str(r#"say "hi"it's"#.to_string()));
+    }
+
+    #[test]
+    fn dsl_errors() {
+        assert!(parse_dsl("md5(body)").unwrap_err().contains("unsupported function"));
+        assert!(parse_dsl("contains(body)").unwrap_err().contains("takes 2"));
+        assert!(parse_dsl("'open").unwrap_err().contains("unterminated"));
+        assert!(parse_dsl("(1 == 1").is_err());
+        assert!(parse_dsl("1 == 1)").is_err());
+        assert!(parse_dsl("a ~ b").is_err());
+        assert!(parse_dsl("regex('(', body)").is_err());
+    }
+
+    #[test]
+    fn substitutes_markers() {
+        let mut vars = Vars::new();
+        vars.insert("BaseURL".to_string(), Value::Str("http://x".to_string()));
+        assert_eq!(substitute("{{BaseURL}}/a?b={{ base64('u:p') }}", &vars).as_deref(), Some("http://x/a?b=dTpw"));
+        assert_eq!(substitute("no markers {{ unclosed", &vars).as_deref(), Some("no markers {{ unclosed"));
+        assert_eq!(substitute("{{missing}}", &vars), None);
+    }
+
+    // ---- runs ----
+
+    /// Answers from a table keyed by `METHOD url`, 404 otherwise, and
+    /// records what was sent.
+    #[derive(Default)]
+    struct FakeSender {
+        routes: HashMap<String, HttpResponse>,
+        sent: Mutex<Vec<(HttpRequest, usize)>>,
+    }
+
+    impl FakeSender {
+        fn route(mut self, key: &str, status: u16, headers: &[(&str, &str)], body: &str) -> Self {
+            let headers = headers.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+            self.routes.insert(key.to_string(), HttpResponse { status, headers, body: body.to_string(), duration: 0.1 });
+            self
+        }
+    }
+
+    #[async_trait]
+    impl HttpSender for FakeSender {
+        async fn send(&self, req: &HttpRequest, max_redirects: usize) -> Result<HttpResponse> {
+            self.sent.lock().unwrap().push((req.clone(), max_redirects));
+            let key = format!("{} {}", req.method, req.url);
+            Ok(self.routes.get(&key).cloned().unwrap_or(HttpResponse { status: 404, ..Default::default() }))
+        }
+    }
+
+    fn target() -> Url { Url::parse("http://10.0.0.1:8080").unwrap() }
+
+    #[tokio::test]
+    async fn first_matching_request_wins() {
+        let poc = Poc::parse(oid(), TOMCAT_VERSION).unwrap();
+        let sender = FakeSender::default()
+            .route("GET http://10.0.0.1:8080", 200, &[("Server", "nginx")], "welcome")
+            .route("GET http://10.0.0.1:8080/docs/", 200, &[], "<h1>Apache Tomcat/9.0.83</h1>");
+        let finding = poc.run(&sender, &target(), &HashMap::new()).await.unwrap();
+        assert_eq!(finding.matched, "http://10.0.0.1:8080/docs/");
+        assert_eq!(finding.extracted, ["9.0.83"]);
+        assert!(finding.request.starts_with("GET /docs/ HTTP/1.1\r\nHost: 10.0.0.1:8080\r\n"));
+        assert_eq!(sender.sent.lock().unwrap().len(), 2);
+
+        let miss = FakeSender::default().route("GET http://10.0.0.1:8080", 200, &[], "nginx");
+        assert!(poc.run(&miss, &target(), &HashMap::new()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn internal_extractors_feed_later_requests() {
+        let poc = Poc::parse(oid(), TOKEN_LOGIN).unwrap();
+        let sender = FakeSender::default()
+            .route("GET http://10.0.0.1:8080/api/token", 200, &[], r#"{"token":"s3cr3t"}"#)
+            .route("POST http://10.0.0.1:8080/api/login?user=admin", 200, &[("X-Session", "42")], "welcome back");
+        let finding = poc.run(&sender, &target(), &HashMap::new()).await.unwrap();
+        // the internal token is not reported, the session header is
+        assert_eq!(finding.extracted, ["42"]);
+        let sent = sender.sent.lock().unwrap();
+        assert_eq!(sent[1].0.headers[0], ("X-Token".to_string(), "s3cr3t".to_string()));
+        assert_eq!(sent[1].0.body, r#"{"token":"s3cr3t"}"#);
+        assert!(sent.iter().all(|(_, redirects)| *redirects == 3));
+    }
+
+    #[tokio::test]
+    async fn req_condition_waits_for_every_response() {
+        let poc = Poc::parse(oid(), TOKEN_LOGIN).unwrap();
+        // the second response alone would match `contains(body_2, ...)`, not the first status
+        let sender = FakeSender::default()
+            .route("GET http://10.0.0.1:8080/api/token", 500, &[], r#"{"token":"s3cr3t"}"#)
+            .route("POST http://10.0.0.1:8080/api/login?user=admin", 200, &[], "welcome");
+        assert!(poc.run(&sender, &target(), &HashMap::new()).await.is_none());
+    }
+}
@@ -1,9 +1,9 @@
 use anyhow::Result;
-use redis::{aio::Connection, AsyncCommands, Client, RedisResult};
-use tokio::time::{sleep, Duration};
+use redis::{aio::MultiplexedConnection, AsyncCommands, Client, Direction, RedisResult};
+use tokio::{task::JoinHandle, time::{sleep, Duration}};
 use crate::settings::AppConfig;
 
-pub async fn connect_redis(cfg: &AppConfig) -> Result<Connection> {
+pub async fn connect_redis(cfg: &AppConfig) -> Result<MultiplexedConnection> {
     let url = format!(
         "redis://:{}@{}:{}",
         urlencoding::encode(&cfg.redis.password),
@@ -11,20 +11,34 @@ pub async fn connect_redis(cfg: &AppConfig) -> Result<Connection> {
         cfg.redis.port
     );
     let client = Client::open(url)?;
-    let conn = client.get_tokio_connection().await?;
+    let conn = client.get_multiplexed_tokio_connection().await?;
     Ok(conn)
 }
 
-pub async fn rpush_json<T: serde::Serialize>(con: &mut Connection, key: &str, value: &T) -> RedisResult<i64> {
+pub async fn rpush_json<T: serde::Serialize>(con: &mut MultiplexedConnection, key: &str, value: &T) -> RedisResult<i64> {
     let payload = serde_json::to_string(value).unwrap();
     con.rpush(key, payload).await
 }
 
-pub async fn publish_json<T: serde::Serialize>(con: &mut Connection, channel: &str, value: &T) -> RedisResult<i64> {
+pub async fn publish_json<T: serde::Serialize>(con: &mut MultiplexedConnection, channel: &str, value: &T) -> RedisResult<i64> {
     let payload = serde_json::to_string(value).unwrap();
     con.publish(channel, payload).await
 }
 
+/// Keys matching `pattern`, walked with `SCAN` so a large keyspace does not
+/// block the server the way `KEYS` does.
+pub async fn scan_keys(con: &mut MultiplexedConnection, pattern: &str) -> RedisResult<Vec<String>> {
+    let mut iter: redis::AsyncIter<String> = con.scan_match(pattern).await?;
+    let mut keys = vec![];
+    while let Some(key) = iter.next_item().await {
+        keys.push(key);
+    }
+    // SCAN may return a key more than once
+    keys.sort();
+    keys.dedup();
+    Ok(keys)
+}
+
 pub async fn keep_try_redis<F, Fut, T>(mut f: F, retries: usize, delay_ms: u64) -> Result<T>
 where
     F: FnMut() -> Fut,
@@ -41,4 +55,330 @@ where
         }
     }
     Err(anyhow::anyhow!(last.unwrap_or_else(|| redis::RedisError::from((redis::ErrorKind::IoError, "unknown")))) )
-}
\ No newline at end of file
+}
+
+/// At-least-once work queue over the Redis list `key`.
+///
+/// Producers `RPUSH` onto `key`. A consumer claims the head item with
+/// `LMOVE`/`BLMOVE` into its own `{key}:processing:{consumer}` list and
+/// holds a lease in the `{key}:leases` sorted set (scored by expiry, unix
+/// seconds) until it acks. The lease is taken before the move, so a consumer
+/// never holds an item without one. Items of a consumer that crashed, or
+/// whose lease was not renewed in time, are put back at the head of `key` by
+/// [`WorkQueue::requeue`] (see [`WorkQueue::recover`]), so another consumer
+/// picks them up first.
+#[derive(Debug, Clone)]
+pub struct WorkQueue {
+    key: String,
+}
+
+impl WorkQueue {
+    pub fn new(key: impl Into<String>) -> Self {
+        WorkQueue { key: key.into() }
+    }
+
+    pub fn key(&self) -> &str { &self.key }
+
+    fn processing(&self, consumer: &str) -> String {
+        format!("{}:processing:{}", self.key, consumer)
+    }
+
+    fn leases(&self) -> String {
+        format!("{}:leases", self.key)
+    }
+
+    /// Queues `consumer` holds items of, among the queues matching
+    /// `pattern` (such as `TaskInfo:*`).
+    pub async fn held_by(con: &mut MultiplexedConnection, pattern: &str, consumer: &str) -> RedisResult<Vec<WorkQueue>> {
+        let suffix = format!(":processing:{}", consumer);
+        let keys = scan_keys(con, &format!("{}{}", pattern, suffix)).await?;
+        Ok(keys.into_iter().filter_map(|k| k.strip_suffix(&suffix).map(WorkQueue::new)).collect())
+    }
+
+    /// Take the next item for `consumer` without waiting, leased for `visibility`.
+    pub async fn claim(&self, con: &mut MultiplexedConnection, consumer: &str, visibility: Duration) -> RedisResult<Option<String>> {
+        self.extend(con, consumer, visibility).await?;
+        let item: Option<String> = con.lmove(&self.key, self.processing(consumer), Direction::Left, Direction::Right).await?;
+        if item.is_none() { self.release_if_idle(con, consumer).await?; }
+        Ok(item)
+    }
+
+    /// [`WorkQueue::claim`], waiting up to `wait` seconds for an item.
+    pub async fn claim_blocking(&self, con: &mut MultiplexedConnection, consumer: &str, visibility: Duration, wait: f64) -> RedisResult<Option<String>> {
+        // the lease has to outlast the wait, or the item could arrive unleased
+        self.extend(con, consumer, visibility + Duration::from_secs_f64(wait.max(0.0))).await?;
+        let item: Option<String> = con.blmove(&self.key, self.processing(consumer), Direction::Left, Direction::Right, wait).await?;
+        match item {
+            Some(_) => self.extend(con, consumer, visibility).await?,
+            None => self.release_if_idle(con, consumer).await?,
+        }
+        Ok(item)
+    }
+
+    /// Drop the lease taken for a claim that found nothing, unless
+    /// `consumer` still holds earlier items.
+    async fn release_if_idle(&self, con: &mut MultiplexedConnection, consumer: &str) -> RedisResult<()> {
+        let held: i64 = con.llen(self.processing(consumer)).await?;
+        if held == 0 {
+            let _: i64 = con.zrem(self.leases(), consumer).await?;
+        }
+        Ok(())
+    }
+
+    /// Renew `consumer`'s lease for another `visibility`.
+    pub async fn extend(&self, con: &mut MultiplexedConnection, consumer: &str, visibility: Duration) -> RedisResult<()> {
+        let expiry = chrono::Utc::now().timestamp() + visibility.as_secs() as i64;
+        con.zadd(self.leases(), consumer, expiry).await
+    }
+
+    /// Mark `item` done; the lease is released once `consumer` holds nothing.
+    pub async fn ack(&self, con: &mut MultiplexedConnection, consumer: &str, item: &str) -> RedisResult<()> {
+        let _: i64 = con.lrem(self.processing(consumer), 1, item).await?;
+        let left: i64 = con.llen(self.processing(consumer)).await?;
+        if left == 0 {
+            let _: i64 = con.zrem(self.leases(), consumer).await?;
+        }
+        Ok(())
+    }
+
+    /// Items waiting in the queue, followed by the ones `consumer` holds.
+    pub async fn pending_and_held(&self, con: &mut MultiplexedConnection, consumer: &str) -> RedisResult<Vec<String>> {
+        let mut items: Vec<String> = con.lrange(&self.key, 0, -1).await?;
+        let held: Vec<String> = con.lrange(self.processing(consumer), 0, -1).await?;
+        items.extend(held);
+        Ok(items)
+    }
+
+    /// Consumers holding a lease, with its expiry (unix seconds).
+    pub async fn leases_of(&self, con: &mut MultiplexedConnection) -> RedisResult<Vec<(String, i64)>> {
+        con.zrangebyscore_withscores(self.leases(), "-inf", "+inf").await
+    }
+
+    /// Move everything `consumer` holds back to the head of the queue and
+    /// drop its lease. Returns the number of items requeued.
+    pub async fn requeue(&self, con: &mut MultiplexedConnection, consumer: &str) -> RedisResult<usize> {
+        let mut moved = 0;
+        loop {
+            // newest first onto the head, so the oldest claim ends up first in line
+            let item: Option<String> = con.lmove(self.processing(consumer), &self.key, Direction::Right, Direction::Left).await?;
+            if item.is_none() { break; }
+            moved += 1;
+        }
+        let _: i64 = con.zrem(self.leases(), consumer).await?;
+        Ok(moved)
+    }
+
+    /// Requeue the items of consumers whose lease expired before `now` (unix
+    /// seconds) or that are not in `live`, including items a consumer holds
+    /// without a lease at all (left by nodes predating leased claims).
+    /// Returns the number of items requeued.
+    pub async fn recover(&self, con: &mut MultiplexedConnection, live: &[String], now: i64) -> RedisResult<usize> {
+        let leases = self.leases_of(con).await?;
+        let prefix = format!("{}:processing:", self.key);
+        let holders = scan_keys(con, &format!("{}*", prefix)).await?;
+        let unleased: Vec<(String, i64)> = holders.iter()
+            .filter_map(|k| k.strip_prefix(&prefix))
+            .filter(|c| !leases.iter().any(|(l, _)| l == c))
+            .map(|c| (c.to_string(), i64::MAX))
+            .collect();
+        let mut requeued = 0;
+        for (consumer, expiry) in leases.into_iter().chain(unleased) {
+            if expiry >= now && live.contains(&consumer) { continue; }
+            requeued += self.requeue(con, &consumer).await?;
+        }
+        Ok(requeued)
+    }
+
+    /// Renew `consumer`'s lease every third of `visibility` on `con` until
+    /// the returned guard is dropped, for work that outlasts one lease.
+    pub fn keep_leased(&self, mut con: MultiplexedConnection, consumer: &str, visibility: Duration) -> LeaseGuard {
+        let (queue, consumer) = (self.clone(), consumer.to_string());
+        let period = (visibility / 3).max(Duration::from_secs(1));
+        LeaseGuard(tokio::spawn(async move {
+            loop {
+                sleep(period).await;
+                if let Err(e) = queue.extend(&mut con, &consumer, visibility).await {
+                    tracing::warn!("lease renewal on {} failed: {}", queue.key, e);
+                }
+            }
+        }))
+    }
+
+    /// Move every pending item to the tail of `other`. Returns the number of
+    /// items moved.
+    pub async fn drain_into(&self, con: &mut MultiplexedConnection, other: &WorkQueue) -> RedisResult<usize> {
+        let mut moved = 0;
+        loop {
+            let item: Option<String> = con.lmove(&self.key, &other.key, Direction::Left, Direction::Right).await?;
+            if item.is_none() { break; }
+            moved += 1;
+        }
+        Ok(moved)
+    }
+}
+
+/// The queue of task `task_id`'s targets reserved for `node` by a sharded
+/// distribution; nodes drain it before the task's shared `TaskInfo:{id}`.
+pub fn target_shard(task_id: &str, node: &str) -> String {
+    format!("TaskInfo:{}:node:{}", task_id, node)
+}
+
+/// Stops the lease renewal started by [`WorkQueue::keep_leased`] when dropped.
+pub struct LeaseGuard(JoinHandle<()>);
+
+impl Drop for LeaseGuard {
+    fn drop(&mut self) { self.0.abort(); }
+}
+
+#[cfg(test)]
+mod tests {
+    //! These run against the Redis server at `REDIS_URL` and pass without
+    //! checking anything when it is unset: `REDIS_URL=redis://127.0.0.1:6379 cargo test`.
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use super::*;
+
+    const VISIBILITY: Duration = Duration::from_secs(60);
+
+    /// A connection to `REDIS_URL`, or `None` when the variable is unset.
+    async fn redis() -> Option<MultiplexedConnection> {
+        let url = std::env::var("REDIS_URL").ok()?;
+        Some(Client::open(url).unwrap().get_multiplexed_tokio_connection().await.unwrap())
+    }
+
+    /// A queue under a key of its own, holding `items`.
+    async fn queue(con: &mut MultiplexedConnection, items: &[&str]) -> WorkQueue {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        let queue = WorkQueue::new(format!("test:workqueue:{}:{}", std::process::id(), nanos));
+        for item in items {
+            let _: i64 = con.rpush(queue.key(), *item).await.unwrap();
+        }
+        queue
+    }
+
+    async fn pending(con: &mut MultiplexedConnection, queue: &WorkQueue) -> Vec<String> {
+        con.lrange(queue.key(), 0, -1).await.unwrap()
+    }
+
+    async fn held(con: &mut MultiplexedConnection, queue: &WorkQueue, consumer: &str) -> Vec<String> {
+        con.lrange(queue.processing(consumer), 0, -1).await.unwrap()
+    }
+
+    async fn cleanup(con: &mut MultiplexedConnection, queue: &WorkQueue) {
+        let mut keys = scan_keys(con, &format!("{}:*", queue.key())).await.unwrap();
+        keys.push(queue.key().to_string());
+        let _: i64 = con.del(keys).await.unwrap();
+    }
+
+    fn now() -> i64 { chrono::Utc::now().timestamp() }
+
+    #[tokio::test]
+    async fn ack_releases_claim_and_lease() {
+        let Some(mut con) = redis().await else { return };
+        let q = queue(&mut con, &["a", "b"]).await;
+        assert_eq!(q.claim(&mut con, "n1", VISIBILITY).await.unwrap().as_deref(), Some("a"));
+        assert_eq!(held(&mut con, &q, "n1").await, ["a"]);
+        assert_eq!(q.leases_of(&mut con).await.unwrap().len(), 1);
+
+        q.ack(&mut con, "n1", "a").await.unwrap();
+        assert!(held(&mut con, &q, "n1").await.is_empty());
+        assert!(q.leases_of(&mut con).await.unwrap().is_empty());
+        assert_eq!(pending(&mut con, &q).await, ["b"]);
+        cleanup(&mut con, &q).await;
+    }
+
+    #[tokio::test]
+    async fn empty_claim_leaves_no_lease() {
+        let Some(mut con) = redis().await else { return };
+        let q = queue(&mut con, &[]).await;
+        assert_eq!(q.claim(&mut con, "n1", VISIBILITY).await.unwrap(), None);
+        assert_eq!(q.claim_blocking(&mut con, "n1", VISIBILITY, 0.1).await.unwrap(), None);
+        assert!(q.leases_of(&mut con).await.unwrap().is_empty());
+        cleanup(&mut con, &q).await;
+    }
+
+    #[tokio::test]
+    async fn requeue_puts_claims_back_in_order() {
+        let Some(mut con) = redis().await else { return };
+        let q = queue(&mut con, &["a", "b", "c"]).await;
+        q.claim(&mut con, "n1", VISIBILITY).await.unwrap();
+        q.claim(&mut con, "n1", VISIBILITY).await.unwrap();
+        assert_eq!(q.requeue(&mut con, "n1").await.unwrap(), 2);
+        assert_eq!(pending(&mut con, &q).await, ["a", "b", "c"]);
+        assert!(q.leases_of(&mut con).await.unwrap().is_empty());
+        cleanup(&mut con, &q).await;
+    }
+
+    #[tokio::test]
+    async fn recover_takes_back_dead_and_expired_claims() {
+        let Some(mut con) = redis().await else { return };
+        let q = queue(&mut con, &["a", "b"]).await;
+        q.claim(&mut con, "n1", VISIBILITY).await.unwrap();
+        q.claim(&mut con, "n2", VISIBILITY).await.unwrap();
+        let live = ["n1".to_string(), "n2".to_string()];
+
+        // live and leased: left alone
+        assert_eq!(q.recover(&mut con, &live, now()).await.unwrap(), 0);
+        // n2 died
+        assert_eq!(q.recover(&mut con, &live[..1], now()).await.unwrap(), 1);
+        assert_eq!(pending(&mut con, &q).await, ["b"]);
+        // n1 stopped renewing its lease
+        assert_eq!(q.recover(&mut con, &live, now() + VISIBILITY.as_secs() as i64 + 1).await.unwrap(), 1);
+        assert_eq!(pending(&mut con, &q).await, ["a", "b"]);
+        assert!(q.leases_of(&mut con).await.unwrap().is_empty());
+        cleanup(&mut con, &q).await;
+    }
+
+    #[tokio::test]
+    async fn crash_after_lease_before_move_loses_nothing() {
+        let Some(mut con) = redis().await else { return };
+        let q = queue(&mut con, &["a"]).await;
+        // the claim's first step only
+        q.extend(&mut con, "n1", VISIBILITY).await.unwrap();
+        assert_eq!(q.recover(&mut con, &[], now()).await.unwrap(), 0);
+        assert_eq!(pending(&mut con, &q).await, ["a"]);
+        assert!(q.leases_of(&mut con).await.unwrap().is_empty());
+        cleanup(&mut con, &q).await;
+    }
+
+    #[tokio::test]
+    async fn unleased_claim_of_dead_consumer_is_recovered() {
+        let Some(mut con) = redis().await else { return };
+        let q = queue(&mut con, &["a", "b"]).await;
+        // moved without a lease, as a consumer killed mid-claim by an older release left it
+        let _: Option<String> = con.lmove(q.key(), q.processing("n1"), Direction::Left, Direction::Right).await.unwrap();
+        assert!(q.leases_of(&mut con).await.unwrap().is_empty());
+
+        assert_eq!(q.recover(&mut con, &["n1".to_string()], now()).await.unwrap(), 0);
+        assert_eq!(q.recover(&mut con, &[], now()).await.unwrap(), 1);
+        assert_eq!(pending(&mut con, &q).await, ["a", "b"]);
+        assert!(held(&mut con, &q, "n1").await.is_empty());
+        cleanup(&mut con, &q).await;
+    }
+
+    #[tokio::test]
+    async fn scan_keys_walks_every_page() {
+        let Some(mut con) = redis().await else { return };
+        let q = queue(&mut con, &[]).await;
+        // more consumers than one SCAN page holds
+        for i in 0..25 {
+            let _: i64 = con.rpush(q.processing(&format!("n{}", i)), "a").await.unwrap();
+        }
+        let _: i64 = con.rpush(q.key(), "b").await.unwrap();
+        let held = WorkQueue::held_by(&mut con, q.key(), "n7").await.unwrap();
+        assert_eq!(held.iter().map(WorkQueue::key).collect::<Vec<_>>(), [q.key()]);
+        assert_eq!(scan_keys(&mut con, &format!("{}:processing:*", q.key())).await.unwrap().len(), 25);
+        cleanup(&mut con, &q).await;
+    }
+
+    #[tokio::test]
+    async fn pending_and_held_lists_both_sides() {
+        let Some(mut con) = redis().await else { return };
+        let q = queue(&mut con, &["a", "b", "c"]).await;
+        q.claim(&mut con, "n1", VISIBILITY).await.unwrap();
+        q.claim(&mut con, "n2", VISIBILITY).await.unwrap();
+        assert_eq!(q.pending_and_held(&mut con, "n1").await.unwrap(), ["c", "a"]);
+        assert_eq!(q.pending_and_held(&mut con, "n3").await.unwrap(), ["c"]);
+        cleanup(&mut con, &q).await;
+    }
+}
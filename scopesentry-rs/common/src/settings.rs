@@ -5,6 +5,9 @@ use std::{env, fs};
 #[derive(Debug, Clone, Deserialize)]
 pub struct SystemSettings {
     pub timezone: Option<String>,
+    /// HMAC key for API tokens; a random key is used when unset.
+    #[serde(default)]
+    pub secret_key: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -0,0 +1,154 @@
+use std::collections::HashMap;
+
+use anyhow::Result;
+use bson::{doc, Document};
+use mongodb::Database;
+use regex::Regex;
+
+/// A placeholder that could not be resolved against the lookup tables.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ParamError {
+    #[error("unknown dictionary {{dict.{reference}}} in {module}.{plugin}")]
+    UnknownDict { module: String, plugin: String, reference: String },
+    #[error("unknown port set {{port.{reference}}} in {module}.{plugin}")]
+    UnknownPort { module: String, plugin: String, reference: String },
+}
+
+/// Lookup tables for template placeholders, keyed case-insensitively.
+///
+/// - `dicts`: `"<category>.<name>"` -> dictionary id. Nodes fetch the content
+///   from GridFS, where the file is stored under that id as its filename.
+/// - `ports`: `"<name>"` -> port list from `PortDict`, e.g. `"80,443,8000-8100"`.
+#[derive(Debug, Clone, Default)]
+pub struct ParamTables {
+    pub dicts: HashMap<String, String>,
+    pub ports: HashMap<String, String>,
+}
+
+impl ParamTables {
+    pub async fn load(db: &Database) -> Result<Self> {
+        let mut tables = ParamTables::default();
+
+        let mut cursor = db.collection::<Document>("dictionary").find(doc!{}).await?;
+        while cursor.advance().await? {
+            let d = cursor.deserialize_current()?;
+            let (Ok(id), Ok(category), Ok(name)) = (d.get_object_id("_id"), d.get_str("category"), d.get_str("name")) else { continue; };
+            tables.dicts.insert(format!("{}.{}", category.to_lowercase(), name.to_lowercase()), id.to_hex());
+        }
+
+        let mut cursor = db.collection::<Document>("PortDict").find(doc!{}).await?;
+        while cursor.advance().await? {
+            let d = cursor.deserialize_current()?;
+            let (Ok(name), Ok(value)) = (d.get_str("name"), d.get_str("value")) else { continue; };
+            tables.ports.insert(name.to_lowercase(), value.to_string());
+        }
+        Ok(tables)
+    }
+}
+
+/// Substitute `{dict.*}` and `{port.*}` placeholders in every module/plugin
+/// argument string. Other `{...}` sequences are left untouched. All unknown
+/// references are collected so the caller can report them in one go.
+pub fn resolve_parameters(
+    params: &HashMap<String, HashMap<String, String>>,
+    tables: &ParamTables,
+) -> Result<HashMap<String, HashMap<String, String>>, Vec<ParamError>> {
+    let re = Regex::new(r"\{(dict|port)\.([^{}]+)\}").unwrap();
+    let mut errors = vec![];
+    let mut resolved = HashMap::new();
+    for (module, plugins) in params {
+        let mut out = HashMap::new();
+        for (plugin, args) in plugins {
+            let value = re.replace_all(args, |c: &regex::Captures| {
+                let reference = c[2].to_string();
+                let found = match &c[1] {
+                    "dict" => tables.dicts.get(&reference.to_lowercase()),
+                    _ => tables.ports.get(&reference.to_lowercase()),
+                };
+                match found {
+                    Some(v) => v.clone(),
+                    None => {
+                        let (module, plugin) = (module.clone(), plugin.clone());
+                        errors.push(match &c[1] {
+                            "dict" => ParamError::UnknownDict { module, plugin, reference },
+                            _ => ParamError::UnknownPort { module, plugin, reference },
+                        });
+                        c[0].to_string()
+                    }
+                }
+            });
+            out.insert(plugin.clone(), value.into_owned());
+        }
+        resolved.insert(module.clone(), out);
+    }
+    if errors.is_empty() { Ok(resolved) } else { Err(errors) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tables() -> ParamTables {
+        let mut tables = ParamTables::default();
+        tables.dicts.insert("dir.default".to_string(), "66b4ddeb983387df2b7ee7c7".to_string());
+        tables.dicts.insert("subdomain.default".to_string(), "66b4ddeb983387df2b7ee7c6".to_string());
+        tables.ports.insert("nmap top 1000".to_string(), "1,3-4,6-7,9".to_string());
+        tables
+    }
+
+    /// `module -> plugin -> args` as the template stores it.
+    fn template(entries: &[(&str, &str, &str)]) -> HashMap<String, HashMap<String, String>> {
+        let mut params: HashMap<String, HashMap<String, String>> = HashMap::new();
+        for (module, plugin, args) in entries {
+            params.entry(module.to_string()).or_default().insert(plugin.to_string(), args.to_string());
+        }
+        params
+    }
+
+    fn arg<'a>(params: &'a HashMap<String, HashMap<String, String>>, module: &str, plugin: &str) -> &'a str {
+        &params[module][plugin]
+    }
+
+    #[test]
+    fn resolves_dictionaries_and_port_sets() {
+        let params = template(&[
+            ("DirScan", "920eb73ffac6e2a8c2c4ba3f6c80e1b6", "-d {dict.dir.default} -t 10"),
+            ("SubdomainScan", "d60ba57e0b2bb7c9a6c6e9a4b4c2a1b0", "-subfile {dict.subdomain.default}"),
+            ("PortScan", "66b4ddeb983387df2b7ee7c9", "-port {port.nmap top 1000} -b 600"),
+        ]);
+        let out = resolve_parameters(&params, &tables()).unwrap();
+        assert_eq!(arg(&out, "DirScan", "920eb73ffac6e2a8c2c4ba3f6c80e1b6"), "-d 66b4ddeb983387df2b7ee7c7 -t 10");
+        assert_eq!(arg(&out, "SubdomainScan", "d60ba57e0b2bb7c9a6c6e9a4b4c2a1b0"), "-subfile 66b4ddeb983387df2b7ee7c6");
+        assert_eq!(arg(&out, "PortScan", "66b4ddeb983387df2b7ee7c9"), "-port 1,3-4,6-7,9 -b 600");
+    }
+
+    #[test]
+    fn references_are_case_insensitive() {
+        let params = template(&[("PortScan", "p", "-port {port.Nmap Top 1000}")]);
+        let out = resolve_parameters(&params, &tables()).unwrap();
+        assert_eq!(arg(&out, "PortScan", "p"), "-port 1,3-4,6-7,9");
+    }
+
+    #[test]
+    fn unknown_references_are_all_reported() {
+        let params = template(&[
+            ("DirScan", "d", "-d {dict.dir.missing}"),
+            ("PortScan", "p", "-port {port.top 5} -d {dict.dir.default}"),
+        ]);
+        let mut errors = resolve_parameters(&params, &tables()).unwrap_err();
+        errors.sort_by_key(|e| e.to_string());
+        assert_eq!(errors, [
+            ParamError::UnknownDict { module: "DirScan".to_string(), plugin: "d".to_string(), reference: "dir.missing".to_string() },
+            ParamError::UnknownPort { module: "PortScan".to_string(), plugin: "p".to_string(), reference: "top 5".to_string() },
+        ]);
+        assert_eq!(errors[0].to_string(), "unknown dictionary {dict.dir.missing} in DirScan.d");
+    }
+
+    #[test]
+    fn other_braces_are_left_alone() {
+        let literal = r#"-H "X-Token: {token}" -json {"a": {"b": 1}} -x {dict} {port.} {dict.{nested}}"#;
+        let params = template(&[("VulnerabilityScan", "v", literal)]);
+        let out = resolve_parameters(&params, &tables()).unwrap();
+        assert_eq!(arg(&out, "VulnerabilityScan", "v"), literal);
+    }
+}
@@ -19,7 +19,7 @@ pub fn expand_targets(raw: &str, ignore: &str) -> Vec<String> {
             if !regex_list.is_empty() {
                 let mut ok = true;
                 for re in &regex_list {
-                    if re.is_match(&r) == false { ok = false; break; }
+                    if !re.is_match(&r) { ok = false; break; }
                 }
                 if ok { seen.insert(r); }
             } else {
@@ -65,7 +65,8 @@ fn generate_target(target: &str) -> Vec<String> {
     vec![target.to_string()]
 }
 
-fn generate_ignore(ignore: &str) -> (std::collections::BTreeSet<String>, Vec<Regex>) {
+/// Split ignore rules into exact entries and `*` wildcard patterns; schemes are stripped.
+pub fn generate_ignore(ignore: &str) -> (std::collections::BTreeSet<String>, Vec<Regex>) {
     let mut ignore_set = std::collections::BTreeSet::new();
     let mut regexes = vec![];
     for line in ignore.lines() {
@@ -81,5 +82,3 @@ fn generate_ignore(ignore: &str) -> (std::collections::BTreeSet<String>, Vec<Reg
     }
     (ignore_set, regexes)
 }
-
-use std::str::FromStr;
\ No newline at end of file
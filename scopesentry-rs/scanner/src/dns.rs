@@ -0,0 +1,164 @@
+use std::{collections::BTreeMap, net::{IpAddr, SocketAddr}, sync::Arc, time::Duration};
+
+use tokio::{sync::Semaphore, task::JoinSet};
+use trust_dns_resolver::{
+    config::{NameServerConfigGroup, ResolverConfig, ResolverOpts},
+    error::ResolveErrorKind,
+    proto::{op::ResponseCode, rr::{RData, RecordType}},
+    TokioAsyncResolver,
+};
+
+/// CNAME chains longer than this are treated as loops.
+const MAX_CHAIN: usize = 10;
+/// Hosts resolved at once by [`resolve_all`].
+const RESOLVE_CONCURRENCY: usize = 50;
+
+/// A resolver querying `servers` (`ip` or `ip:port`), or Google's public
+/// resolvers when none are given.
+pub fn resolver(servers: &[String], timeout: Duration) -> anyhow::Result<TokioAsyncResolver> {
+    let mut opts = ResolverOpts::default();
+    opts.timeout = timeout;
+    opts.attempts = 2;
+    let config = if servers.is_empty() {
+        ResolverConfig::google()
+    } else {
+        let mut group = NameServerConfigGroup::new();
+        for s in servers {
+            let addr = match s.parse::<SocketAddr>() {
+                Ok(addr) => addr,
+                Err(_) => SocketAddr::new(s.parse::<IpAddr>()?, 53),
+            };
+            group.merge(NameServerConfigGroup::from_ips_clear(&[addr.ip()], addr.port(), true));
+        }
+        ResolverConfig::from_parts(None, vec![], group)
+    };
+    Ok(TokioAsyncResolver::tokio(config, opts)?)
+}
+
+/// Fully qualified form of `host`, so no search domain is appended.
+pub fn fqdn(host: &str) -> String {
+    format!("{}.", host.trim_end_matches('.'))
+}
+
+/// CNAME targets of `host` in resolution order, lower-cased; empty when the
+/// host has no CNAME.
+pub async fn cname_chain(resolver: &TokioAsyncResolver, host: &str) -> Vec<String> {
+    let mut chain: Vec<String> = vec![];
+    let mut name = host.trim_end_matches('.').to_lowercase();
+    while chain.len() < MAX_CHAIN {
+        // asking for the CNAME itself returns it even when its target is gone
+        let Ok(lookup) = resolver.lookup(fqdn(&name), RecordType::CNAME).await else { break; };
+        let next = lookup.record_iter().find_map(|r| match r.data() {
+            Some(RData::CNAME(target)) => Some(target.to_utf8().trim_end_matches('.').to_lowercase()),
+            _ => None,
+        });
+        match next {
+            Some(target) if target != name && !chain.contains(&target) => {
+                chain.push(target.clone());
+                name = target;
+            }
+            _ => break,
+        }
+    }
+    chain
+}
+
+/// Whether `host` does not exist (NXDOMAIN), as opposed to existing without
+/// address records.
+pub async fn is_nxdomain(resolver: &TokioAsyncResolver, host: &str) -> bool {
+    match resolver.lookup_ip(fqdn(host)).await {
+        Ok(_) => false,
+        Err(e) => matches!(e.kind(), ResolveErrorKind::NoRecordsFound { response_code: ResponseCode::NXDomain, .. }),
+    }
+}
+
+/// What a host resolves to, as stored on `subdomain` documents.
+#[derive(Debug, Clone, Default)]
+pub struct Records {
+    pub host: String,
+    /// CNAME chain in resolution order
+    pub cnames: Vec<String>,
+    /// A and AAAA addresses of the host (of the end of the chain for aliases)
+    pub ips: Vec<IpAddr>,
+    /// optional MX/TXT/NS/... records, by type
+    pub other: BTreeMap<String, Vec<String>>,
+}
+
+impl Records {
+    /// `CNAME` for aliases, else `A` (or `AAAA` for IPv6-only hosts).
+    pub fn typ(&self) -> &'static str {
+        if !self.cnames.is_empty() { return "CNAME"; }
+        if !self.ips.is_empty() && self.ips.iter().all(IpAddr::is_ipv6) { "AAAA" } else { "A" }
+    }
+
+    /// The CNAME chain for aliases, else the addresses.
+    pub fn values(&self) -> Vec<String> {
+        if self.cnames.is_empty() { self.ip_strings() } else { self.cnames.clone() }
+    }
+
+    pub fn ip_strings(&self) -> Vec<String> {
+        self.ips.iter().map(IpAddr::to_string).collect()
+    }
+}
+
+/// Record types named in a list such as `mx,txt,ns`; A, AAAA and CNAME are
+/// always resolved and unknown names are skipped.
+pub fn record_types(spec: &str) -> Vec<RecordType> {
+    spec.split(',')
+        .filter_map(|t| t.trim().to_uppercase().parse::<RecordType>().ok())
+        .filter(|t| !matches!(t, RecordType::A | RecordType::AAAA | RecordType::CNAME))
+        .collect()
+}
+
+fn rdata_text(data: &RData) -> String {
+    match data {
+        RData::TXT(txt) => txt.txt_data().iter().map(|b| String::from_utf8_lossy(b)).collect(),
+        RData::NS(name) | RData::PTR(name) => name.to_utf8().trim_end_matches('.').to_string(),
+        RData::MX(mx) => format!("{} {}", mx.preference(), mx.exchange().to_utf8().trim_end_matches('.')),
+        other => other.to_string(),
+    }
+}
+
+/// Resolve `host`'s CNAME chain, addresses and the `extra` record types.
+pub async fn resolve(resolver: &TokioAsyncResolver, host: &str, extra: &[RecordType]) -> Records {
+    let cnames = cname_chain(resolver, host).await;
+    let mut ips: Vec<IpAddr> = vec![];
+    if let Ok(lookup) = resolver.lookup_ip(fqdn(host)).await {
+        for ip in lookup.iter() {
+            if !ips.contains(&ip) { ips.push(ip); }
+        }
+    }
+    let mut other = BTreeMap::new();
+    for &rt in extra {
+        let Ok(lookup) = resolver.lookup(fqdn(host), rt).await else { continue; };
+        let values: Vec<String> = lookup.record_iter()
+            .filter(|r| r.record_type() == rt)
+            .filter_map(|r| r.data())
+            .map(rdata_text)
+            .collect();
+        if !values.is_empty() { other.insert(rt.to_string(), values); }
+    }
+    Records { host: host.to_string(), cnames, ips, other }
+}
+
+/// [`resolve`] every host, a bounded number at a time. Results keep the
+/// order of `hosts`.
+pub async fn resolve_all(resolver: &TokioAsyncResolver, hosts: &[String], extra: &[RecordType]) -> Vec<Records> {
+    let limit = Arc::new(Semaphore::new(RESOLVE_CONCURRENCY));
+    let extra: Arc<[RecordType]> = extra.into();
+    let mut set = JoinSet::new();
+    for (i, host) in hosts.iter().enumerate() {
+        let Ok(permit) = limit.clone().acquire_owned().await else { break; };
+        let (resolver, extra, host) = (resolver.clone(), extra.clone(), host.clone());
+        set.spawn(async move {
+            let _permit = permit;
+            (i, resolve(&resolver, &host, &extra).await)
+        });
+    }
+    let mut out = vec![];
+    while let Some(res) = set.join_next().await {
+        if let Ok(r) = res { out.push(r); }
+    }
+    out.sort_by_key(|(i, _)| *i);
+    out.into_iter().map(|(_, r)| r).collect()
+}
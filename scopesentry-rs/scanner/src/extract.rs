@@ -0,0 +1,217 @@
+use std::sync::OnceLock;
+
+use regex::Regex;
+use url::Url;
+
+/// Extensions of resources that are recorded but never fetched for links.
+const STATIC_EXTS: [&str; 22] = [
+    "png", "jpg", "jpeg", "gif", "bmp", "ico", "svg", "webp", "css", "woff", "woff2", "ttf", "eot",
+    "otf", "mp3", "mp4", "avi", "flv", "pdf", "zip", "rar", "exe",
+];
+
+fn re(cell: &'static OnceLock<Regex>, pattern: &str) -> &'static Regex {
+    cell.get_or_init(|| Regex::new(pattern).unwrap())
+}
+
+/// Resolve a reference found in a page; non-HTTP schemes and fragments are dropped.
+pub fn resolve(base: &Url, reference: &str) -> Option<Url> {
+    let reference = reference.trim().replace("&amp;", "&");
+    if reference.is_empty() || reference.starts_with('#') { return None; }
+    let mut url = base.join(&reference).ok()?;
+    url.set_fragment(None);
+    matches!(url.scheme(), "http" | "https").then_some(url)
+}
+
+pub fn is_static(url: &Url) -> bool {
+    let path = url.path().to_lowercase();
+    path.rsplit_once('.').map(|(_, ext)| STATIC_EXTS.contains(&ext)).unwrap_or(false)
+}
+
+pub fn is_script(url: &Url) -> bool {
+    url.path().to_lowercase().ends_with(".js")
+}
+
+/// `href`, `src` and `action` targets of an HTML page.
+pub fn links(base: &Url, html: &str) -> Vec<Url> {
+    static ATTR: OnceLock<Regex> = OnceLock::new();
+    let attr = re(&ATTR, r#"(?i)\b(?:href|src|action|data-src|data-url)\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s"'>]+))"#);
+    let mut out: Vec<Url> = vec![];
+    for c in attr.captures_iter(html) {
+        let value = c.get(1).or(c.get(2)).or(c.get(3)).map(|m| m.as_str()).unwrap_or_default();
+        if let Some(u) = resolve(base, value) {
+            if !out.contains(&u) { out.push(u); }
+        }
+    }
+    out
+}
+
+#[derive(Debug, Clone)]
+pub struct Form {
+    pub action: Url,
+    pub method: String,
+    /// field names, in page order
+    pub fields: Vec<String>,
+}
+
+impl Form {
+    /// Urlencoded body with empty values, as stored in the `crawler` collection.
+    pub fn body(&self) -> String {
+        url::form_urlencoded::Serializer::new(String::new()).extend_pairs(self.fields.iter().map(|f| (f, ""))).finish()
+    }
+}
+
+pub fn forms(base: &Url, html: &str) -> Vec<Form> {
+    static FORM: OnceLock<Regex> = OnceLock::new();
+    static ACTION: OnceLock<Regex> = OnceLock::new();
+    static METHOD: OnceLock<Regex> = OnceLock::new();
+    static FIELD: OnceLock<Regex> = OnceLock::new();
+    let form = re(&FORM, r"(?is)<form\b([^>]*)>(.*?)</form>");
+    let action = re(&ACTION, r#"(?i)\baction\s*=\s*["']?([^"'\s>]*)"#);
+    let method = re(&METHOD, r#"(?i)\bmethod\s*=\s*["']?([a-z]+)"#);
+    let field = re(&FIELD, r#"(?i)<(?:input|select|textarea)\b[^>]*\bname\s*=\s*["']?([^"'\s>]+)"#);
+
+    form.captures_iter(html).filter_map(|c| {
+        let attrs = &c[1];
+        let target = action.captures(attrs).map(|a| a[1].to_string()).unwrap_or_default();
+        let action = if target.is_empty() { base.clone() } else { resolve(base, &target)? };
+        let method = method.captures(attrs).map(|m| m[1].to_uppercase()).unwrap_or_else(|| "GET".to_string());
+        let mut fields: Vec<String> = vec![];
+        for f in field.captures_iter(&c[2]) {
+            if !fields.iter().any(|x| x == &f[1]) { fields.push(f[1].to_string()); }
+        }
+        Some(Form { action, method, fields })
+    }).collect()
+}
+
+/// Quoted URLs and paths in JavaScript, after LinkFinder: absolute URLs,
+/// `/`- or `./`-relative paths, `dir/file.ext` and `dir/route` strings.
+pub fn js_endpoints(js: &str) -> Vec<String> {
+    static ENDPOINT: OnceLock<Regex> = OnceLock::new();
+    let endpoint = re(&ENDPOINT, r#"["'`]((?:[a-zA-Z]{1,10}://|//)[^"'`/\s]+\.[a-zA-Z]{2,}[^"'`\s]*|(?:/|\.\./|\./)[^"'`><,;| *()%$^/\\\[\]][^"'`><,;|()\s]+|[a-zA-Z0-9_\-/]+/[a-zA-Z0-9_\-/.]+\.(?:[a-zA-Z]{1,4}|action)(?:[?#][^"'`\s]*)?|[a-zA-Z0-9_\-/]+/[a-zA-Z0-9_\-/]{3,}(?:[?#][^"'`\s]*)?|[a-zA-Z0-9_\-]+\.(?:php|asp|aspx|jsp|json|action|html|js|txt|xml)(?:[?#][^"'`\s]*)?)["'`]"#);
+    let mut out: Vec<String> = vec![];
+    for c in endpoint.captures_iter(js) {
+        let e = &c[1];
+        // MIME types and date formats look like paths
+        if e.starts_with("text/") || e.starts_with("application/") || e.starts_with("image/") || e.contains("/MM/") { continue; }
+        if !out.iter().any(|x| x == e) { out.push(e.to_string()); }
+    }
+    out
+}
+
+/// Contents of inline `<script>` blocks.
+pub fn inline_scripts(html: &str) -> Vec<&str> {
+    static SCRIPT: OnceLock<Regex> = OnceLock::new();
+    let script = re(&SCRIPT, r"(?is)<script\b[^>]*>(.*?)</script>");
+    script.captures_iter(html).filter_map(|c| c.get(1)).map(|m| m.as_str()).filter(|s| !s.trim().is_empty()).collect()
+}
+
+/// Hostnames mentioned anywhere in `text`, lower-cased. Callers filter them
+/// by scope; most matches are property accesses such as `window.location`.
+pub fn hostnames(text: &str) -> Vec<String> {
+    static HOST: OnceLock<Regex> = OnceLock::new();
+    let host = re(&HOST, r"(?i)\b((?:[a-z0-9](?:[a-z0-9-]{0,61}[a-z0-9])?\.)+[a-z]{2,63})\b");
+    let mut out: Vec<String> = vec![];
+    for c in host.captures_iter(text) {
+        let h = c[1].to_lowercase();
+        if !out.contains(&h) { out.push(h); }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn base() -> Url {
+        Url::parse("https://example.com/app/index.html").unwrap()
+    }
+
+    fn strings(urls: Vec<Url>) -> Vec<String> {
+        urls.into_iter().map(String::from).collect()
+    }
+
+    #[test]
+    fn links_resolve_against_the_page() {
+        let cases: [(&str, &[&str]); 7] = [
+            (r#"<a href="/login">"#, &["https://example.com/login"]),
+            (r#"<a href='next.html'>"#, &["https://example.com/app/next.html"]),
+            (r#"<img src=logo.png alt=x>"#, &["https://example.com/app/logo.png"]),
+            (r#"<div data-src="//cdn.example.com/a.js">"#, &["https://cdn.example.com/a.js"]),
+            (r#"<a HREF = "?q=1&amp;p=2#top">"#, &["https://example.com/app/index.html?q=1&p=2"]),
+            (r##"<a href="#top"><a href="mailto:a@example.com"><a href="javascript:void(0)"><a href="">"##, &[]),
+            (r#"<a href="/a"><form action="/a"><script src="/a">"#, &["https://example.com/a"]),
+        ];
+        for (html, want) in cases {
+            assert_eq!(strings(links(&base(), html)), want, "{}", html);
+        }
+    }
+
+    #[test]
+    fn forms_collect_action_method_and_fields() {
+        let html = r#"
+            <form action="/login" method="post">
+              <input type="text" name="user"><input type="password" name='pass'>
+              <select name=lang></select><textarea name="user"></textarea>
+            </form>
+            <FORM><input name="q"></FORM>
+            <form action="mailto:x@example.com"><input name="body"></form>
+        "#;
+        let found = forms(&base(), html);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[0].action.as_str(), "https://example.com/login");
+        assert_eq!(found[0].method, "POST");
+        assert_eq!(found[0].fields, ["user", "pass", "lang"]);
+        assert_eq!(found[0].body(), "user=&pass=&lang=");
+        assert_eq!(found[1].action, base());
+        assert_eq!(found[1].method, "GET");
+        assert_eq!(found[1].fields, ["q"]);
+    }
+
+    #[test]
+    fn js_endpoints_find_quoted_urls_and_paths() {
+        let cases: [(&str, &[&str]); 9] = [
+            (r#"fetch("https://api.example.com/v1/users")"#, &["https://api.example.com/v1/users"]),
+            (r#"var u = '//cdn.example.com/lib.js';"#, &["//cdn.example.com/lib.js"]),
+            (r#"axios.get(`/api/items?id=1`)"#, &["/api/items?id=1"]),
+            (r#"load("./chunks/app.js"); load("../up/x.json")"#, &["./chunks/app.js", "../up/x.json"]),
+            (r#"route = "admin/settings/users""#, &["admin/settings/users"]),
+            (r#"x = "config.json"; y = "login.action""#, &["config.json", "login.action"]),
+            (r#"h = "application/json"; t = "text/html"; d = "YYYY/MM/DD""#, &[]),
+            (r#"a = "hello world"; b = "/"; c = 'ab'"#, &[]),
+            (r#"p("/api/a"); p('/api/a')"#, &["/api/a"]),
+        ];
+        for (js, want) in cases {
+            assert_eq!(js_endpoints(js), want, "{}", js);
+        }
+    }
+
+    #[test]
+    fn hostnames_are_lowercased_and_deduplicated() {
+        let cases: [(&str, &[&str]); 4] = [
+            ("see https://API.Example.com/x and api.example.com", &["api.example.com"]),
+            ("window.location.href", &["window.location.href"]),
+            ("mail a-b.example.co.uk, not -bad.example or 10.0.0.1", &["a-b.example.co.uk", "bad.example"]),
+            ("no dots here", &[]),
+        ];
+        for (text, want) in cases {
+            assert_eq!(hostnames(text), want, "{}", text);
+        }
+    }
+
+    #[test]
+    fn scripts_and_static_files_by_extension() {
+        let cases = [
+            ("https://example.com/app.js", true, false),
+            ("https://example.com/APP.JS?v=3", true, false),
+            ("https://example.com/app.json", false, false),
+            ("https://example.com/js/app", false, false),
+            ("https://example.com/logo.PNG", false, true),
+            ("https://example.com/docs/a.pdf#p2", false, true),
+        ];
+        for (url, script, stat) in cases {
+            let url = Url::parse(url).unwrap();
+            assert_eq!(is_script(&url), script, "{}", url);
+            assert_eq!(is_static(&url), stat, "{}", url);
+        }
+    }
+}
@@ -0,0 +1,284 @@
+use std::{io::Cursor, sync::OnceLock, time::Duration};
+
+use base64::Engine;
+use regex::Regex;
+use reqwest::{header::HeaderMap, redirect::Policy, Client, StatusCode};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::scope::Scope;
+
+/// Bodies larger than this are truncated before hashing and matching.
+pub const MAX_BODY: usize = 2 * 1024 * 1024;
+const MAX_REDIRECTS: usize = 10;
+const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0 Safari/537.36";
+
+/// Client shared by the HTTP stages: redirects are followed by [`fetch`] so the
+/// chain can be recorded, certificates are not verified, and TLS peer info is
+/// kept on the response.
+pub fn client(timeout: Duration) -> anyhow::Result<Client> {
+    Ok(Client::builder()
+        .timeout(timeout)
+        .redirect(Policy::none())
+        .danger_accept_invalid_certs(true)
+        .tls_info(true)
+        .user_agent(USER_AGENT)
+        .build()?)
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct TlsCert {
+    pub subject: String,
+    pub issuer: String,
+    pub sans: Vec<String>,
+    pub not_before: String,
+    pub not_after: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct Fetched {
+    /// final URL after redirects
+    pub url: Url,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    /// every URL that answered with a redirect, in order
+    pub redirects: Vec<String>,
+    pub tls: Option<TlsCert>,
+}
+
+impl Fetched {
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    pub fn header(&self, name: &str) -> Option<String> {
+        self.headers.get(name).and_then(|v| v.to_str().ok()).map(|v| v.to_string())
+    }
+}
+
+/// GET `url`, following up to 10 redirects by hand.
+pub async fn fetch(client: &Client, url: &str) -> anyhow::Result<Fetched> {
+    fetch_following(client, url, |_| true).await
+}
+
+/// Like [`fetch`], but redirects are only followed to URLs in `scope`; a
+/// redirect leaving it is returned as the response.
+pub async fn fetch_in(client: &Client, url: &str, scope: &Scope) -> anyhow::Result<Fetched> {
+    fetch_following(client, url, |next| scope.contains(next)).await
+}
+
+async fn fetch_following(client: &Client, url: &str, follow: impl Fn(&Url) -> bool) -> anyhow::Result<Fetched> {
+    let mut url = Url::parse(url)?;
+    let mut redirects = vec![];
+    loop {
+        let resp = client.get(url.clone()).send().await?;
+        let location = resp.headers().get(reqwest::header::LOCATION).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+        if let (true, Some(loc)) = (resp.status().is_redirection(), location) {
+            if redirects.len() < MAX_REDIRECTS {
+                match url.join(&loc) {
+                    Ok(next) if follow(&next) => {
+                        redirects.push(url.to_string());
+                        url = next;
+                        continue;
+                    }
+                    Ok(next) => tracing::debug!("not following redirect from {} to {}: out of scope", url, next),
+                    Err(_) => {}
+                }
+            }
+        }
+
+        let status = resp.status();
+        let headers = resp.headers().clone();
+        let tls = resp.extensions()
+            .get::<reqwest::tls::TlsInfo>()
+            .and_then(|i| i.peer_certificate())
+            .and_then(parse_cert);
+        let body = read_body(resp, MAX_BODY).await;
+        return Ok(Fetched { url, status, headers, body, redirects, tls });
+    }
+}
+
+/// Read at most `max` bytes of the body; the rest is never downloaded.
+pub async fn read_body(mut resp: reqwest::Response, max: usize) -> Vec<u8> {
+    let mut body = vec![];
+    while let Ok(Some(chunk)) = resp.chunk().await {
+        body.extend_from_slice(&chunk);
+        if body.len() >= max {
+            body.truncate(max);
+            break;
+        }
+    }
+    body
+}
+
+fn parse_cert(der: &[u8]) -> Option<TlsCert> {
+    let (_, cert) = x509_parser::parse_x509_certificate(der).ok()?;
+    let sans = cert.subject_alternative_name().ok().flatten()
+        .map(|ext| ext.value.general_names.iter().filter_map(|n| match n {
+            x509_parser::extensions::GeneralName::DNSName(d) => Some(d.to_string()),
+            x509_parser::extensions::GeneralName::IPAddress(ip) => ip_string(ip),
+            _ => None,
+        }).collect())
+        .unwrap_or_default();
+    let time = |t: x509_parser::time::ASN1Time| {
+        chrono::DateTime::from_timestamp(t.timestamp(), 0).map(|d| d.format("%Y-%m-%d %H:%M:%S").to_string()).unwrap_or_default()
+    };
+    Some(TlsCert {
+        subject: cert.subject().to_string(),
+        issuer: cert.issuer().to_string(),
+        sans,
+        not_before: time(cert.validity().not_before),
+        not_after: time(cert.validity().not_after),
+    })
+}
+
+fn ip_string(raw: &[u8]) -> Option<String> {
+    match raw.len() {
+        4 => Some(std::net::Ipv4Addr::from(<[u8; 4]>::try_from(raw).ok()?).to_string()),
+        16 => Some(std::net::Ipv6Addr::from(<[u8; 16]>::try_from(raw).ok()?).to_string()),
+        _ => None,
+    }
+}
+
+/// Contents of `<title>`, whitespace collapsed.
+pub fn title(body: &str) -> String {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap());
+    re.captures(body)
+        .map(|c| c[1].split_whitespace().collect::<Vec<_>>().join(" "))
+        .unwrap_or_default()
+}
+
+/// Status line plus headers, as stored in `rawheaders`.
+pub fn raw_headers(status: StatusCode, headers: &HeaderMap) -> String {
+    let mut out = format!("HTTP/1.1 {}\r\n", status);
+    for (k, v) in headers {
+        out.push_str(&format!("{}: {}\r\n", k, String::from_utf8_lossy(v.as_bytes())));
+    }
+    out
+}
+
+/// Signed 32-bit murmur3, as printed by Python's `mmh3.hash`.
+pub fn mmh3(data: &[u8]) -> i32 {
+    murmur3::murmur3_32(&mut Cursor::new(data), 0).unwrap_or_default() as i32
+}
+
+/// Shodan-style favicon hash: mmh3 of the base64 encoding with a newline
+/// every 76 characters (Python's `base64.encodebytes`).
+pub fn favicon_hash(icon: &[u8]) -> i32 {
+    mmh3(encodebytes(icon).as_bytes())
+}
+
+pub fn encodebytes(data: &[u8]) -> String {
+    let b64 = base64::engine::general_purpose::STANDARD.encode(data);
+    let mut out = String::with_capacity(b64.len() + b64.len() / 76 + 1);
+    for chunk in b64.as_bytes().chunks(76) {
+        out.push_str(std::str::from_utf8(chunk).unwrap_or_default());
+        out.push('\n');
+    }
+    out
+}
+
+pub fn sha256_hex(data: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(data))
+}
+
+/// Icon declared by `<link rel="icon" href=...>`, else `/favicon.ico`.
+pub fn favicon_url(base: &Url, body: &str) -> Option<Url> {
+    static RE: OnceLock<Regex> = OnceLock::new();
+    let re = RE.get_or_init(|| Regex::new(r#"(?is)<link\b[^>]*\brel\s*=\s*["']?(?:shortcut )?icon["']?[^>]*>"#).unwrap());
+    static HREF: OnceLock<Regex> = OnceLock::new();
+    let href = HREF.get_or_init(|| Regex::new(r#"(?i)\bhref\s*=\s*["']?([^"'\s>]+)"#).unwrap());
+    re.find(body)
+        .and_then(|tag| href.captures(tag.as_str()))
+        .and_then(|c| base.join(&c[1]).ok())
+        .filter(|u| u.scheme() == "http" || u.scheme() == "https")
+        .or_else(|| base.join("/favicon.ico").ok())
+}
+
+/// Technology hints from well-known headers, cookies and the generator meta
+/// tag. The fingerprint rules add richer matches on top of these.
+pub fn tech_hints(headers: &HeaderMap, body: &str) -> Vec<String> {
+    let mut tech = vec![];
+    let mut add = |t: &str| {
+        let t = t.trim();
+        if !t.is_empty() && !tech.iter().any(|x: &String| x.eq_ignore_ascii_case(t)) { tech.push(t.to_string()); }
+    };
+    for name in ["server", "x-powered-by", "x-aspnet-version", "x-generator"] {
+        for v in headers.get_all(name) {
+            if let Ok(v) = v.to_str() { add(v); }
+        }
+    }
+    for v in headers.get_all(reqwest::header::SET_COOKIE) {
+        let cookie = v.to_str().unwrap_or_default().to_ascii_uppercase();
+        if cookie.starts_with("PHPSESSID=") { add("PHP"); }
+        if cookie.starts_with("JSESSIONID=") { add("Java"); }
+        if cookie.starts_with("ASP.NET_SESSIONID=") || cookie.starts_with("ASPSESSIONID") { add("ASP.NET"); }
+        if cookie.starts_with("LARAVEL_SESSION=") { add("Laravel"); }
+        if cookie.starts_with("CSRFTOKEN=") { add("Django"); }
+        if cookie.starts_with("RACK.SESSION=") { add("Ruby"); }
+    }
+    static META: OnceLock<Regex> = OnceLock::new();
+    let meta = META.get_or_init(|| Regex::new(r#"(?is)<meta[^>]+name\s*=\s*["']generator["'][^>]+content\s*=\s*["']([^"']+)["']"#).unwrap());
+    if let Some(c) = meta.captures(body) { add(&c[1]); }
+    tech
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+    use super::*;
+
+    /// `/a` redirects to `/b` on the same host, `/b` to `/c` on `localhost`.
+    async fn serve() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let head = String::from_utf8_lossy(&buf[..n]).into_owned();
+                let (status, location, body) = match head.split(' ').nth(1).unwrap_or_default() {
+                    "/a" => ("302 Found", "/b".to_string(), ""),
+                    "/b" => ("301 Moved Permanently", format!("http://localhost:{}/c", port), ""),
+                    "/c" => ("200 OK", String::new(), "done"),
+                    _ => ("404 Not Found", String::new(), ""),
+                };
+                let resp = format!("HTTP/1.1 {}\r\nLocation: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, location, body.len(), body);
+                stream.write_all(resp.as_bytes()).await.ok();
+            }
+        });
+        format!("http://127.0.0.1:{}", port)
+    }
+
+    #[tokio::test]
+    async fn fetch_follows_every_redirect() {
+        let base = serve().await;
+        let resp = fetch(&client(Duration::from_secs(5)).unwrap(), &format!("{}/a", base)).await.unwrap();
+        assert_eq!(resp.status, StatusCode::OK);
+        assert_eq!(resp.url.host_str(), Some("localhost"));
+        assert_eq!(resp.redirects, [format!("{}/a", base), format!("{}/b", base)]);
+        assert_eq!(resp.text(), "done");
+    }
+
+    #[tokio::test]
+    async fn fetch_in_stops_at_the_scope() {
+        let base = serve().await;
+        let scope = Scope::new(["127.0.0.1"], "").await;
+        let resp = fetch_in(&client(Duration::from_secs(5)).unwrap(), &format!("{}/a", base), &scope).await.unwrap();
+        assert_eq!(resp.status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(resp.url.as_str(), format!("{}/b", base));
+        assert_eq!(resp.redirects, [format!("{}/a", base)]);
+        assert!(resp.header("location").unwrap().starts_with("http://localhost:"));
+
+        // ignore rules take hosts out of scope as well
+        let scope = Scope::new(["127.0.0.1", "localhost"], "").await;
+        let resp = fetch_in(&client(Duration::from_secs(5)).unwrap(), &format!("{}/a", base), &scope).await.unwrap();
+        assert_eq!(resp.status, StatusCode::OK);
+        let scope = Scope::new(["127.0.0.1", "localhost"], "localhost").await;
+        let resp = fetch_in(&client(Duration::from_secs(5)).unwrap(), &format!("{}/a", base), &scope).await.unwrap();
+        assert_eq!(resp.status, StatusCode::MOVED_PERMANENTLY);
+    }
+}
@@ -2,29 +2,48 @@ use std::{sync::Arc, time::Duration};
 
 use mongodb::{bson::{doc, Document}, options::IndexOptions, IndexModel};
 use redis::AsyncCommands;
-use serde::{Deserialize, Serialize};
 use tracing_subscriber::{EnvFilter, fmt};
 use tracing_subscriber::prelude::*;
 
-use scopesentry_common::{settings::AppConfig, mongo, rds, models::DispatchTemplate, util::now_string};
+use scopesentry_common::{settings::AppConfig, mongo, rds::{self, WorkQueue}, models::{DispatchTemplate, NodeState}, util::now_string};
+
+mod dns;
+mod extract;
+mod http;
+mod modules;
+mod node;
+mod passive;
+mod pipeline;
+mod scope;
+mod store;
+mod telemetry;
+
+use node::{Backend, Node, Stop};
+use pipeline::Pipeline;
+
+/// How long a claimed template or target stays leased without renewal; the
+/// scheduler requeues it after that.
+const LEASE: Duration = Duration::from_secs(120);
+/// How long a shutdown waits for the target in progress before abandoning it
+/// to the queue.
+const SHUTDOWN_GRACE: Duration = Duration::from_secs(20);
 
 #[derive(Debug, Clone)]
 struct Ctx {
-    cfg: Arc<AppConfig>,
-    mongo: mongodb::Client,
     node_name: String,
+    node: Arc<Node>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct ProgressEntry {
-    node: String,
-    scan_start: String,
-    scan_end: String,
-    TargetHandler_start: String,
-    SubdomainScan_start: String,
-    SubdomainScan_end: String,
-    AssetMapping_start: String,
-    AssetMapping_end: String,
+impl Ctx {
+    /// The config as of the last reload.
+    fn cfg(&self) -> Arc<AppConfig> {
+        self.node.backend().cfg.clone()
+    }
+
+    fn db(&self) -> mongodb::Database {
+        let backend = self.node.backend();
+        mongo::db(&backend.mongo, &backend.cfg)
+    }
 }
 
 #[tokio::main]
@@ -34,197 +53,222 @@ async fn main() -> anyhow::Result<()> {
         .with(fmt::layer())
         .init();
 
-    let cfg = Arc::new(AppConfig::load()?);
-    let mongo = mongo::connect_mongo(&cfg).await?;
+    let backend = Backend::connect(AppConfig::load()?).await?;
     let node_name = std::env::var("NODE_NAME").ok().unwrap_or_else(|| hostname::get().unwrap_or_default().to_string_lossy().to_string());
-    let ctx = Ctx { cfg: cfg.clone(), mongo, node_name };
+    let node = Node::register(backend, &node_name).await?;
+    let ctx = Ctx { node_name, node };
 
     ensure_indexes(&ctx).await?;
 
-    let mut con = rds::connect_redis(&ctx.cfg).await?;
-
-    // initial register
-    register_node(&mut con, &ctx.node_name).await?;
+    let pipeline = modules::default_pipeline();
+    let mut con = rds::connect_redis(&ctx.cfg()).await?;
     publish_log(&mut con, &ctx.node_name, "Register Success").await.ok();
+    ctx.node.spawn_heartbeat();
+    ctx.node.spawn_control();
 
-    // spawn heartbeat task
-    {
-        let node = ctx.node_name.clone();
-        let mut con_hb = rds::connect_redis(&ctx.cfg).await?;
-        tokio::spawn(async move {
-            loop {
-                let _ : redis::RedisResult<()> = con_hb.hset(format!("node:{}", node), "state", "1").await;
-                let _ : redis::RedisResult<()> = con_hb.hset(format!("node:{}", node), "updateTime", now_string()).await;
-                tokio::time::sleep(Duration::from_secs(10)).await;
+    // hand back whatever a previous run of this node left unfinished
+    recover_claims(&mut con, &ctx.node_name).await;
+
+    // the worker returns once a restart or uninstall command let the
+    // current target finish; a signal gives it a moment to do the same
+    let mut worker = tokio::spawn(work(ctx.clone(), pipeline, con));
+    let outcome = tokio::select! {
+        res = &mut worker => res.map_err(anyhow::Error::from).and_then(|r| r),
+        _ = node::shutdown_signal() => {
+            tracing::info!("shutting down, draining {}", ctx.node_name);
+            ctx.node.request_stop(Stop::Shutdown).await;
+            if tokio::time::timeout(SHUTDOWN_GRACE, &mut worker).await.is_err() {
+                worker.abort();
             }
-        });
+            Ok(())
+        }
+    };
+
+    // hand back everything still claimed
+    let mut con = rds::connect_redis(&ctx.cfg()).await?;
+    recover_claims(&mut con, &ctx.node_name).await;
+    ctx.node.set(NodeState::Offline).await?;
+    match ctx.node.stopping() {
+        Some(Stop::Restart) => {
+            publish_log(&mut con, &ctx.node_name, "Node restarting").await.ok();
+            return Err(restart());
+        }
+        Some(Stop::Uninstall) => {
+            ctx.node.deregister().await?;
+            publish_log(&mut con, &ctx.node_name, "Node uninstalled").await.ok();
+        }
+        _ => { publish_log(&mut con, &ctx.node_name, "Node offline").await.ok(); }
     }
+    outcome
+}
 
-    // main loop: consume NodeTask and process tasks
-    loop {
-        let key = format!("NodeTask:{}", ctx.node_name);
-        let res: redis::RedisResult<(String, String)> = con.blpop(&key, 5.0).await; // (key, payload)
-        match res {
-            Ok((_k, payload)) => {
-                match serde_json::from_str::<DispatchTemplate>(&payload) {
-                    Ok(tmpl) => {
-                        if let Err(e) = handle_task(&ctx, &mut con, tmpl).await {
-                            tracing::error!("task error: {}", e);
-                        }
-                    }
-                    Err(e) => {
-                        tracing::warn!("invalid tmpl: {}", e);
-                    }
+/// Replace this process with a fresh run of the same binary; returns only if
+/// that fails.
+fn restart() -> anyhow::Error {
+    let exe = match std::env::current_exe() {
+        Ok(exe) => exe,
+        Err(e) => return e.into(),
+    };
+    tracing::info!("restarting {}", exe.display());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        std::process::Command::new(exe).args(std::env::args_os().skip(1)).exec().into()
+    }
+    #[cfg(not(unix))]
+    match std::process::Command::new(exe).args(std::env::args_os().skip(1)).spawn() {
+        Ok(_) => std::process::exit(0),
+        Err(e) => e.into(),
+    }
+}
+
+/// Main loop: consume NodeTask and process tasks until the node stops. A
+/// template is acked once its task ran out of targets; a pause keeps it
+/// claimed until the node resumes, one interrupted by a stop stays claimed
+/// and is requeued, and one whose task failed goes back to the queue.
+async fn work(ctx: Ctx, pipeline: Pipeline, mut con: redis::aio::MultiplexedConnection) -> anyhow::Result<()> {
+    let templates = WorkQueue::new(format!("NodeTask:{}", ctx.node_name));
+    let mut backend = ctx.node.backend();
+    while ctx.node.stopping().is_none() {
+        // a config reload may point at another Redis
+        let current = ctx.node.backend();
+        if !Arc::ptr_eq(&backend, &current) {
+            match rds::connect_redis(&current.cfg).await {
+                Ok(c) => { con = c; backend = current; }
+                Err(e) => {
+                    tracing::warn!("redis reconnect failed: {}", e);
+                    tokio::time::sleep(Duration::from_secs(5)).await;
+                    continue;
                 }
             }
-            Err(_timeout) => {
-                // idle
+        }
+        if ctx.node.draining() {
+            if !ctx.node.wait_resumed().await { break; }
+            continue;
+        }
+        let payload = match templates.claim_blocking(&mut con, &ctx.node_name, LEASE, 5.0).await {
+            Ok(Some(payload)) => payload,
+            Ok(None) => continue, // idle
+            Err(e) => {
+                tracing::warn!("NodeTask claim failed: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+        let lease = templates.keep_leased(rds::connect_redis(&ctx.cfg()).await?, &ctx.node_name, LEASE);
+        let outcome = match serde_json::from_str::<DispatchTemplate>(&payload) {
+            Ok(tmpl) => {
+                ctx.node.task_started();
+                let outcome = loop {
+                    ctx.node.set(NodeState::Busy).await.ok();
+                    match handle_task(&ctx, &mut con, &pipeline, tmpl.clone()).await {
+                        // paused part way: carry on with the same task once resumed
+                        Ok(false) if ctx.node.wait_resumed().await => continue,
+                        res => break res,
+                    }
+                };
+                ctx.node.task_finished();
+                outcome
+            }
+            Err(e) => {
+                // can never run, so it is dropped
+                tracing::warn!("invalid tmpl: {}", e);
+                Ok(true)
+            }
+        };
+        drop(lease);
+        match outcome {
+            Ok(true) => { templates.ack(&mut con, &ctx.node_name, &payload).await.ok(); }
+            Ok(false) => break,
+            Err(e) => {
+                // keep the task: hand it back to the queue after a pause; if
+                // that fails too, the node requeues it when it next starts
+                tracing::error!("task error: {}", e);
+                tokio::time::sleep(Duration::from_secs(5)).await;
+                if let Err(e) = templates.requeue(&mut con, &ctx.node_name).await {
+                    tracing::warn!("NodeTask requeue failed: {}", e);
+                }
             }
         }
+        // a pause or stop that came in meanwhile stays in place
+        ctx.node.set_if(NodeState::Busy, NodeState::Online).await.ok();
+    }
+    Ok(())
+}
+
+/// Requeue the templates and targets this node had claimed but not acked.
+async fn recover_claims(con: &mut redis::aio::MultiplexedConnection, node: &str) {
+    let mut queues = vec![WorkQueue::new(format!("NodeTask:{}", node))];
+    queues.extend(WorkQueue::held_by(con, "TaskInfo:*", node).await.unwrap_or_default());
+    for queue in queues {
+        match queue.requeue(con, node).await {
+            Ok(0) => {}
+            Ok(n) => tracing::info!("requeued {} unfinished items of {}", n, queue.key()),
+            Err(e) => tracing::warn!("requeue of {} failed: {}", queue.key(), e),
+        }
     }
 }
 
 async fn ensure_indexes(ctx: &Ctx) -> anyhow::Result<()> {
-    let db = scopesentry_common::mongo::db(&ctx.mongo, &ctx.cfg);
+    let db = ctx.db();
     // asset unique (host, port)
     let asset = db.collection::<Document>("asset");
     let keys = doc!{"host": 1, "port": 1};
     let opts = IndexOptions::builder().unique(true).build();
     let model = IndexModel::builder().keys(keys).options(opts).build();
     let _ = asset.create_index(model).await;
+    // subdomain unique (host); older nodes inserted duplicates, which block the unique index
+    let subdomain = db.collection::<Document>("subdomain");
+    let opts = IndexOptions::builder().unique(true).build();
+    let model = IndexModel::builder().keys(doc!{"host": 1}).options(opts).build();
+    if let Err(e) = subdomain.create_index(model).await {
+        tracing::warn!("subdomain.host is not unique, remove duplicate hosts to enforce it: {}", e);
+        let _ = subdomain.create_index(IndexModel::builder().keys(doc!{"host": 1}).build()).await;
+    }
+    // every task that found a host, for per-task duplicate checks and task deletion
+    let _ = subdomain.create_index(IndexModel::builder().keys(doc!{"tasks": 1}).build()).await;
     Ok(())
 }
 
-async fn register_node(con: &mut redis::aio::Connection, name: &str) -> redis::RedisResult<()> {
-    let key = format!("node:{}", name);
-    let _: () = con.hset(&key, "state", "1").await?;
-    let _: () = con.hset(&key, "name", name).await?;
-    let _: () = con.hset(&key, "updateTime", now_string()).await?;
-    Ok(())
-}
-
-async fn publish_log(con: &mut redis::aio::Connection, name: &str, log: &str) -> redis::RedisResult<i64> {
+async fn publish_log(con: &mut redis::aio::MultiplexedConnection, name: &str, log: &str) -> redis::RedisResult<i64> {
     let payload = serde_json::json!({"name": name, "log": log});
     con.publish("logs", payload.to_string()).await
 }
 
-async fn handle_task(ctx: &Ctx, con: &mut redis::aio::Connection, tmpl: DispatchTemplate) -> anyhow::Result<()> {
+/// Scan the task's targets until they run out (`true`) or the node pauses
+/// or stops (`false`).
+async fn handle_task(ctx: &Ctx, con: &mut redis::aio::MultiplexedConnection, pipeline: &Pipeline, tmpl: DispatchTemplate) -> anyhow::Result<bool> {
     let id = tmpl.ID.clone();
-    let mut progress = ProgressEntry{
-        node: ctx.node_name.clone(),
-        scan_start: now_string(),
-        scan_end: String::new(),
-        TargetHandler_start: now_string(),
-        SubdomainScan_start: String::new(),
-        SubdomainScan_end: String::new(),
-        AssetMapping_start: String::new(),
-        AssetMapping_end: String::new(),
-    };
 
-    // consume targets list
-    let list_key = format!("TaskInfo:{}", id);
-    loop {
-        let r: redis::RedisResult<String> = con.rpop(&list_key, None).await; // pop from tail
-        let Some(target) = r.ok() else { break; };
-        let t = target.clone();
-        // mark per-target progress hash
-        let pkey = format!("TaskInfo:progress:{}:{}", id, t);
-        let _: () = con.hset(&pkey, "node", &ctx.node_name).await?;
-        let _: () = con.hset(&pkey, "TargetHandler_start", &progress.TargetHandler_start).await?;
-
-    // Subdomain scan
-    progress.SubdomainScan_start = now_string();
-    let subs = subdomain_scan_rsubdomain(&t).await.unwrap_or_default();
-        progress.SubdomainScan_end = now_string();
-        if !subs.is_empty() { save_subdomains(ctx, &tmpl.TaskName, &subs).await.ok(); }
-        let _: () = con.hset(&pkey, "SubdomainScan_start", &progress.SubdomainScan_start).await?;
-        let _: () = con.hset(&pkey, "SubdomainScan_end", &progress.SubdomainScan_end).await?;
-
-        // Asset liveness
-        progress.AssetMapping_start = now_string();
-        if let Some(asset) = asset_probe(&t).await { save_asset(ctx, &tmpl.TaskName, &asset).await.ok(); }
-        progress.AssetMapping_end = now_string();
-        let _: () = con.hset(&pkey, "AssetMapping_start", &progress.AssetMapping_start).await?;
-        let _: () = con.hset(&pkey, "AssetMapping_end", &progress.AssetMapping_end).await?;
+    // consume targets list; a target stays claimed until it is counted, so
+    // the scheduler requeues it if this node dies on the way
+    // targets sharded to this node come before the shared ones
+    let queues = [WorkQueue::new(rds::target_shard(&id, &ctx.node_name)), WorkQueue::new(format!("TaskInfo:{}", id))];
+    while !ctx.node.draining() {
+        let mut claimed = None;
+        for queue in &queues {
+            if let Some(target) = queue.claim(con, &ctx.node_name, LEASE).await? {
+                claimed = Some((queue, target));
+                break;
+            }
+        }
+        let Some((targets, target)) = claimed else { break; };
+        let _lease = targets.keep_leased(rds::connect_redis(&ctx.cfg()).await?, &ctx.node_name, LEASE);
+        ctx.node.set_target(Some(&target));
+        if let Err(e) = pipeline.run(ctx, &tmpl, con, &target).await {
+            targets.requeue(con, &ctx.node_name).await.ok();
+            return Err(e);
+        }
 
         // add to tmp set for progress counting
-        let _: () = con.sadd(format!("TaskInfo:tmp:{}", id), &t).await?;
+        let _: () = con.sadd(format!("TaskInfo:tmp:{}", id), &target).await?;
+        targets.ack(con, &ctx.node_name, &target).await?;
+        ctx.node.set_target(None);
     }
+    if ctx.node.draining() { return Ok(false); }
 
-    progress.scan_end = now_string();
-    let _: () = con.set(format!("TaskInfo:time:{}", id), &progress.scan_end).await?;
+    let _: () = con.set(format!("TaskInfo:time:{}", id), now_string()).await?;
     publish_log(con, &ctx.node_name, &format!("Task {} completed", id)).await.ok();
 
-    Ok(())
-}
-
-async fn subdomain_scan_rsubdomain(target: &str) -> anyhow::Result<Vec<String>> {
-    // skip non-domain inputs
-    if target.contains("://") { return Ok(vec![]); }
-    if target.parse::<std::net::IpAddr>().is_ok() { return Ok(vec![]); }
-
-    // Use rsubdomain with default dictionary and resolver; skip wildcard to reduce false positives
-    let domains = vec![target.to_string()];
-    let results = rsubdomain::brute_force_subdomains(
-        domains,
-        None,   // dictionary_file
-        None,   // resolvers
-        true,   // skip_wildcard
-        None,   // bandwidth_limit
-        false,  // verify_mode
-        false,  // resolve_records
-        true,   // silent
-        None,   // device
-    ).await.map_err(|e| anyhow::anyhow!(e.to_string()))?;
-
-    let mut subs: Vec<String> = results.into_iter().map(|r| r.domain).collect();
-    subs.sort();
-    subs.dedup();
-    Ok(subs)
-}
-
-//
-
-#[derive(Debug, Clone)]
-struct AssetRec { url: String, host: String, port: i32, service: String, typ: String }
-
-async fn asset_probe(target: &str) -> Option<AssetRec> {
-    // If already URL, try request; else attempt http://target
-    let (url, host, port, svc, typ);
-    if target.contains("://") {
-        let parsed = url::Url::parse(target).ok()?;
-        host = parsed.host_str()?.to_string();
-        port = parsed.port().unwrap_or_else(|| if parsed.scheme() == "https" { 443 } else { 80 }) as i32;
-        svc = parsed.scheme().to_string();
-        typ = "http".to_string();
-        url = target.to_string();
-    } else {
-        host = target.to_string();
-        port = 80;
-        svc = "http".to_string();
-        typ = "http".to_string();
-        url = format!("http://{}", target);
-    }
-    let client = reqwest::Client::builder().timeout(Duration::from_secs(3)).build().ok()?;
-    if let Ok(resp) = client.get(&url).send().await { let _ = resp.status(); } else { return None; }
-    Some(AssetRec{ url, host, port, service: svc, typ })
+    Ok(true)
 }
-
-async fn save_subdomains(ctx: &Ctx, task_name: &str, subs: &[String]) -> anyhow::Result<()> {
-    let db = scopesentry_common::mongo::db(&ctx.mongo, &ctx.cfg);
-    let coll = db.collection::<Document>("subdomain");
-    let now = now_string();
-    let docs: Vec<Document> = subs.iter().map(|h| doc!{"host": h, "time": &now, "taskName": task_name}).collect();
-    if !docs.is_empty() { let _ = coll.insert_many(docs).await; }
-    Ok(())
-}
-
-async fn save_asset(ctx: &Ctx, task_name: &str, a: &AssetRec) -> anyhow::Result<()> {
-    let db = scopesentry_common::mongo::db(&ctx.mongo, &ctx.cfg);
-    let coll = db.collection::<Document>("asset");
-    let now = now_string();
-    let filter = doc!{"host": &a.host, "port": a.port};
-    let update = doc!{"$set": {"url": &a.url, "host": &a.host, "port": a.port, "service": &a.service, "type": &a.typ, "time": &now, "taskName": task_name}};
-    let _ = coll.update_one(filter, update).await;
-    Ok(())
-}
\ No newline at end of file
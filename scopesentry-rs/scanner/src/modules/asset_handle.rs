@@ -0,0 +1,91 @@
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, oid::ObjectId, Document};
+
+use scopesentry_common::{fingerprint::{FingerprintRule, FingerprintRules, MatchTarget}, models::DispatchTemplate};
+
+use super::RuleCache;
+use crate::{pipeline::{AssetRec, ModuleParams, PortRec, ScanModule, ScanState}, Ctx};
+
+/// Web/service fingerprinting with the `FingerprintRules` collection.
+///
+/// Web assets are matched on body, headers, title, certificate and favicon
+/// hash; other open ports on their banner. Matched product names are added to
+/// the asset's `technologies`, their categories to `categories`.
+#[derive(Default)]
+pub struct AssetHandle {
+    rules: RuleCache<FingerprintRules>,
+}
+
+#[async_trait]
+impl ScanModule for AssetHandle {
+    fn name(&self) -> &'static str { "AssetHandle" }
+
+    async fn run(&self, ctx: &Ctx, _tmpl: &DispatchTemplate, _params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let db = ctx.db();
+        let rules = self.rules.get(|| FingerprintRules::load(&db)).await?;
+        let mut hits: HashMap<ObjectId, i64> = HashMap::new();
+
+        for asset in &mut state.assets {
+            let matched = rules.matches(web_target(asset));
+            for r in &matched {
+                if !asset.technologies.iter().any(|t| t == &r.name) { asset.technologies.push(r.name.clone()); }
+            }
+            save_matches(ctx, &asset.host, asset.port, &matched, &mut hits).await.ok();
+        }
+
+        let web: Vec<(String, i32)> = state.assets.iter().map(|a| (a.host.clone(), a.port)).collect();
+        for rec in &state.ports {
+            if rec.banner.is_empty() || web.contains(&(rec.host.clone(), rec.port as i32)) { continue; }
+            let matched = rules.matches(port_target(rec));
+            save_matches(ctx, &rec.host, rec.port as i32, &matched, &mut hits).await.ok();
+        }
+
+        let coll = db.collection::<Document>("FingerprintRules");
+        for (id, n) in hits {
+            coll.update_one(doc!{"_id": id}, doc!{"$inc": {"amount": n}}).await.ok();
+        }
+        Ok(())
+    }
+}
+
+fn web_target(a: &AssetRec) -> MatchTarget {
+    MatchTarget {
+        body: a.body.clone(),
+        header: a.raw_headers.clone(),
+        title: a.title.clone(),
+        banner: a.raw_headers.clone(),
+        cert: a.tls.as_ref().map(|t| format!("{}\n{}\n{}", t.subject, t.issuer, t.sans.join("\n"))).unwrap_or_default(),
+        icon: a.favicon_mmh3.map(|h| h.to_string()).unwrap_or_default(),
+        protocol: a.service.clone(),
+        server: a.web_server.clone(),
+        port: a.port.to_string(),
+    }
+}
+
+fn port_target(p: &PortRec) -> MatchTarget {
+    MatchTarget {
+        banner: p.banner.clone(),
+        protocol: p.service.clone(),
+        port: p.port.to_string(),
+        ..Default::default()
+    }
+}
+
+async fn save_matches(ctx: &Ctx, host: &str, port: i32, matched: &[&FingerprintRule], hits: &mut HashMap<ObjectId, i64>) -> anyhow::Result<()> {
+    if matched.is_empty() { return Ok(()); }
+    for r in matched {
+        *hits.entry(r.id).or_default() += 1;
+    }
+    let names: Vec<&str> = matched.iter().map(|r| r.name.as_str()).collect();
+    let mut categories: Vec<&str> = matched.iter().flat_map(|r| [r.category.as_str(), r.parent_category.as_str()]).filter(|c| !c.is_empty()).collect();
+    categories.sort_unstable();
+    categories.dedup();
+
+    let db = ctx.db();
+    let coll = db.collection::<Document>("asset");
+    let update = doc!{"$addToSet": {"technologies": {"$each": names}, "categories": {"$each": categories}}};
+    coll.update_one(doc!{"host": host, "port": port}, update).await?;
+    Ok(())
+}
@@ -0,0 +1,186 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use base64::Engine;
+use mongodb::bson::{doc, Bson, Document};
+use tokio::{sync::Semaphore, task::JoinSet};
+
+use scopesentry_common::{models::DispatchTemplate, util::now_string};
+
+use crate::{http, pipeline::{AssetRec, ModuleParams, Page, ScanModule, ScanState}, Ctx};
+
+/// Favicons larger than this are not hashed or stored.
+const MAX_ICON: usize = 256 * 1024;
+
+/// HTTP probe of every URL and host known for the target. Records status,
+/// title, headers, body hash, favicon hash, redirect chain, certificate and
+/// technology hints. Args: `-t <n>` concurrent requests, `-timeout <s>`.
+pub struct AssetMapping;
+
+#[async_trait]
+impl ScanModule for AssetMapping {
+    fn name(&self) -> &'static str { "AssetMapping" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let mut candidates = state.urls.clone();
+        // open ports from the port scan; unidentified ports are tried as HTTP
+        for p in &state.ports {
+            let scheme = match p.service.as_str() {
+                "https" => "https",
+                "http" => "http",
+                "" | "unknown" if p.port == 443 => "https",
+                "" | "unknown" => "http",
+                _ => continue,
+            };
+            candidates.push(format!("{}://{}:{}", scheme, url_host(&p.host), p.port));
+        }
+        for host in &state.hosts {
+            let covered = candidates.iter().any(|u| url::Url::parse(u).ok().and_then(|p| bare_host(&p)).is_some_and(|h| &h == host));
+            if !covered { candidates.push(url_host(host)); }
+        }
+
+        let client = http::client(Duration::from_secs(params.flag_or("timeout", 5)))?;
+        let limit = Arc::new(Semaphore::new(params.concurrency("t", 20)));
+        let mut set = JoinSet::new();
+        for target in candidates {
+            let (limit, client) = (limit.clone(), client.clone());
+            set.spawn(async move {
+                let _permit = limit.acquire_owned().await.ok()?;
+                asset_probe(&client, &target).await
+            });
+        }
+        while let Some(res) = set.join_next().await {
+            let Ok(Some(asset)) = res else { continue; };
+            save_asset(ctx, &tmpl.TaskName, &asset).await.ok();
+            state.pages.push(Page { url: asset.url.clone(), body: asset.body.clone() });
+            state.assets.push(asset);
+        }
+        Ok(())
+    }
+}
+
+/// `host` as written in a URL: IPv6 literals go in brackets.
+fn url_host(host: &str) -> String {
+    match host.parse::<std::net::Ipv6Addr>() {
+        Ok(_) => format!("[{}]", host),
+        Err(_) => host.to_string(),
+    }
+}
+
+/// The host of `url`, without the brackets of an IPv6 literal.
+fn bare_host(url: &url::Url) -> Option<String> {
+    match url.host()? {
+        url::Host::Ipv6(addr) => Some(addr.to_string()),
+        host => Some(host.to_string()),
+    }
+}
+
+async fn asset_probe(client: &reqwest::Client, target: &str) -> Option<AssetRec> {
+    let url = if target.contains("://") { target.to_string() } else { format!("http://{}", url_host(target)) };
+    let parsed = url::Url::parse(&url).ok()?;
+    let host = bare_host(&parsed)?;
+    let port = parsed.port_or_known_default().unwrap_or(80) as i32;
+    let resp = http::fetch(client, &url).await.ok()?;
+
+    let body = resp.text();
+    let mut asset = AssetRec {
+        url,
+        host,
+        port,
+        service: parsed.scheme().to_string(),
+        typ: "http".to_string(),
+        status: resp.status.as_u16(),
+        title: http::title(&body),
+        raw_headers: http::raw_headers(resp.status, &resp.headers),
+        content_length: resp.header("content-length").and_then(|v| v.parse().ok()).unwrap_or(resp.body.len() as i64),
+        body_sha256: http::sha256_hex(&resp.body),
+        body_mmh3: http::mmh3(&resp.body),
+        redirects: resp.redirects.clone(),
+        tls: resp.tls.clone(),
+        technologies: http::tech_hints(&resp.headers, &body),
+        web_server: resp.header("server").unwrap_or_default(),
+        ..Default::default()
+    };
+
+    if let Some(icon_url) = http::favicon_url(&resp.url, &body) {
+        if let Ok(icon) = http::fetch(client, icon_url.as_str()).await {
+            if icon.status.is_success() && !icon.body.is_empty() && icon.body.len() <= MAX_ICON {
+                asset.favicon_mmh3 = Some(http::favicon_hash(&icon.body));
+                asset.favicon = base64::engine::general_purpose::STANDARD.encode(&icon.body);
+                asset.favicon_url = icon.url.to_string();
+            }
+        }
+    }
+    asset.body = body;
+    Some(asset)
+}
+
+async fn save_asset(ctx: &Ctx, task_name: &str, a: &AssetRec) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("asset");
+    let now = now_string();
+    let tls = a.tls.as_ref().map(|t| Bson::Document(doc!{
+        "subject": &t.subject,
+        "issuer": &t.issuer,
+        "sans": &t.sans,
+        "not_before": &t.not_before,
+        "not_after": &t.not_after,
+    })).unwrap_or(Bson::Null);
+    let filter = doc!{"host": &a.host, "port": a.port};
+    let update = doc!{"$set": {
+        "url": &a.url, "host": &a.host, "port": a.port, "service": &a.service, "type": &a.typ,
+        "statuscode": a.status as i32,
+        "title": &a.title,
+        "rawheaders": &a.raw_headers,
+        "contentlength": a.content_length,
+        "hashes": {"body_sha256": &a.body_sha256, "body_mmh3": a.body_mmh3.to_string()},
+        "faviconmmh3": a.favicon_mmh3.map(|h| h.to_string()).unwrap_or_default(),
+        "faviconpath": &a.favicon_url,
+        "iconcontent": &a.favicon,
+        "redirects": &a.redirects,
+        "tlsdata": tls,
+        "technologies": &a.technologies,
+        "webServer": &a.web_server,
+        "lastScanTime": &now, "taskName": task_name,
+    }, "$setOnInsert": {"time": &now}};
+    coll.update_one(filter, update).upsert(true).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+    use super::*;
+
+    #[test]
+    fn ipv6_hosts_are_bracketed() {
+        assert_eq!(url_host("example.com"), "example.com");
+        assert_eq!(url_host("10.0.0.1"), "10.0.0.1");
+        assert_eq!(url_host("2001:db8::1"), "[2001:db8::1]");
+        let url = url::Url::parse(&format!("http://{}:8080", url_host("2001:db8::1"))).unwrap();
+        assert_eq!(url.port(), Some(8080));
+        assert_eq!(bare_host(&url).as_deref(), Some("2001:db8::1"));
+        assert_eq!(bare_host(&url::Url::parse("https://example.com/").unwrap()).as_deref(), Some("example.com"));
+    }
+
+    #[tokio::test]
+    async fn probes_ipv6_literal() {
+        let Ok(listener) = TcpListener::bind("[::1]:0").await else { return; };
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((mut sock, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = sock.read(&mut buf).await;
+                let body = "<title>v6</title>";
+                let resp = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                let _ = sock.write_all(resp.as_bytes()).await;
+            }
+        });
+        let client = http::client(Duration::from_secs(5)).unwrap();
+        let asset = asset_probe(&client, &format!("[::1]:{}", port)).await.unwrap();
+        assert_eq!(asset.host, "::1");
+        assert_eq!(asset.port, port as i32);
+        assert_eq!(asset.title, "v6");
+    }
+}
@@ -0,0 +1,325 @@
+use std::{collections::{HashMap, HashSet}, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::Client;
+use tokio::{sync::Semaphore, task::JoinSet};
+use url::Url;
+
+use scopesentry_common::{models::DispatchTemplate, util::now_string};
+
+use super::DictCache;
+use crate::{http, pipeline::{ModuleParams, ScanModule, ScanState}, Ctx};
+
+/// GridFS file holding the stock wordlist, used when no `-d` is given.
+const DEFAULT_DICT: &str = "dirdict";
+const DEFAULT_STATUS: &str = "200,204,301,302,307,401,403,405";
+/// Only this much of each body is read; enough to compare against baselines.
+const MAX_BODY: usize = 256 * 1024;
+/// Directories followed per recursion level and site.
+const MAX_DIRS: usize = 20;
+/// A status/length pair seen more often than this is a catch-all page.
+const MAX_SAME_RESPONSE: usize = 10;
+
+/// Path brute force of every web asset.
+///
+/// Args: `-d <dict id>` wordlist (`{dict.dir.default}` in the template),
+/// `-e php,jsp` extensions substituted for `%EXT%`, `-s <codes>` statuses to
+/// keep, `-x <codes>` statuses to drop, `-t <n>` concurrent requests per site,
+/// `-timeout <s>`, `-depth <n>` recursion into found directories (0 = off).
+///
+/// Soft-404s are filtered by requesting random paths per directory and file
+/// extension first and dropping hits that look the same.
+#[derive(Default)]
+pub struct DirScan {
+    dicts: DictCache,
+}
+
+#[derive(Debug, Clone)]
+struct Hit {
+    url: String,
+    status: u16,
+    length: i64,
+    location: String,
+}
+
+#[derive(Debug, Clone)]
+struct Baseline {
+    status: u16,
+    length: usize,
+    location: String,
+}
+
+struct Options {
+    keep: Vec<u16>,
+    drop: Vec<u16>,
+    concurrency: usize,
+    depth: usize,
+}
+
+#[async_trait]
+impl ScanModule for DirScan {
+    fn name(&self) -> &'static str { "DirScan" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let dict = params.flag("d").unwrap_or_else(|| DEFAULT_DICT.to_string());
+        let words = self.dicts.get(ctx, &dict).await?;
+        let exts: Vec<String> = params.flag("e").unwrap_or_default()
+            .split(',')
+            .map(|e| e.trim().trim_start_matches('.').to_string())
+            .filter(|e| !e.is_empty())
+            .collect();
+        let paths = Arc::new(expand(&words, &exts));
+        let opts = Arc::new(Options {
+            keep: parse_codes(&params.flag("s").unwrap_or_else(|| DEFAULT_STATUS.to_string())),
+            drop: parse_codes(&params.flag("x").unwrap_or_default()),
+            concurrency: params.concurrency("t", 10),
+            depth: params.flag_or("depth", 0usize),
+        });
+        let client = http::client(Duration::from_secs(params.flag_or("timeout", 5)))?;
+
+        let mut sites = vec![];
+        for asset in &state.assets {
+            let Ok(url) = Url::parse(&asset.url) else { continue; };
+            let Ok(root) = url.join("/") else { continue; };
+            if !sites.contains(&root) { sites.push(root); }
+        }
+
+        for site in sites {
+            for hit in scan_site(&client, site, paths.clone(), opts.clone()).await {
+                save_hit(ctx, &tmpl.TaskName, &hit).await.ok();
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Wordlist entries as relative paths; `%EXT%` entries are expanded once per
+/// extension and dropped when no extension is configured.
+fn expand(words: &[String], exts: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    let mut out = vec![];
+    for w in words {
+        let w = w.trim_start_matches('/');
+        let items: Vec<String> = if w.contains("%EXT%") {
+            exts.iter().map(|e| w.replace("%EXT%", e)).collect()
+        } else {
+            vec![w.to_string()]
+        };
+        for item in items {
+            if !item.is_empty() && seen.insert(item.clone()) { out.push(item); }
+        }
+    }
+    out
+}
+
+fn parse_codes(spec: &str) -> Vec<u16> {
+    spec.split(',').filter_map(|c| c.trim().parse().ok()).collect()
+}
+
+/// Baseline class of a path: `/` for directories, else its lower-cased file
+/// extension ("" when it has none).
+fn class_of(path: &str) -> String {
+    if path.ends_with('/') { return "/".to_string(); }
+    let name = path.rsplit('/').next().unwrap_or(path);
+    let name = name.split(['?', '#']).next().unwrap_or(name);
+    name.rsplit_once('.').map(|(_, e)| e.to_lowercase()).unwrap_or_default()
+}
+
+async fn scan_site(client: &Client, site: Url, paths: Arc<Vec<String>>, opts: Arc<Options>) -> Vec<Hit> {
+    let classes: HashSet<String> = paths.iter().map(|p| class_of(p)).collect();
+    let mut hits = vec![];
+    let mut dirs = vec![site];
+    let mut visited = HashSet::new();
+
+    for level in 0..=opts.depth {
+        let mut next = vec![];
+        for dir in dirs {
+            if !visited.insert(dir.to_string()) { continue; }
+            let baselines = Arc::new(baselines(client, &dir, &classes).await);
+            let found = brute(client, &dir, &paths, &opts, baselines).await;
+            for hit in found {
+                if level < opts.depth && next.len() < MAX_DIRS {
+                    if let Some(sub) = as_directory(&hit) { next.push(sub); }
+                }
+                hits.push(hit);
+            }
+        }
+        dirs = next;
+    }
+
+    // drop responses repeated across many paths (catch-all pages missed by the baselines)
+    let mut counts: HashMap<(u16, i64), usize> = HashMap::new();
+    for h in &hits { *counts.entry((h.status, h.length)).or_default() += 1; }
+    hits.retain(|h| counts[&(h.status, h.length)] <= MAX_SAME_RESPONSE);
+    hits
+}
+
+/// A hit that is a directory worth recursing into: a redirect to the same path
+/// on the same site with a trailing slash, or a listed path that already ends
+/// with one.
+fn as_directory(hit: &Hit) -> Option<Url> {
+    let url = Url::parse(&hit.url).ok()?;
+    if (300..400).contains(&hit.status) {
+        let target = url.join(&hit.location).ok()?;
+        let same_dir = target.origin() == url.origin() && target.path() == format!("{}/", url.path());
+        return same_dir.then_some(target);
+    }
+    (hit.status == 200 || hit.status == 403).then_some(url).filter(|u| u.path().ends_with('/'))
+}
+
+async fn baselines(client: &Client, dir: &Url, classes: &HashSet<String>) -> HashMap<String, Baseline> {
+    let mut out = HashMap::new();
+    for class in classes {
+        let token: String = rand::thread_rng().sample_iter(&Alphanumeric).take(12).map(char::from).collect::<String>().to_lowercase();
+        let path = match class.as_str() {
+            "" => token.clone(),
+            "/" => format!("{}/", token),
+            ext => format!("{}.{}", token, ext),
+        };
+        let Ok(url) = dir.join(&path) else { continue; };
+        if let Some((status, body, location)) = request(client, url.as_str()).await {
+            out.insert(class.clone(), Baseline {
+                status,
+                length: body.replace(&token, "").len(),
+                location: location.replace(&token, ""),
+            });
+        }
+    }
+    out
+}
+
+async fn brute(client: &Client, dir: &Url, paths: &[String], opts: &Arc<Options>, baselines: Arc<HashMap<String, Baseline>>) -> Vec<Hit> {
+    let limit = Arc::new(Semaphore::new(opts.concurrency));
+    let mut set = JoinSet::new();
+    for path in paths {
+        let Ok(url) = dir.join(path) else { continue; };
+        let Ok(permit) = limit.clone().acquire_owned().await else { break; };
+        let (client, opts, baselines, path) = (client.clone(), opts.clone(), baselines.clone(), path.clone());
+        set.spawn(async move {
+            let _permit = permit;
+            let (status, body, location) = request(&client, url.as_str()).await?;
+            if !opts.keep.contains(&status) || opts.drop.contains(&status) { return None; }
+
+            let word = path.trim_end_matches('/').rsplit('/').next().unwrap_or(&path);
+            if let Some(b) = baselines.get(&class_of(&path)) {
+                if soft_404(b, status, body.replace(word, "").len(), &location.replace(word, "")) { return None; }
+            }
+            Some(Hit { url: url.to_string(), status, length: body.len() as i64, location })
+        });
+    }
+
+    let mut hits = vec![];
+    while let Some(res) = set.join_next().await {
+        if let Ok(Some(hit)) = res { hits.push(hit); }
+    }
+    hits.sort_by(|a, b| a.url.cmp(&b.url));
+    hits
+}
+
+fn soft_404(b: &Baseline, status: u16, length: usize, location: &str) -> bool {
+    if status != b.status { return false; }
+    if (300..400).contains(&status) { return location == b.location; }
+    length.abs_diff(b.length) <= (b.length / 50).max(32)
+}
+
+async fn request(client: &Client, url: &str) -> Option<(u16, String, String)> {
+    let resp = client.get(url).send().await.ok()?;
+    let status = resp.status().as_u16();
+    let location = resp.headers().get(reqwest::header::LOCATION).and_then(|v| v.to_str().ok()).unwrap_or_default().to_string();
+    let body = http::read_body(resp, MAX_BODY).await;
+    Some((status, String::from_utf8_lossy(&body).into_owned(), location))
+}
+
+/// One document per URL and task, so a later task's hit does not take over
+/// (and, on its deletion, remove) an earlier task's.
+async fn save_hit(ctx: &Ctx, task_name: &str, hit: &Hit) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("DirScanResult");
+    let update = doc!{
+        "$set": {"url": &hit.url, "status": hit.status as i32, "msg": &hit.location, "length": hit.length, "time": now_string(), "taskName": task_name},
+        "$setOnInsert": {"tags": []},
+    };
+    coll.update_one(doc!{"url": &hit.url, "taskName": task_name}, update).upsert(true).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|s| s.to_string()).collect()
+    }
+
+    #[test]
+    fn expand_substitutes_extensions() {
+        let words = strings(&["admin", "/login.%EXT%", "backup.%EXT%.bak", "admin/", "/admin", "", "/"]);
+        assert_eq!(expand(&words, &strings(&["php", "jsp"])), [
+            "admin", "login.php", "login.jsp", "backup.php.bak", "backup.jsp.bak", "admin/",
+        ]);
+        // without extensions the %EXT% entries go
+        assert_eq!(expand(&words, &[]), ["admin", "admin/"]);
+    }
+
+    #[test]
+    fn parse_codes_skips_junk() {
+        assert_eq!(parse_codes("200, 301,abc,,403 "), [200, 301, 403]);
+        assert_eq!(parse_codes(DEFAULT_STATUS), [200, 204, 301, 302, 307, 401, 403, 405]);
+        assert!(parse_codes("").is_empty());
+        assert!(parse_codes("70000").is_empty());
+    }
+
+    #[test]
+    fn class_of_groups_by_extension() {
+        for (path, class) in [
+            ("admin/", "/"),
+            ("a/b/", "/"),
+            ("login.PHP", "php"),
+            ("static/app.min.js", "js"),
+            ("index.php?x=1.2", "php"),
+            ("page#a.b", ""),
+            ("v1.2/readme", ""),
+            ("admin", ""),
+        ] {
+            assert_eq!(class_of(path), class, "{}", path);
+        }
+    }
+
+    #[test]
+    fn soft_404_compares_with_the_baseline() {
+        let page = Baseline { status: 200, length: 5000, location: String::new() };
+        // within 2% (at least 32 bytes) of the random path's page
+        assert!(soft_404(&page, 200, 5000, ""));
+        assert!(soft_404(&page, 200, 5100, ""));
+        assert!(!soft_404(&page, 200, 5101, ""));
+        assert!(!soft_404(&page, 403, 5000, ""));
+        let small = Baseline { status: 404, length: 10, location: String::new() };
+        assert!(soft_404(&small, 404, 42, ""));
+        assert!(!soft_404(&small, 404, 43, ""));
+
+        let redirect = Baseline { status: 302, length: 0, location: "/login?next=/".to_string() };
+        assert!(soft_404(&redirect, 302, 999, "/login?next=/"));
+        assert!(!soft_404(&redirect, 302, 0, "/admin/"));
+        assert!(!soft_404(&redirect, 301, 0, "/login?next=/"));
+    }
+
+    fn hit(url: &str, status: u16, location: &str) -> Hit {
+        Hit { url: url.to_string(), status, length: 0, location: location.to_string() }
+    }
+
+    #[test]
+    fn as_directory_follows_slash_redirects_and_listings() {
+        let dir = |h: Hit| as_directory(&h).map(String::from);
+        assert_eq!(dir(hit("http://a.test/admin", 301, "/admin/")).as_deref(), Some("http://a.test/admin/"));
+        assert_eq!(dir(hit("http://a.test/admin", 302, "http://a.test/admin/")).as_deref(), Some("http://a.test/admin/"));
+        assert_eq!(dir(hit("http://a.test/x/admin", 301, "admin/")).as_deref(), Some("http://a.test/x/admin/"));
+        assert_eq!(dir(hit("http://a.test/admin", 302, "/login")), None);
+        assert_eq!(dir(hit("http://a.test/admin", 302, "http://b.test/admin/")), None);
+        assert_eq!(dir(hit("http://a.test/files/", 200, "")).as_deref(), Some("http://a.test/files/"));
+        assert_eq!(dir(hit("http://a.test/files/", 403, "")).as_deref(), Some("http://a.test/files/"));
+        assert_eq!(dir(hit("http://a.test/files/", 401, "")), None);
+        assert_eq!(dir(hit("http://a.test/index.php", 200, "")), None);
+    }
+}
@@ -0,0 +1,112 @@
+use std::{collections::HashMap, future::Future, sync::{atomic::{AtomicU64, Ordering}, Arc}, time::{Duration, Instant}};
+
+use tokio::sync::Mutex;
+
+use crate::{pipeline::Pipeline, Ctx};
+
+pub mod target_handler;
+pub mod subdomain_scan;
+pub mod subdomain_security;
+pub mod port_scan_preparation;
+pub mod port_scan;
+pub mod port_fingerprint;
+pub mod asset_mapping;
+pub mod asset_handle;
+pub mod url_scan;
+pub mod web_crawler;
+pub mod url_security;
+pub mod dir_scan;
+pub mod vulnerability_scan;
+
+/// All scan modules the node knows about. The pipeline orders them by stage.
+pub fn default_pipeline() -> Pipeline {
+    Pipeline::default()
+        .register(target_handler::TargetHandler)
+        .register(subdomain_scan::SubdomainScan::default())
+        .register(subdomain_security::SubdomainSecurity)
+        .register(port_scan_preparation::PortScanPreparation)
+        .register(port_scan::PortScan)
+        .register(port_fingerprint::PortFingerprint)
+        .register(asset_mapping::AssetMapping)
+        .register(asset_handle::AssetHandle::default())
+        .register(url_scan::UrlScan)
+        .register(web_crawler::WebCrawler)
+        .register(url_security::UrlSecurity::default())
+        .register(dir_scan::DirScan::default())
+        .register(vulnerability_scan::VulnerabilityScan::default())
+}
+
+
+/// Rule sets are re-read from Mongo at most this often, so edits in the UI
+/// reach running nodes without a restart.
+const RULES_TTL: Duration = Duration::from_secs(300);
+
+/// Bumped by [`invalidate_caches`]; entries loaded under an older
+/// generation count as expired.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// Make every rule and dictionary cache reload on its next use.
+pub fn invalidate_caches() {
+    GENERATION.fetch_add(1, Ordering::Relaxed);
+}
+
+/// A loaded value, when it was loaded and under which generation.
+type Cached<T> = (Instant, u64, Arc<T>);
+
+fn fresh<T>(entry: &Cached<T>) -> bool {
+    entry.0.elapsed() < RULES_TTL && entry.1 == GENERATION.load(Ordering::Relaxed)
+}
+
+/// Rule set shared by all targets a module scans, reloaded after [`RULES_TTL`].
+pub struct RuleCache<T> {
+    inner: Mutex<Option<Cached<T>>>,
+}
+
+impl<T> Default for RuleCache<T> {
+    fn default() -> Self { RuleCache { inner: Mutex::new(None) } }
+}
+
+impl<T> RuleCache<T> {
+    pub async fn get<F, Fut>(&self, load: F) -> anyhow::Result<Arc<T>>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = anyhow::Result<T>>,
+    {
+        let mut cached = self.inner.lock().await;
+        if let Some(entry) = cached.as_ref().filter(|e| fresh(e)) {
+            return Ok(entry.2.clone());
+        }
+        let generation = GENERATION.load(Ordering::Relaxed);
+        let rules = Arc::new(load().await?);
+        *cached = Some((Instant::now(), generation, rules.clone()));
+        Ok(rules)
+    }
+}
+
+/// Dictionaries fetched from GridFS, one entry per dictionary id, as
+/// trimmed non-empty lines. Entries expire like [`RuleCache`].
+#[derive(Default)]
+pub struct DictCache {
+    inner: Mutex<HashMap<String, Cached<Vec<String>>>>,
+}
+
+impl DictCache {
+    pub async fn get(&self, ctx: &Ctx, id: &str) -> anyhow::Result<Arc<Vec<String>>> {
+        let mut cached = self.inner.lock().await;
+        if let Some(entry) = cached.get(id).filter(|e| fresh(e)) {
+            return Ok(entry.2.clone());
+        }
+        let generation = GENERATION.load(Ordering::Relaxed);
+        let db = ctx.db();
+        let raw = scopesentry_common::mongo::read_gridfs(&db, id).await?;
+        let lines: Vec<String> = String::from_utf8_lossy(&raw)
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(str::to_string)
+            .collect();
+        let lines = Arc::new(lines);
+        cached.insert(id.to_string(), (Instant::now(), generation, lines.clone()));
+        Ok(lines)
+    }
+}
\ No newline at end of file
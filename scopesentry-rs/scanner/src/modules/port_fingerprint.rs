@@ -0,0 +1,320 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, sync::Semaphore, task::JoinSet, time::timeout};
+
+use scopesentry_common::models::DispatchTemplate;
+
+use crate::{pipeline::{ModuleParams, PortRec, ScanModule, ScanState}, Ctx};
+
+const MAX_BANNER: usize = 512;
+
+/// Service identification of the open ports found by the port scan.
+///
+/// A port is first read passively for a greeting (SSH, FTP, SMTP, MySQL, ...);
+/// silent ports are then probed with a TLS ClientHello, an HTTP request and a
+/// Redis PING. Args: `-t <n>` concurrent ports, `-timeout <ms>` per read.
+pub struct PortFingerprint;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Fingerprint {
+    service: String,
+    version: String,
+    banner: String,
+}
+
+#[async_trait]
+impl ScanModule for PortFingerprint {
+    fn name(&self) -> &'static str { "PortFingerprint" }
+
+    async fn run(&self, ctx: &Ctx, _tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let limit = Arc::new(Semaphore::new(params.concurrency("t", 50)));
+        let wait = Duration::from_millis(params.flag_or("timeout", 2000u64));
+
+        let mut set = JoinSet::new();
+        for (idx, rec) in state.ports.iter().enumerate() {
+            let permit = limit.clone().acquire_owned().await?;
+            let rec = rec.clone();
+            set.spawn(async move {
+                let _permit = permit;
+                (idx, identify(&rec, wait).await)
+            });
+        }
+        while let Some(res) = set.join_next().await {
+            let Ok((idx, Some(fp))) = res else { continue; };
+            let rec = &mut state.ports[idx];
+            rec.service = fp.service.clone();
+            rec.version = fp.version.clone();
+            rec.banner = fp.banner.clone();
+            save_fingerprint(ctx, rec, &fp).await.ok();
+        }
+        Ok(())
+    }
+}
+
+async fn identify(rec: &PortRec, wait: Duration) -> Option<Fingerprint> {
+    let addr = SocketAddr::new(rec.ip.parse().ok()?, rec.port);
+
+    // passive greeting
+    let greeting = exchange(addr, None, wait).await.unwrap_or_default();
+    if !greeting.is_empty() {
+        return Some(match_greeting(&greeting));
+    }
+
+    if let Some(resp) = exchange(addr, Some(&client_hello(&rec.host)), wait).await {
+        // TLS record: handshake (0x16) or alert (0x15), major version 3
+        if resp.len() >= 3 && (resp[0] == 0x16 || resp[0] == 0x15) && resp[1] == 0x03 {
+            return Some(Fingerprint { service: "https".into(), ..Default::default() });
+        }
+    }
+
+    let http = format!("GET / HTTP/1.0\r\nHost: {}\r\nUser-Agent: Mozilla/5.0\r\n\r\n", rec.host);
+    if let Some(resp) = exchange(addr, Some(http.as_bytes()), wait).await {
+        let text = String::from_utf8_lossy(&resp);
+        if text.starts_with("HTTP/") {
+            let version = header_value(&text, "server").unwrap_or_default();
+            return Some(Fingerprint { service: "http".into(), version, banner: printable(&resp) });
+        }
+    }
+
+    if let Some(resp) = exchange(addr, Some(b"*1\r\n$4\r\nPING\r\n"), wait).await {
+        let text = String::from_utf8_lossy(&resp);
+        if text.starts_with("+PONG") || text.starts_with("-NOAUTH") || text.starts_with("-ERR") || text.starts_with("-DENIED") {
+            return Some(Fingerprint { service: "redis".into(), version: String::new(), banner: printable(&resp) });
+        }
+        if !resp.is_empty() {
+            return Some(Fingerprint { service: "unknown".into(), version: String::new(), banner: printable(&resp) });
+        }
+    }
+    None
+}
+
+/// Connect, optionally send a probe, and read whatever arrives within `wait`.
+async fn exchange(addr: SocketAddr, probe: Option<&[u8]>, wait: Duration) -> Option<Vec<u8>> {
+    let mut stream = timeout(wait, TcpStream::connect(addr)).await.ok()?.ok()?;
+    if let Some(p) = probe {
+        timeout(wait, stream.write_all(p)).await.ok()?.ok()?;
+    }
+    let mut buf = vec![0u8; 2048];
+    match timeout(wait, stream.read(&mut buf)).await {
+        Ok(Ok(n)) => { buf.truncate(n); Some(buf) }
+        _ => Some(vec![]),
+    }
+}
+
+fn match_greeting(raw: &[u8]) -> Fingerprint {
+    let text = String::from_utf8_lossy(raw);
+    let first = text.lines().next().unwrap_or("").trim().to_string();
+    let banner = printable(raw);
+    let fp = |service: &str, version: &str| Fingerprint { service: service.into(), version: version.trim().to_string(), banner: banner.clone() };
+
+    if let Some(rest) = first.strip_prefix("SSH-") {
+        // SSH-2.0-OpenSSH_8.9p1 Ubuntu-3
+        return fp("ssh", rest.split_once('-').map(|(_, v)| v).unwrap_or(rest));
+    }
+    if let Some(rest) = first.strip_prefix("220") {
+        let lower = first.to_lowercase();
+        let rest = rest.trim_start_matches(['-', ' ']);
+        if lower.contains("smtp") || lower.contains("mail") {
+            return fp("smtp", rest);
+        }
+        return fp("ftp", rest);
+    }
+    if let Some(v) = first.strip_prefix("+OK") { return fp("pop3", v); }
+    if let Some(v) = first.strip_prefix("* OK") { return fp("imap", v); }
+    if let Some(v) = first.strip_prefix("RFB ") { return fp("vnc", v); }
+    if let Some(v) = mysql_version(raw) { return fp("mysql", &v); }
+    if first.starts_with("-ERR") || first.starts_with("-NOAUTH") { return fp("redis", ""); }
+    fp("unknown", "")
+}
+
+/// MySQL initial handshake: 3-byte length, sequence id, protocol 10, then a
+/// NUL-terminated server version.
+fn mysql_version(raw: &[u8]) -> Option<String> {
+    if raw.len() < 6 || raw[4] != 0x0a { return None; }
+    let end = raw[5..].iter().position(|b| *b == 0)?;
+    let v = std::str::from_utf8(&raw[5..5 + end]).ok()?;
+    v.chars().next().filter(|c| c.is_ascii_digit()).map(|_| v.to_string())
+}
+
+fn header_value(resp: &str, name: &str) -> Option<String> {
+    resp.lines()
+        .skip(1)
+        .take_while(|l| !l.trim().is_empty())
+        .find_map(|l| l.split_once(':').filter(|(k, _)| k.trim().eq_ignore_ascii_case(name)).map(|(_, v)| v.trim().to_string()))
+}
+
+fn printable(raw: &[u8]) -> String {
+    raw.iter()
+        .take(MAX_BANNER)
+        .map(|&b| if b == b'\n' || b == b'\r' || b == b'\t' || (0x20..0x7f).contains(&b) { b as char } else { '.' })
+        .collect()
+}
+
+/// Minimal TLS 1.2 ClientHello, with SNI when `host` is a name.
+fn client_hello(host: &str) -> Vec<u8> {
+    let mut ext = vec![];
+    if host.parse::<std::net::IpAddr>().is_err() {
+        let name = host.as_bytes();
+        let n = name.len() as u16;
+        ext.extend([0x00, 0x00]);
+        ext.extend((n + 5).to_be_bytes());
+        ext.extend((n + 3).to_be_bytes());
+        ext.push(0x00);
+        ext.extend(n.to_be_bytes());
+        ext.extend(name);
+    }
+    // supported groups + signature algorithms, required by most servers
+    ext.extend([0x00, 0x0a, 0x00, 0x08, 0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18]);
+    ext.extend([0x00, 0x0d, 0x00, 0x0a, 0x00, 0x08, 0x04, 0x01, 0x05, 0x01, 0x08, 0x04, 0x04, 0x03]);
+
+    let ciphers: [u16; 8] = [0xc02f, 0xc030, 0xc02b, 0xc02c, 0xc013, 0xc014, 0x009c, 0x002f];
+    let mut body = vec![0x03, 0x03];
+    body.extend((0..32u8).map(|i| i.wrapping_mul(37)));
+    body.push(0x00); // session id
+    body.extend(((ciphers.len() * 2) as u16).to_be_bytes());
+    for c in ciphers { body.extend(c.to_be_bytes()); }
+    body.extend([0x01, 0x00]); // null compression
+    body.extend((ext.len() as u16).to_be_bytes());
+    body.extend(ext);
+
+    let mut hs = vec![0x01];
+    hs.extend(&(body.len() as u32).to_be_bytes()[1..]);
+    hs.extend(body);
+
+    let mut rec = vec![0x16, 0x03, 0x01];
+    rec.extend((hs.len() as u16).to_be_bytes());
+    rec.extend(hs);
+    rec
+}
+
+async fn save_fingerprint(ctx: &Ctx, rec: &PortRec, fp: &Fingerprint) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("asset");
+    let typ = if fp.service == "http" || fp.service == "https" { "http" } else { "other" };
+    let filter = doc!{"host": &rec.host, "port": rec.port as i32};
+    let update = doc!{"$set": {"service": &fp.service, "version": &fp.version, "banner": &fp.banner, "transport": "tcp", "type": typ}};
+    coll.update_one(filter, update).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A MySQL initial handshake packet announcing `version`, as sent by the server.
+    fn mysql_greeting(version: &str) -> Vec<u8> {
+        let mut payload = vec![0x0a];
+        payload.extend(version.as_bytes());
+        payload.push(0);
+        payload.extend([0x08, 0x00, 0x00, 0x00]); // connection id
+        payload.extend(b"abcdefgh\0");
+        payload.extend([0xff, 0xf7, 0x21, 0x02, 0x00]);
+        let mut packet = (payload.len() as u32).to_le_bytes()[..3].to_vec();
+        packet.push(0x00);
+        packet.extend(payload);
+        packet
+    }
+
+    #[test]
+    fn greetings_name_service_and_version() {
+        let cases: [(&[u8], &str, &str); 12] = [
+            (b"SSH-2.0-OpenSSH_8.9p1 Ubuntu-3ubuntu0.6\r\n", "ssh", "OpenSSH_8.9p1 Ubuntu-3ubuntu0.6"),
+            (b"SSH-1.99-Cisco-1.25\r\n", "ssh", "Cisco-1.25"),
+            (b"SSH-2.0-dropbear\r\n", "ssh", "dropbear"),
+            (b"220 (vsFTPd 3.0.3)\r\n", "ftp", "(vsFTPd 3.0.3)"),
+            (b"220-FileZilla Server 0.9.60 beta\r\n220-written by Tim Kosse\r\n220 Please visit https://filezilla-project.org/\r\n", "ftp", "FileZilla Server 0.9.60 beta"),
+            (b"220 mail.example.com ESMTP Postfix (Ubuntu)\r\n", "smtp", "mail.example.com ESMTP Postfix (Ubuntu)"),
+            (b"220 mx.example.com Microsoft ESMTP MAIL Service ready\r\n", "smtp", "mx.example.com Microsoft ESMTP MAIL Service ready"),
+            (b"+OK Dovecot (Ubuntu) ready.\r\n", "pop3", "Dovecot (Ubuntu) ready."),
+            (b"* OK [CAPABILITY IMAP4rev1 SASL-IR LOGIN-REFERRALS ID ENABLE IDLE LITERAL+ STARTTLS AUTH=PLAIN] Dovecot (Ubuntu) ready.\r\n", "imap", "[CAPABILITY IMAP4rev1 SASL-IR LOGIN-REFERRALS ID ENABLE IDLE LITERAL+ STARTTLS AUTH=PLAIN] Dovecot (Ubuntu) ready."),
+            (b"RFB 003.008\n", "vnc", "003.008"),
+            (b"-NOAUTH Authentication required.\r\n", "redis", ""),
+            (b"\x00\x00\x00\x1c\xff\x53\x4d\x42", "unknown", ""),
+        ];
+        for (raw, service, version) in cases {
+            let fp = match_greeting(raw);
+            assert_eq!((fp.service.as_str(), fp.version.as_str()), (service, version), "{}", String::from_utf8_lossy(raw));
+            assert_eq!(fp.banner, printable(raw));
+        }
+    }
+
+    #[test]
+    fn mysql_greetings_carry_the_server_version() {
+        for version in ["5.7.42-log", "8.0.36", "5.5.5-10.6.12-MariaDB-0ubuntu0.22.04.1"] {
+            let raw = mysql_greeting(version);
+            assert_eq!(mysql_version(&raw).as_deref(), Some(version));
+            let fp = match_greeting(&raw);
+            assert_eq!((fp.service.as_str(), fp.version.as_str()), ("mysql", version));
+        }
+    }
+
+    #[test]
+    fn mysql_version_rejects_other_packets() {
+        // "Host ... is not allowed to connect" error packet
+        let refused = b"\x45\x00\x00\x00\xff\x6a\x04Host '10.0.0.1' is not allowed to connect to this MySQL server";
+        assert_eq!(mysql_version(refused), None);
+        assert_eq!(match_greeting(refused).service, "unknown");
+        assert_eq!(mysql_version(b"\x0a\x00\x00\x00\x0a"), None);
+        // no NUL after the version
+        assert_eq!(mysql_version(b"\x0a\x00\x00\x00\x0a8.0.36"), None);
+        // the version has to start with a digit
+        assert_eq!(mysql_version(b"\x0a\x00\x00\x00\x0aabc\x00"), None);
+    }
+
+    #[test]
+    fn banners_mask_control_bytes() {
+        assert_eq!(printable(b"RFB 003.008\n"), "RFB 003.008\n");
+        assert_eq!(printable(b"\x16\x03\x01ok\xff"), "...ok.");
+        assert_eq!(printable(&[b'a'; 600]).len(), MAX_BANNER);
+    }
+
+    #[test]
+    fn http_server_header_is_read_from_the_head() {
+        let resp = "HTTP/1.1 200 OK\r\nserver: nginx/1.24.0\r\nContent-Type: text/html\r\n\r\nServer: not-a-header";
+        assert_eq!(header_value(resp, "Server").as_deref(), Some("nginx/1.24.0"));
+        assert_eq!(header_value("HTTP/1.1 204 No Content\r\n\r\nServer: x", "server"), None);
+    }
+
+    /// Walk a ClientHello record and return its SNI host name, checking every
+    /// length field on the way.
+    fn sni_of(rec: &[u8]) -> Option<String> {
+        let be16 = |b: &[u8]| u16::from_be_bytes([b[0], b[1]]) as usize;
+        assert_eq!(&rec[..3], [0x16, 0x03, 0x01]);
+        assert_eq!(be16(&rec[3..]), rec.len() - 5);
+        let hs = &rec[5..];
+        assert_eq!(hs[0], 0x01);
+        assert_eq!(u32::from_be_bytes([0, hs[1], hs[2], hs[3]]) as usize, hs.len() - 4);
+        let body = &hs[4..];
+        assert_eq!(&body[..2], [0x03, 0x03]);
+        let mut i = 2 + 32;
+        i += 1 + body[i] as usize; // session id
+        i += 2 + be16(&body[i..]); // cipher suites
+        i += 1 + body[i] as usize; // compression
+        let ext_len = be16(&body[i..]);
+        i += 2;
+        assert_eq!(i + ext_len, body.len());
+        let mut sni = None;
+        while i < body.len() {
+            let (typ, len) = (be16(&body[i..]), be16(&body[i + 2..]));
+            let data = &body[i + 4..i + 4 + len];
+            if typ == 0 {
+                assert_eq!(be16(data), len - 2);
+                assert_eq!(data[2], 0x00);
+                let n = be16(&data[3..]);
+                sni = Some(String::from_utf8(data[5..5 + n].to_vec()).unwrap());
+            }
+            i += 4 + len;
+        }
+        assert_eq!(i, body.len());
+        sni
+    }
+
+    #[test]
+    fn client_hello_is_well_formed() {
+        assert_eq!(sni_of(&client_hello("www.example.com")).as_deref(), Some("www.example.com"));
+        assert_eq!(sni_of(&client_hello("10.0.0.1")), None);
+        assert_eq!(sni_of(&client_hello("2001:db8::1")), None);
+    }
+}
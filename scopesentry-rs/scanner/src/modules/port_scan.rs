@@ -0,0 +1,183 @@
+use std::{net::{IpAddr, SocketAddr}, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+use tokio::{net::TcpStream, sync::{Mutex, Semaphore}, task::JoinSet, time::{interval, timeout, Interval, MissedTickBehavior}};
+
+use scopesentry_common::{models::DispatchTemplate, util::now_string};
+
+use crate::{dns, pipeline::{ModuleParams, PortRec, ScanModule, ScanState}, Ctx};
+
+const DEFAULT_PORTS: &str = "21,22,23,25,53,80,110,135,139,143,443,445,1433,1521,3306,3389,5432,5900,6379,8000,8080,8443,8888,9200,27017";
+
+/// Native TCP connect scan of every in-scope host, on each of its addresses.
+/// Hosts resolved by the subdomain stages are connected to at their recorded
+/// addresses; the others are resolved through `-resolvers` or the node's
+/// resolvers.
+///
+/// Args: `-port <list>` ports or ranges (`80,443,8000-8100`), `-b <n>` concurrent
+/// connects, `-t <ms>` connect timeout, `-r <n>` max connects per second for the
+/// whole scan (0 = unlimited), `-resolvers <ip,ip:port>`. The node scans one
+/// target at a time, so `-r` and the node's rate limit hold node-wide.
+pub struct PortScan;
+
+#[async_trait]
+impl ScanModule for PortScan {
+    fn name(&self) -> &'static str { "PortScan" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let ports = parse_ports(&params.flag("port").unwrap_or_else(|| DEFAULT_PORTS.to_string()));
+        let concurrency = params.concurrency("b", 600);
+        let connect_timeout = Duration::from_millis(params.flag_or("t", 3000u64));
+        let rate = params.rate(0);
+        // one pacer for every host, so the rate caps the scan as a whole
+        let pacer = (rate > 0).then(|| {
+            let mut iv = interval(Duration::from_secs(1) / rate.min(1_000_000) as u32);
+            iv.set_missed_tick_behavior(MissedTickBehavior::Delay);
+            Arc::new(Mutex::new(iv))
+        });
+
+        let mut resolvers: Vec<String> = params.flag("resolvers").unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if resolvers.is_empty() { resolvers = ctx.node.resolvers().to_vec(); }
+        let targets = addresses(state, &resolvers, connect_timeout).await?;
+
+        let limit = Arc::new(Semaphore::new(concurrency));
+        let mut set = JoinSet::new();
+        for (host, ip) in targets {
+            for &port in &ports {
+                // bound the number of in-flight connects (and spawned tasks)
+                let permit = limit.clone().acquire_owned().await?;
+                let (pacer, host) = (pacer.clone(), host.clone());
+                set.spawn(async move {
+                    let _permit = permit;
+                    if let Some(p) = pacer { pace(&p).await; }
+                    let open = matches!(timeout(connect_timeout, TcpStream::connect(SocketAddr::new(ip, port))).await, Ok(Ok(_)));
+                    open.then(|| PortRec { host, ip: ip.to_string(), port, ..Default::default() })
+                });
+            }
+        }
+
+        let mut found = vec![];
+        while let Some(res) = set.join_next().await {
+            if let Ok(Some(rec)) = res { found.push(rec); }
+        }
+        found.sort_by(|a, b| (&a.host, a.port, &a.ip).cmp(&(&b.host, b.port, &b.ip)));
+        // a port open on several addresses of a host is one asset
+        found.dedup_by(|a, b| a.host == b.host && a.port == b.port);
+        for rec in &found {
+            save_port(ctx, &tmpl.TaskName, rec).await.ok();
+        }
+        state.ports.extend(found);
+        Ok(())
+    }
+}
+
+async fn pace(pacer: &Mutex<Interval>) {
+    pacer.lock().await.tick().await;
+}
+
+/// Every `(host, address)` pair to scan. Addresses recorded by earlier stages
+/// are used as they are; other hostnames are looked up through `resolvers`.
+async fn addresses(state: &ScanState, resolvers: &[String], timeout: Duration) -> anyhow::Result<Vec<(String, IpAddr)>> {
+    let mut out = vec![];
+    let mut unresolved = vec![];
+    for host in &state.hosts {
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            out.push((host.clone(), ip));
+            continue;
+        }
+        let known = state.resolved_ips(host);
+        if known.is_empty() { unresolved.push(host.clone()); }
+        out.extend(known.into_iter().map(|ip| (host.clone(), ip)));
+    }
+    if !unresolved.is_empty() {
+        let resolver = dns::resolver(resolvers, timeout)?;
+        for r in dns::resolve_all(&resolver, &unresolved, &[]).await {
+            out.extend(r.ips.into_iter().map(|ip| (r.host.clone(), ip)));
+        }
+    }
+    Ok(out)
+}
+
+/// Parse a port list such as `22,80,8000-8100`; invalid items are skipped.
+pub fn parse_ports(spec: &str) -> Vec<u16> {
+    let mut ports = vec![];
+    for item in spec.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+        match item.split_once('-') {
+            Some((a, b)) => {
+                if let (Ok(a), Ok(b)) = (a.trim().parse::<u16>(), b.trim().parse::<u16>()) {
+                    ports.extend(a.min(b)..=a.max(b));
+                }
+            }
+            None => { if let Ok(p) = item.parse::<u16>() { ports.push(p); } }
+        }
+    }
+    ports.retain(|p| *p != 0);
+    ports.sort_unstable();
+    ports.dedup();
+    ports
+}
+
+async fn save_port(ctx: &Ctx, task_name: &str, rec: &PortRec) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("asset");
+    let now = now_string();
+    let port = rec.port as i32;
+    let filter = doc!{"host": &rec.host, "port": port};
+    let update = doc!{
+        "$set": {"host": &rec.host, "ip": &rec.ip, "port": port, "time": &now, "taskName": task_name},
+        "$setOnInsert": {"type": "other", "service": ""},
+    };
+    coll.update_one(filter, update).upsert(true).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    use crate::dns::Records;
+
+    use super::*;
+
+    #[test]
+    fn parse_ports_takes_lists_and_ranges() {
+        for (spec, want) in [
+            ("80", vec![80]),
+            ("443, 80 ,22", vec![22, 80, 443]),
+            ("8000-8003", vec![8000, 8001, 8002, 8003]),
+            ("8003 - 8001", vec![8001, 8002, 8003]),
+            ("80,79-81,80", vec![79, 80, 81]),
+            ("0,0-2", vec![1, 2]),
+            ("http,70000,-5,5-,1-x,", vec![]),
+            ("65534-65535,abc,22", vec![22, 65534, 65535]),
+            ("", vec![]),
+        ] {
+            assert_eq!(parse_ports(spec), want, "{}", spec);
+        }
+        assert_eq!(parse_ports(DEFAULT_PORTS).len(), 25);
+        assert_eq!(parse_ports("1-65535").len(), 65535);
+    }
+
+    #[tokio::test]
+    async fn addresses_cover_every_recorded_ip() {
+        let mut state = ScanState::new("example.com");
+        for h in ["www.example.com", "10.0.0.9", "2001:db8::9", "gone.example.com"] { state.add_host(h); }
+        let v4 = IpAddr::V4(Ipv4Addr::new(192, 0, 2, 1));
+        let v6 = IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1));
+        state.records.push(Records { host: "www.example.com".to_string(), ips: vec![v4, v6], ..Default::default() });
+
+        // nothing answers on the resolver, so the unresolved host is dropped
+        let found = addresses(&state, &["127.0.0.1:9".to_string()], Duration::from_millis(200)).await.unwrap();
+        assert_eq!(found, [
+            ("www.example.com".to_string(), v4),
+            ("www.example.com".to_string(), v6),
+            ("10.0.0.9".to_string(), "10.0.0.9".parse().unwrap()),
+            ("2001:db8::9".to_string(), "2001:db8::9".parse().unwrap()),
+        ]);
+    }
+}
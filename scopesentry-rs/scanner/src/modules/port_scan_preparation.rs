@@ -0,0 +1,23 @@
+use async_trait::async_trait;
+
+use scopesentry_common::models::DispatchTemplate;
+
+use crate::{pipeline::{ModuleParams, ScanModule, ScanState}, Ctx};
+
+/// Adds the addresses the subdomains resolved to as port scan targets, so
+/// hosts behind a name are also scanned (and mapped) by IP. Only runs when
+/// the template enables it.
+pub struct PortScanPreparation;
+
+#[async_trait]
+impl ScanModule for PortScanPreparation {
+    fn name(&self) -> &'static str { "PortScanPreparation" }
+
+    async fn run(&self, _ctx: &Ctx, _tmpl: &DispatchTemplate, _params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let ips: Vec<String> = state.records.iter().flat_map(|r| r.ip_strings()).collect();
+        for ip in ips {
+            state.add_host(&ip);
+        }
+        Ok(())
+    }
+}
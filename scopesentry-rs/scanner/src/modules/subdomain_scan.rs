@@ -0,0 +1,199 @@
+use std::{collections::HashSet, net::IpAddr, path::PathBuf, time::Duration};
+
+use async_trait::async_trait;
+use rand::{distributions::Alphanumeric, Rng};
+use trust_dns_resolver::TokioAsyncResolver;
+
+use scopesentry_common::models::DispatchTemplate;
+
+use super::DictCache;
+use crate::{dns::{self, Records}, http, passive, pipeline::{ModuleParams, ScanModule, ScanState}, store, Ctx};
+
+/// Random labels resolved to learn what a wildcard zone answers.
+const WILDCARD_PROBES: usize = 3;
+
+/// Subdomain brute force through rsubdomain, merged with the passive sources
+/// the args enable (see [`passive::from_params`]).
+///
+/// Every subdomain found is resolved (CNAME chain, A/AAAA) before it is
+/// saved. Args: `-subfile <dict id>` brute force wordlist
+/// (`{dict.subdomain.default}` in the template; rsubdomain's built-in list
+/// when unset), `-resolvers <ip[:port],...>` resolver pool (the node's
+/// `RESOLVERS`, else Google's public resolvers), `-bandwidth <rate>` send
+/// rate such as `5M`, `-device <iface>` capture interface, `-wildcard
+/// skip|filter|keep` wildcard zones are skipped by rsubdomain (default),
+/// have answers matching random labels dropped, or are kept as is,
+/// `-brute-timeout <s>` give up on the brute force after this long (0, no
+/// limit), `-records mx,txt,ns` extra record types to store, `-timeout <s>`
+/// per DNS query (5), `-passive-timeout <s>` per passive source request (60).
+#[derive(Default)]
+pub struct SubdomainScan {
+    dicts: DictCache,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Wildcard {
+    Skip,
+    Filter,
+    Keep,
+}
+
+impl Wildcard {
+    fn parse(s: &str) -> Self {
+        match s.to_lowercase().as_str() {
+            "filter" => Wildcard::Filter,
+            "keep" | "none" | "off" => Wildcard::Keep,
+            _ => Wildcard::Skip,
+        }
+    }
+}
+
+/// rsubdomain settings taken from the module args.
+#[derive(Debug, Clone)]
+struct BruteOptions {
+    dictionary: Option<PathBuf>,
+    resolvers: Vec<String>,
+    bandwidth: Option<String>,
+    device: Option<String>,
+    skip_wildcard: bool,
+    timeout: Option<Duration>,
+}
+
+#[async_trait]
+impl ScanModule for SubdomainScan {
+    fn name(&self) -> &'static str { "SubdomainScan" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let Some(domain) = state.domain.clone() else { return Ok(()); };
+        let mut resolvers: Vec<String> = params.flag("resolvers").unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_string())
+            .filter(|s| !s.is_empty())
+            .collect();
+        if resolvers.is_empty() { resolvers = ctx.node.resolvers().to_vec(); }
+        let resolver = dns::resolver(&resolvers, Duration::from_secs(params.flag_or("timeout", 5)))?;
+        let wildcard = Wildcard::parse(&params.flag("wildcard").unwrap_or_default());
+
+        let dictionary = match params.flag("subfile") {
+            Some(id) => Some(self.write_dict(ctx, &id).await?),
+            None => None,
+        };
+        let opts = BruteOptions {
+            dictionary: dictionary.clone(),
+            resolvers,
+            bandwidth: params.flag("bandwidth"),
+            device: params.flag("device"),
+            skip_wildcard: wildcard == Wildcard::Skip,
+            timeout: Some(Duration::from_secs(params.flag_or("brute-timeout", 0u64))).filter(|t| !t.is_zero()),
+        };
+
+        let client = http::client(Duration::from_secs(params.flag_or("passive-timeout", 60)))?;
+        let sources = passive::from_params(&params, &resolver, &client);
+        let (brute, found) = tokio::join!(subdomain_scan_rsubdomain(&domain, &opts), passive::enumerate(sources, &domain));
+        if let Some(path) = dictionary { let _ = tokio::fs::remove_file(path).await; }
+        let brute = brute?;
+        let mut subs = brute.clone();
+        subs.extend(found.iter().cloned());
+        subs.sort();
+        subs.dedup();
+
+        let extra = dns::record_types(&params.flag("records").unwrap_or_default());
+        let mut records = dns::resolve_all(&resolver, &subs, &extra).await;
+        if wildcard == Wildcard::Filter {
+            let answers = wildcard_answers(&resolver, &domain).await;
+            if !answers.is_empty() {
+                // only guessed names can be wildcard artefacts; passive ones were seen in the wild
+                let passive: HashSet<&String> = found.iter().collect();
+                let before = records.len();
+                records.retain(|r| passive.contains(&r.host) || !is_wildcard(r, &answers));
+                tracing::info!("{}: dropped {} wildcard answers", domain, before - records.len());
+            }
+        }
+
+        for r in store::save_subdomains(ctx, tmpl, records).await? {
+            state.add_host(&r.host);
+            state.subdomains.push(r.host.clone());
+            state.records.push(r);
+        }
+        Ok(())
+    }
+}
+
+impl SubdomainScan {
+    /// rsubdomain reads its wordlist from a file, so the GridFS dictionary is
+    /// written to a temporary one; the caller removes it.
+    async fn write_dict(&self, ctx: &Ctx, id: &str) -> anyhow::Result<PathBuf> {
+        let words = self.dicts.get(ctx, id).await?;
+        let path = std::env::temp_dir().join(format!("scopesentry-subdomain-{}-{}.txt", id, std::process::id()));
+        tokio::fs::write(&path, words.join("\n")).await?;
+        Ok(path)
+    }
+}
+
+/// rsubdomain's future is not `Send` (it keeps a thread-local RNG across
+/// awaits), so it gets a thread and a current-thread runtime of its own.
+async fn subdomain_scan_rsubdomain(target: &str, opts: &BruteOptions) -> anyhow::Result<Vec<String>> {
+    let (tx, rx) = tokio::sync::oneshot::channel();
+    let (target, opts) = (target.to_string(), opts.clone());
+    std::thread::Builder::new().name("rsubdomain".to_string()).spawn(move || {
+        let res = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(anyhow::Error::from)
+            .and_then(|rt| rt.block_on(brute_force(&target, &opts)));
+        let _ = tx.send(res);
+    })?;
+    rx.await.map_err(|_| anyhow::anyhow!("subdomain brute force thread died"))?
+}
+
+async fn brute_force(target: &str, opts: &BruteOptions) -> anyhow::Result<Vec<String>> {
+    let domains = vec![target.to_string()];
+    let brute = rsubdomain::brute_force_subdomains(
+        domains,
+        opts.dictionary.as_ref().map(|p| p.to_string_lossy().into_owned()),
+        Some(opts.resolvers.clone()).filter(|r| !r.is_empty()),
+        opts.skip_wildcard,
+        opts.bandwidth.clone(),
+        false,  // verify_mode
+        false,  // resolve_records
+        true,   // silent
+        opts.device.clone(),
+    );
+    let results = match opts.timeout {
+        Some(limit) => match tokio::time::timeout(limit, brute).await {
+            Ok(res) => res,
+            Err(_) => {
+                tracing::warn!("{}: subdomain brute force timed out after {}s", target, limit.as_secs());
+                return Ok(vec![]);
+            }
+        },
+        None => brute.await,
+    }.map_err(|e| anyhow::anyhow!(e.to_string()))?;
+
+    let mut subs: Vec<String> = results.into_iter().map(|r| r.domain).collect();
+    subs.sort();
+    subs.dedup();
+    Ok(subs)
+}
+
+/// What names that cannot exist under `domain` resolve to: the CNAME targets
+/// and addresses a wildcard answers with, empty for zones without one.
+async fn wildcard_answers(resolver: &TokioAsyncResolver, domain: &str) -> HashSet<String> {
+    let mut answers = HashSet::new();
+    for _ in 0..WILDCARD_PROBES {
+        let label: String = rand::thread_rng().sample_iter(&Alphanumeric).take(12).map(char::from).collect();
+        let probe = dns::resolve(resolver, &format!("{}.{}", label.to_lowercase(), domain), &[]).await;
+        answers.extend(probe.cnames);
+        answers.extend(probe.ips.iter().map(IpAddr::to_string));
+    }
+    answers
+}
+
+/// `r` resolved, and only to what the wildcard answers.
+fn is_wildcard(r: &Records, answers: &HashSet<String>) -> bool {
+    if r.ips.is_empty() && r.cnames.is_empty() { return false; }
+    match r.cnames.first() {
+        Some(cname) => answers.contains(cname),
+        None => r.ips.iter().all(|ip| answers.contains(&ip.to_string())),
+    }
+}
@@ -0,0 +1,302 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+use reqwest::Client;
+use tokio::{sync::Semaphore, task::JoinSet};
+use trust_dns_resolver::TokioAsyncResolver;
+
+use scopesentry_common::{models::DispatchTemplate, util::now_string};
+
+use crate::{dns, http, pipeline::{ModuleParams, ScanModule, ScanState}, scope::Scope, Ctx};
+
+/// Stored response bodies are cut to this many bytes.
+const MAX_RESPONSE: usize = 4096;
+
+/// A hosting service whose unclaimed resources can be registered by anyone,
+/// so a CNAME left pointing at one can be taken over (after
+/// can-i-take-over-xyz).
+struct Provider {
+    name: &'static str,
+    /// CNAME target suffixes of the service
+    cnames: &'static [&'static str],
+    /// text the service serves for an unclaimed resource
+    fingerprints: &'static [&'static str],
+    /// a CNAME target that does not resolve is enough to claim the resource
+    nxdomain: bool,
+}
+
+impl Provider {
+    fn serves(&self, cname: &str) -> bool {
+        let cname = format!(".{}", cname);
+        self.cnames.iter().any(|s| cname.ends_with(&format!(".{}", s)))
+    }
+}
+
+const PROVIDERS: &[Provider] = &[
+    Provider { name: "AWS/S3", cnames: &["amazonaws.com"], fingerprints: &["The specified bucket does not exist", "NoSuchBucket"], nxdomain: false },
+    Provider { name: "AWS/Elastic Beanstalk", cnames: &["elasticbeanstalk.com"], fingerprints: &[], nxdomain: true },
+    Provider { name: "Microsoft Azure", cnames: &[
+        "cloudapp.net", "cloudapp.azure.com", "azurewebsites.net", "blob.core.windows.net", "azure-api.net", "azurehdinsight.net",
+        "azureedge.net", "azurecontainer.io", "database.windows.net", "azuredatalakestore.net", "search.windows.net", "azurecr.io",
+        "redis.cache.windows.net", "servicebus.windows.net", "visualstudio.com", "trafficmanager.net",
+    ], fingerprints: &[], nxdomain: true },
+    Provider { name: "GitHub Pages", cnames: &["github.io"], fingerprints: &["There isn't a GitHub Pages site here."], nxdomain: false },
+    Provider { name: "Heroku", cnames: &["herokuapp.com", "herokudns.com", "herokussl.com"], fingerprints: &["No such app", "no-such-app.html"], nxdomain: false },
+    Provider { name: "Shopify", cnames: &["myshopify.com"], fingerprints: &["Sorry, this shop is currently unavailable."], nxdomain: false },
+    Provider { name: "Fastly", cnames: &["fastly.net"], fingerprints: &["Fastly error: unknown domain"], nxdomain: false },
+    Provider { name: "Ghost", cnames: &["ghost.io"], fingerprints: &["The thing you were looking for is no longer here, or never was"], nxdomain: false },
+    Provider { name: "Pantheon", cnames: &["pantheonsite.io"], fingerprints: &["The gods are wise, but do not know of the site which you seek."], nxdomain: false },
+    Provider { name: "Tumblr", cnames: &["domains.tumblr.com"], fingerprints: &["Whatever you were looking for doesn't currently exist at this address"], nxdomain: false },
+    Provider { name: "WordPress", cnames: &["wordpress.com"], fingerprints: &["Do you want to register"], nxdomain: false },
+    Provider { name: "Surge.sh", cnames: &["surge.sh"], fingerprints: &["project not found"], nxdomain: false },
+    Provider { name: "Bitbucket", cnames: &["bitbucket.io"], fingerprints: &["Repository not found"], nxdomain: false },
+    Provider { name: "Zendesk", cnames: &["zendesk.com"], fingerprints: &["Help Center Closed"], nxdomain: false },
+    Provider { name: "Unbounce", cnames: &["unbouncepages.com"], fingerprints: &["The requested URL was not found on this server."], nxdomain: false },
+    Provider { name: "Readme.io", cnames: &["readme.io"], fingerprints: &["Project doesnt exist... yet!"], nxdomain: false },
+    Provider { name: "Agile CRM", cnames: &["agilecrm.com"], fingerprints: &["Sorry, this page is no longer available."], nxdomain: false },
+    Provider { name: "Helpjuice", cnames: &["helpjuice.com"], fingerprints: &["We could not find what you're looking for."], nxdomain: false },
+    Provider { name: "Help Scout", cnames: &["helpscoutdocs.com"], fingerprints: &["No settings were found for this company:"], nxdomain: false },
+    Provider { name: "Strikingly", cnames: &["s.strikinglydns.com"], fingerprints: &["But if you're looking to build your own website"], nxdomain: false },
+    Provider { name: "Uberflip", cnames: &["read.uberflip.com"], fingerprints: &["The URL you've accessed does not provide a hub."], nxdomain: false },
+    Provider { name: "Webflow", cnames: &["proxy.webflow.com", "proxy-ssl.webflow.com"], fingerprints: &["The page you are looking for doesn't exist or has been moved."], nxdomain: false },
+    Provider { name: "Ngrok", cnames: &["ngrok.io"], fingerprints: &["ngrok.io not found"], nxdomain: false },
+    Provider { name: "LaunchRock", cnames: &["launchrock.com"], fingerprints: &["It looks like you may have taken a wrong turn somewhere."], nxdomain: false },
+    Provider { name: "Canny", cnames: &["cname.canny.io"], fingerprints: &["Company Not Found"], nxdomain: false },
+    Provider { name: "Gemfury", cnames: &["furyns.com"], fingerprints: &["404: This page could not be found."], nxdomain: false },
+    Provider { name: "Kinsta", cnames: &["kinsta.cloud"], fingerprints: &["No Site For Domain"], nxdomain: false },
+    Provider { name: "SmartJobBoard", cnames: &["smartjobboard.com"], fingerprints: &["This job board website is either expired or its domain name is invalid."], nxdomain: false },
+    Provider { name: "Pingdom", cnames: &["stats.pingdom.com"], fingerprints: &["Sorry, couldn't find the status page"], nxdomain: false },
+    Provider { name: "Tilda", cnames: &["tilda.ws"], fingerprints: &["Please renew your subscription"], nxdomain: false },
+    Provider { name: "JetBrains YouTrack", cnames: &["youtrack.cloud"], fingerprints: &["is not a registered InCloud YouTrack"], nxdomain: false },
+    Provider { name: "Discourse", cnames: &["trydiscourse.com"], fingerprints: &[], nxdomain: true },
+];
+
+/// Subdomain takeover candidates among the subdomains found so far.
+///
+/// Each subdomain's CNAME chain is resolved; chains ending at a service in
+/// [`PROVIDERS`] are confirmed either by the target no longer existing
+/// (NXDOMAIN, for services that allow claiming it) or by the service's
+/// "unclaimed" page being served for the subdomain. Args: `-t <n>` concurrent
+/// checks (20), `-timeout <s>` (5).
+pub struct SubdomainSecurity;
+
+#[derive(Debug, Clone)]
+struct Candidate {
+    host: String,
+    cname: String,
+    provider: &'static str,
+    evidence: String,
+    response: String,
+}
+
+#[async_trait]
+impl ScanModule for SubdomainSecurity {
+    fn name(&self) -> &'static str { "SubdomainSecurity" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let timeout = Duration::from_secs(params.flag_or("timeout", 5));
+        let resolver = Arc::new(dns::resolver(&ctx.node.resolvers(), timeout)?);
+        let client = http::client(timeout)?;
+        let limit = Arc::new(Semaphore::new(params.concurrency("t", 20)));
+
+        let mut hosts: Vec<String> = state.subdomains.clone();
+        if let Some(d) = &state.domain {
+            if !hosts.contains(d) { hosts.push(d.clone()); }
+        }
+        let scope = Arc::new(Scope::new(hosts.iter().map(String::as_str), "").await);
+
+        let mut set = JoinSet::new();
+        for host in hosts {
+            let permit = limit.clone().acquire_owned().await?;
+            let (resolver, client, scope) = (resolver.clone(), client.clone(), scope.clone());
+            let known = state.records.iter().find(|r| r.host == host).map(|r| r.cnames.clone());
+            set.spawn(async move {
+                let _permit = permit;
+                check(&resolver, &client, &scope, host, known).await
+            });
+        }
+        while let Some(res) = set.join_next().await {
+            let Ok(Some(c)) = res else { continue; };
+            tracing::info!("possible takeover of {} via {} ({})", c.host, c.cname, c.provider);
+            save_candidate(ctx, &tmpl.TaskName, &scope.root_domain(&c.host), &c).await.ok();
+        }
+        Ok(())
+    }
+}
+
+/// `known` is the CNAME chain recorded by the subdomain scan, if any. The
+/// page is fetched without leaving `scope`, so a provider's redirect to its
+/// own site is not mistaken for the unclaimed page.
+async fn check(resolver: &TokioAsyncResolver, client: &Client, scope: &Scope, host: String, known: Option<Vec<String>>) -> Option<Candidate> {
+    let chain = match known {
+        Some(chain) => chain,
+        None => dns::cname_chain(resolver, &host).await,
+    };
+    let (cname, provider) = provider_of(&chain)?;
+    let last = chain.last()?;
+
+    if provider.nxdomain && dns::is_nxdomain(resolver, last).await {
+        return Some(Candidate { host, cname, provider: provider.name, evidence: format!("NXDOMAIN {}", last), response: String::new() });
+    }
+    if provider.fingerprints.is_empty() { return None; }
+    for scheme in ["http", "https"] {
+        let Ok(resp) = http::fetch_in(client, &format!("{}://{}/", scheme, host), scope).await else { continue; };
+        let body = resp.text();
+        if let Some(fp) = provider.fingerprints.iter().find(|f| body.contains(**f)) {
+            return Some(Candidate { host, cname, provider: provider.name, evidence: fp.to_string(), response: truncate(&body, MAX_RESPONSE).to_string() });
+        }
+    }
+    None
+}
+
+/// The first CNAME of `chain` pointing at a known provider.
+fn provider_of(chain: &[String]) -> Option<(String, &'static Provider)> {
+    chain.iter().find_map(|c| PROVIDERS.iter().find(|p| p.serves(c)).map(|p| (c.clone(), p)))
+}
+
+/// `s` cut to at most `max` bytes, on a char boundary.
+fn truncate(s: &str, max: usize) -> &str {
+    let mut end = s.len().min(max);
+    while !s.is_char_boundary(end) { end -= 1; }
+    &s[..end]
+}
+
+async fn save_candidate(ctx: &Ctx, task_name: &str, root: &str, c: &Candidate) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("SubdoaminTakerResult");
+    let update = doc!{
+        "$set": {
+            "input": &c.host, "value": &c.cname, "cname": c.provider, "evidence": &c.evidence, "response": &c.response,
+            "rootDomain": root, "time": now_string(), "taskName": task_name,
+        },
+        "$setOnInsert": {"tags": []},
+    };
+    coll.update_one(doc!{"input": &c.host, "value": &c.cname}, update).upsert(true).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, UdpSocket}};
+    use trust_dns_resolver::proto::{op::{Message, MessageType, ResponseCode}, rr::{RData, Record, RecordType}};
+
+    use super::*;
+
+    fn provider(name: &str) -> &'static Provider {
+        PROVIDERS.iter().find(|p| p.name == name).unwrap()
+    }
+
+    #[test]
+    fn serves_matches_whole_labels() {
+        let pages = provider("GitHub Pages");
+        for (cname, want) in [
+            ("github.io", true),
+            ("user.github.io", true),
+            ("a.b.user.github.io", true),
+            ("notgithub.io", false),
+            ("user.notgithub.io", false),
+            ("github.io.evil.net", false),
+            ("github.com", false),
+        ] {
+            assert_eq!(pages.serves(cname), want, "{}", cname);
+        }
+        let tumblr = provider("Tumblr");
+        assert!(tumblr.serves("domains.tumblr.com"));
+        assert!(!tumblr.serves("blog.tumblr.com"));
+    }
+
+    #[test]
+    fn provider_of_takes_the_first_known_cname() {
+        let chain = ["edge.example.net", "shop.myshopify.com", "user.github.io"].map(String::from);
+        let (cname, p) = provider_of(&chain).unwrap();
+        assert_eq!((cname.as_str(), p.name), ("shop.myshopify.com", "Shopify"));
+        assert!(provider_of(&["edge.example.net".to_string()]).is_none());
+        assert!(provider_of(&[]).is_none());
+    }
+
+    #[test]
+    fn truncate_keeps_char_boundaries() {
+        assert_eq!(truncate("héllo", 2), "h");
+        assert_eq!(truncate("héllo", 3), "hé");
+        assert_eq!(truncate("hello", 10), "hello");
+    }
+
+    /// A DNS server on UDP answering NXDOMAIN for names under `gone.` labels
+    /// and 192.0.2.1 for everything else.
+    async fn dns_server() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+                let Ok(query) = Message::from_vec(&buf[..n]) else { continue; };
+                let mut msg = Message::new();
+                msg.set_id(query.id()).set_message_type(MessageType::Response).set_recursion_available(true);
+                msg.add_queries(query.queries().to_vec());
+                let name = query.queries()[0].name().clone();
+                if name.to_ascii().starts_with("gone.") {
+                    msg.set_response_code(ResponseCode::NXDomain);
+                } else if query.queries()[0].query_type() == RecordType::A {
+                    msg.add_answer(Record::from_rdata(name, 300, RData::A(Ipv4Addr::new(192, 0, 2, 1))));
+                }
+                let _ = socket.send_to(&msg.to_vec().unwrap(), peer).await;
+            }
+        });
+        addr
+    }
+
+    /// An HTTP server answering every request with `body`.
+    async fn http_server(body: &'static str) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                let resp = format!("HTTP/1.1 404 Not Found\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                let _ = stream.write_all(resp.as_bytes()).await;
+            }
+        });
+        addr
+    }
+
+    async fn run_check(dns: SocketAddr, host: &str, chain: &[&str]) -> Option<Candidate> {
+        let resolver = dns::resolver(&[dns.to_string()], Duration::from_secs(2)).unwrap();
+        let client = http::client(Duration::from_secs(2)).unwrap();
+        let scope = Scope::new(["127.0.0.1", "example.com"], "").await;
+        let chain = chain.iter().map(|c| c.to_string()).collect();
+        check(&resolver, &client, &scope, host.to_string(), Some(chain)).await
+    }
+
+    #[tokio::test]
+    async fn dangling_cname_to_an_nxdomain_provider_is_reported() {
+        let dns = dns_server().await;
+        let c = run_check(dns, "app.example.com", &["gone.eu-west-1.elasticbeanstalk.com"]).await.unwrap();
+        assert_eq!(c.provider, "AWS/Elastic Beanstalk");
+        assert_eq!(c.cname, "gone.eu-west-1.elasticbeanstalk.com");
+        assert_eq!(c.evidence, "NXDOMAIN gone.eu-west-1.elasticbeanstalk.com");
+
+        // the target still resolves, and the provider has no page to match
+        assert!(run_check(dns, "app.example.com", &["live.eu-west-1.elasticbeanstalk.com"]).await.is_none());
+        // not a provider at all
+        assert!(run_check(dns, "app.example.com", &["gone.example.net"]).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn unclaimed_page_is_reported() {
+        let dns = dns_server().await;
+        let unclaimed = http_server("<h1>404</h1><p>There isn't a GitHub Pages site here.</p>").await;
+        let c = run_check(dns, &unclaimed.to_string(), &["user.github.io"]).await.unwrap();
+        assert_eq!(c.provider, "GitHub Pages");
+        assert_eq!(c.evidence, "There isn't a GitHub Pages site here.");
+        assert!(c.response.contains("<h1>404</h1>"));
+
+        let claimed = http_server("<h1>Welcome</h1>").await;
+        assert!(run_check(dns, &claimed.to_string(), &["user.github.io"]).await.is_none());
+        // NXDOMAIN alone does not count for a provider that needs a page
+        assert!(run_check(dns, &claimed.to_string(), &["gone.github.io"]).await.is_none());
+    }
+}
@@ -0,0 +1,33 @@
+use async_trait::async_trait;
+
+use scopesentry_common::models::DispatchTemplate;
+
+use crate::{pipeline::{ModuleParams, ScanModule, ScanState}, Ctx};
+
+/// Turns the raw target into typed pipeline input: URLs, domains or IPs.
+pub struct TargetHandler;
+
+#[async_trait]
+impl ScanModule for TargetHandler {
+    fn name(&self) -> &'static str { "TargetHandler" }
+
+    fn always_run(&self) -> bool { true }
+
+    async fn run(&self, _ctx: &Ctx, _tmpl: &DispatchTemplate, _params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let target = state.target.trim().to_string();
+        if target.contains("://") {
+            let parsed = url::Url::parse(&target)?;
+            if let Some(host) = parsed.host_str() { state.add_host(host); }
+            state.urls.push(target);
+        } else if target.parse::<std::net::IpAddr>().is_ok() {
+            state.add_host(&target);
+        } else if let Some((host, port)) = target.rsplit_once(':').filter(|(_, p)| p.parse::<u16>().is_ok()) {
+            state.add_host(host);
+            state.urls.push(format!("http://{}:{}", host, port));
+        } else {
+            state.add_host(&target);
+            state.domain = Some(target);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+use tokio::{sync::Semaphore, task::JoinSet};
+use url::Url;
+
+use scopesentry_common::{models::DispatchTemplate, util::now_string};
+
+use crate::{dns, extract, http, pipeline::{ModuleParams, Page, ScanModule, ScanState}, scope::Scope, store, Ctx};
+
+/// Script chunks referenced from scripts are followed this many levels deep.
+const MAX_ROUNDS: usize = 3;
+
+/// Endpoint and hostname extraction from the JavaScript linked by the pages
+/// fetched so far. The stage runs before WebCrawler, so those are the landing
+/// pages of AssetMapping; scripts only the crawler reaches are not scanned.
+///
+/// Every endpoint is written to `UrlScan` with the script it came from as its
+/// source; in-scope hostnames that are not known yet are resolved and saved
+/// as subdomains of the task. Script bodies are kept for the sensitive-content
+/// stage. Args: `-t <n>` concurrent downloads (10), `-max <n>` scripts per target
+/// (100), `-timeout <s>`.
+pub struct UrlScan;
+
+/// A script to download and the page that loaded it.
+#[derive(Debug, Clone)]
+struct Script {
+    url: Url,
+    page: Url,
+}
+
+#[async_trait]
+impl ScanModule for UrlScan {
+    fn name(&self) -> &'static str { "URLScan" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let max_scripts = params.flag_or("max", 100usize);
+        let limit = Arc::new(Semaphore::new(params.concurrency("t", 10)));
+        let client = http::client(Duration::from_secs(params.flag_or("timeout", 5)))?;
+
+        let seeds: Vec<String> = state.assets.iter().map(|a| a.host.clone()).chain(state.domain.clone()).collect();
+        let scope = Arc::new(Scope::new(seeds.iter().map(String::as_str), &tmpl.ignore).await);
+
+        let mut seen = HashSet::new();
+        let mut queue = vec![];
+        for page in &state.pages {
+            let Ok(base) = Url::parse(&page.url) else { continue; };
+            for link in extract::links(&base, &page.body) {
+                if extract::is_script(&link) && scope.contains(&link) && seen.insert(link.to_string()) {
+                    queue.push(Script { url: link, page: base.clone() });
+                }
+            }
+        }
+
+        let mut hosts: Vec<String> = vec![];
+        let mut fetched = 0;
+        for _ in 0..MAX_ROUNDS {
+            if queue.is_empty() || fetched >= max_scripts { break; }
+            queue.truncate(max_scripts - fetched);
+            fetched += queue.len();
+
+            let mut set = JoinSet::new();
+            for script in queue.drain(..) {
+                let permit = limit.clone().acquire_owned().await?;
+                let (client, scope) = (client.clone(), scope.clone());
+                set.spawn(async move {
+                    let _permit = permit;
+                    let resp = http::fetch_in(&client, script.url.as_str(), &scope).await.ok().filter(|r| r.status.is_success());
+                    (script, resp)
+                });
+            }
+
+            let mut next = vec![];
+            while let Some(res) = set.join_next().await {
+                let Ok((script, Some(resp))) = res else { continue; };
+                let body = resp.text();
+
+                for endpoint in extract::js_endpoints(&body) {
+                    let resolved = if endpoint.starts_with('/') && !endpoint.starts_with("//") {
+                        extract::resolve(&script.page, &endpoint)
+                    } else if endpoint.contains("://") || endpoint.starts_with("//") || endpoint.starts_with('.') {
+                        extract::resolve(&script.url, &endpoint)
+                    } else {
+                        None
+                    };
+                    let output = match &resolved {
+                        Some(u) if !scope.contains(u) => continue,
+                        Some(u) => u.to_string(),
+                        None => endpoint.clone(),
+                    };
+                    save_endpoint(ctx, &tmpl.TaskName, &script, &output).await.ok();
+
+                    if let Some(u) = resolved.filter(extract::is_script) {
+                        if seen.insert(u.to_string()) { next.push(Script { url: u, page: script.page.clone() }); }
+                    }
+                }
+
+                for host in extract::hostnames(&body) {
+                    if scope.contains_host(&host) && !hosts.contains(&host) { hosts.push(host); }
+                }
+                state.pages.push(Page { url: script.url.to_string(), body });
+            }
+            queue = next;
+        }
+
+        let new: Vec<String> = hosts.into_iter().filter(|h| !state.hosts.contains(h) && !state.subdomains.contains(h)).collect();
+        if !new.is_empty() {
+            let resolver = dns::resolver(&ctx.node.resolvers(), Duration::from_secs(params.flag_or("timeout", 5)))?;
+            let records = dns::resolve_all(&resolver, &new, &[]).await;
+            for r in store::save_subdomains(ctx, tmpl, records).await? {
+                state.add_host(&r.host);
+                state.subdomains.push(r.host.clone());
+                state.records.push(r);
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn save_endpoint(ctx: &Ctx, task_name: &str, script: &Script, output: &str) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("UrlScan");
+    let update = doc!{
+        "$set": {"input": script.page.as_str(), "source": script.url.as_str(), "outputtype": "js", "output": output, "time": now_string(), "taskName": task_name},
+        "$setOnInsert": {"tags": []},
+    };
+    coll.update_one(doc!{"output": output, "source": script.url.as_str()}, update).upsert(true).await?;
+    Ok(())
+}
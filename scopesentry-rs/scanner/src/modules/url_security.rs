@@ -0,0 +1,61 @@
+use std::collections::HashSet;
+
+use async_trait::async_trait;
+use md5::{Digest, Md5};
+use mongodb::bson::{doc, Document};
+
+use scopesentry_common::{models::DispatchTemplate, sensitive::{SensitiveMatch, SensitiveRules}, util::now_string};
+
+use super::RuleCache;
+use crate::{pipeline::{ModuleParams, Page, ScanModule, ScanState}, Ctx};
+
+/// Sensitive content in every page body fetched by earlier stages, matched
+/// against the `SensitiveRule` collection.
+///
+/// One `SensitiveResult` is kept per URL, rule and body; the body itself is
+/// stored once in `SensitiveBody` under its md5.
+#[derive(Default)]
+pub struct UrlSecurity {
+    rules: RuleCache<SensitiveRules>,
+}
+
+#[async_trait]
+impl ScanModule for UrlSecurity {
+    fn name(&self) -> &'static str { "URLSecurity" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, _params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let db = ctx.db();
+        let rules = self.rules.get(|| SensitiveRules::load(&db)).await?;
+
+        let mut seen = HashSet::new();
+        for page in &state.pages {
+            if page.body.is_empty() || !seen.insert(page.url.as_str()) { continue; }
+            let found = rules.scan(&page.body);
+            if found.is_empty() { continue; }
+            save_matches(ctx, &tmpl.TaskName, page, &found).await.ok();
+        }
+        Ok(())
+    }
+}
+
+async fn save_matches(ctx: &Ctx, task_name: &str, page: &Page, found: &[SensitiveMatch<'_>]) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let md5 = format!("{:x}", Md5::digest(page.body.as_bytes()));
+    let now = now_string();
+
+    db.collection::<Document>("SensitiveBody")
+        .update_one(doc!{"md5": &md5}, doc!{"$setOnInsert": {"md5": &md5, "body": &page.body}})
+        .upsert(true)
+        .await?;
+
+    let coll = db.collection::<Document>("SensitiveResult");
+    for m in found {
+        let filter = doc!{"url": &page.url, "sid": &m.rule.name, "md5": &md5};
+        let update = doc!{
+            "$set": {"url": &page.url, "sid": &m.rule.name, "color": &m.rule.color, "match": &m.matches, "md5": &md5, "time": &now, "taskName": task_name},
+            "$setOnInsert": {"status": 1, "tags": []},
+        };
+        coll.update_one(filter, update).upsert(true).await?;
+    }
+    Ok(())
+}
@@ -0,0 +1,283 @@
+use std::{collections::HashMap, sync::Arc, time::{Duration, Instant}};
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+use rand::{distributions::Alphanumeric, Rng};
+use reqwest::{Client, Method};
+use tokio::{sync::Semaphore, task::JoinSet};
+use url::Url;
+
+use scopesentry_common::{
+    models::DispatchTemplate,
+    poc::{Finding, HttpRequest, HttpResponse, HttpSender, Poc, PocList},
+    util::now_string,
+};
+
+use super::RuleCache;
+use crate::{http, pipeline::{ModuleParams, ScanModule, ScanState}, Ctx};
+
+/// Stored request/response text is cut to this many bytes.
+const MAX_STORED: usize = 64 * 1024;
+
+/// POCs selected by the template's `vullist` run against every web asset.
+///
+/// Templates are nuclei-style YAML from `PocList`, executed by
+/// [`scopesentry_common::poc`]; each POC reports at most one finding per
+/// asset. Args: `-t <n>` concurrent POC runs (10), `-timeout <s>` (10),
+/// `-severity <list>` only run POCs of these severities.
+#[derive(Default)]
+pub struct VulnerabilityScan {
+    pocs: RuleCache<PocList>,
+}
+
+/// Sends POC requests with the scanner's client; redirects are followed here
+/// so each template decides whether it wants them.
+struct Sender {
+    client: Client,
+}
+
+#[async_trait]
+impl HttpSender for Sender {
+    async fn send(&self, req: &HttpRequest, max_redirects: usize) -> anyhow::Result<HttpResponse> {
+        let started = Instant::now();
+        let method = Method::from_bytes(req.method.as_bytes())?;
+        let mut url = Url::parse(&req.url)?;
+        let mut redirects = 0;
+        loop {
+            let mut builder = self.client.request(method.clone(), url.clone());
+            for (k, v) in &req.headers {
+                builder = builder.header(k.as_str(), v.as_str());
+            }
+            if !req.body.is_empty() {
+                builder = builder.body(req.body.clone());
+            }
+            let resp = builder.send().await?;
+            let location = resp.headers().get(reqwest::header::LOCATION).and_then(|v| v.to_str().ok()).map(|v| v.to_string());
+            if let (true, Some(loc)) = (resp.status().is_redirection() && redirects < max_redirects, location) {
+                if let Ok(next) = url.join(&loc) {
+                    url = next;
+                    redirects += 1;
+                    continue;
+                }
+            }
+
+            let status = resp.status().as_u16();
+            let headers = resp.headers().iter()
+                .map(|(k, v)| (k.to_string(), String::from_utf8_lossy(v.as_bytes()).into_owned()))
+                .collect();
+            let body = http::read_body(resp, http::MAX_BODY).await;
+            return Ok(HttpResponse {
+                status,
+                headers,
+                body: String::from_utf8_lossy(&body).into_owned(),
+                duration: started.elapsed().as_secs_f64(),
+            });
+        }
+    }
+}
+
+#[async_trait]
+impl ScanModule for VulnerabilityScan {
+    fn name(&self) -> &'static str { "VulnerabilityScan" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        if tmpl.vullist.is_empty() { return Ok(()); }
+        let db = ctx.db();
+        let pocs = self.pocs.get(|| PocList::load(&db)).await?;
+
+        let severities: Vec<String> = params.flag("severity").unwrap_or_default()
+            .split(',')
+            .map(|s| s.trim().to_lowercase())
+            .filter(|s| !s.is_empty())
+            .collect();
+        let selected: Vec<usize> = pocs.select(&tmpl.vullist).into_iter()
+            .filter(|p| severities.is_empty() || severities.contains(&p.severity))
+            .filter_map(|p| pocs.pocs.iter().position(|x| x.id == p.id))
+            .collect();
+        if selected.is_empty() { return Ok(()); }
+
+        let mut targets: Vec<Url> = vec![];
+        for asset in &state.assets {
+            let Ok(url) = Url::parse(&asset.url) else { continue; };
+            if !targets.contains(&url) { targets.push(url); }
+        }
+
+        let limit = Arc::new(Semaphore::new(params.concurrency("t", 10)));
+        let sender = Arc::new(Sender { client: http::client(Duration::from_secs(params.flag_or("timeout", 10)))? });
+        // one random token per run, for templates that use `{{randstr}}`
+        let randstr: String = rand::thread_rng().sample_iter(&Alphanumeric).take(16).map(char::from).collect();
+        let extra = Arc::new(HashMap::from([("randstr".to_string(), randstr)]));
+
+        let mut set = JoinSet::new();
+        for target in &targets {
+            for &idx in &selected {
+                let permit = limit.clone().acquire_owned().await?;
+                let (pocs, sender, extra, target) = (pocs.clone(), sender.clone(), extra.clone(), target.clone());
+                set.spawn(async move {
+                    let _permit = permit;
+                    let finding = pocs.pocs[idx].run(sender.as_ref(), &target, &extra).await;
+                    finding.map(|f| (idx, target, f))
+                });
+            }
+        }
+
+        while let Some(res) = set.join_next().await {
+            let Ok(Some((idx, target, finding))) = res else { continue; };
+            save_finding(ctx, &tmpl.TaskName, &pocs.pocs[idx], &target, &finding).await.ok();
+        }
+        Ok(())
+    }
+}
+
+fn truncate(s: &str) -> &str {
+    if s.len() <= MAX_STORED { return s; }
+    let mut end = MAX_STORED;
+    while !s.is_char_boundary(end) { end -= 1; }
+    &s[..end]
+}
+
+async fn save_finding(ctx: &Ctx, task_name: &str, poc: &Poc, target: &Url, f: &Finding) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("vulnerability");
+    let vulnid = poc.id.to_hex();
+    let filter = doc!{"url": target.as_str(), "vulnid": &vulnid, "matched": &f.matched};
+    let update = doc!{
+        "$set": {
+            "url": target.as_str(), "vulnid": &vulnid, "vulname": &poc.name, "matched": &f.matched, "level": &poc.severity,
+            "request": truncate(&f.request), "response": truncate(&f.response), "extracted": &f.extracted,
+            "time": now_string(), "taskName": task_name,
+        },
+        "$setOnInsert": {"status": 1, "tags": []},
+    };
+    coll.update_one(filter, update).upsert(true).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use mongodb::bson::oid::ObjectId;
+    use scopesentry_common::poc::Poc;
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpListener};
+
+    use super::*;
+
+    /// A one-request-per-connection HTTP server for the routes below.
+    async fn serve() -> Url {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else { return };
+                tokio::spawn(async move {
+                    let mut buf = Vec::new();
+                    let mut chunk = [0u8; 1024];
+                    let head_end = loop {
+                        let n = stream.read(&mut chunk).await.unwrap_or(0);
+                        if n == 0 {
+                            return;
+                        }
+                        buf.extend_from_slice(&chunk[..n]);
+                        if let Some(i) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                            break i;
+                        }
+                    };
+                    let head = String::from_utf8_lossy(&buf[..head_end]).to_lowercase();
+                    let request_line = head.lines().next().unwrap_or_default().to_string();
+                    let token = head.lines().find_map(|l| l.strip_prefix("x-token: ")).unwrap_or_default().to_string();
+                    let response = match request_line.split(' ').take(2).collect::<Vec<_>>()[..] {
+                        ["get", "/login"] => "HTTP/1.1 302 Found\r\nLocation: /home\r\n\r\n".to_string(),
+                        ["get", "/home"] => "HTTP/1.1 200 OK\r\nServer: demo/2.4.1\r\n\r\nWelcome, admin".to_string(),
+                        ["post", "/api/session"] if token == "s3cr3t" => "HTTP/1.1 200 OK\r\n\r\n{\"role\":\"admin\"}".to_string(),
+                        ["get", "/api/token"] => "HTTP/1.1 200 OK\r\n\r\n{\"token\":\"s3cr3t\"}".to_string(),
+                        _ => "HTTP/1.1 404 Not Found\r\n\r\nnot found".to_string(),
+                    };
+                    let (head, body) = response.split_once("\r\n\r\n").unwrap();
+                    let response = format!("{head}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}", body.len());
+                    stream.write_all(response.as_bytes()).await.ok();
+                });
+            }
+        });
+        Url::parse(&format!("http://{addr}/")).unwrap()
+    }
+
+    fn sender() -> Sender {
+        Sender { client: http::client(Duration::from_secs(5)).unwrap() }
+    }
+
+    #[tokio::test]
+    async fn follows_redirects_only_when_asked() {
+        let target = serve().await;
+        let template = |redirects: bool| format!(r#"
+id: demo-panel
+info:
+  name: Demo Panel
+  severity: low
+http:
+  - method: GET
+    path:
+      - "{{{{RootURL}}}}/login"
+    redirects: {redirects}
+    matchers-condition: and
+    matchers:
+      - type: status
+        status: [200]
+      - type: word
+        part: body
+        words: ["Welcome"]
+      - type: word
+        part: header
+        words: ["Welcome"]
+        negative: true
+    extractors:
+      - type: regex
+        part: header
+        group: 1
+        regex: ['demo/([0-9.]+)']
+"#);
+        let poc = Poc::parse(ObjectId::new(), &template(true)).unwrap();
+        let finding = poc.run(&sender(), &target, &HashMap::new()).await.unwrap();
+        assert_eq!(finding.matched, format!("{target}login"));
+        assert_eq!(finding.extracted, ["2.4.1"]);
+        assert!(finding.response.starts_with("HTTP/1.1 200"));
+
+        // without `redirects` the 302 itself is the response
+        let poc = Poc::parse(ObjectId::new(), &template(false)).unwrap();
+        assert!(poc.run(&sender(), &target, &HashMap::new()).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn extracted_values_reach_the_next_request() {
+        let target = serve().await;
+        let poc = Poc::parse(ObjectId::new(), r#"
+id: token-reuse
+info:
+  name: Token Reuse
+  severity: high
+http:
+  - raw:
+      - |
+        GET /api/token HTTP/1.1
+        Host: {{Hostname}}
+
+      - |
+        POST /api/session HTTP/1.1
+        Host: {{Hostname}}
+        X-Token: {{token}}
+
+        {}
+    extractors:
+      - type: regex
+        name: token
+        internal: true
+        group: 1
+        regex: ['"token":"([^"]+)"']
+    matchers:
+      - type: dsl
+        dsl:
+          - 'status_code == 200 && contains(body, "admin")'
+"#).unwrap();
+        let finding = poc.run(&sender(), &target, &HashMap::new()).await.unwrap();
+        assert!(finding.request.starts_with("POST /api/session HTTP/1.1"));
+        assert!(finding.request.contains("X-Token: s3cr3t"));
+    }
+}
@@ -0,0 +1,142 @@
+use std::{collections::HashSet, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use mongodb::bson::{doc, Document};
+use tokio::{sync::Semaphore, task::JoinSet};
+use url::Url;
+
+use scopesentry_common::{models::DispatchTemplate, util::now_string};
+
+use crate::{extract, http, pipeline::{ModuleParams, Page, ScanModule, ScanState}, scope::Scope, Ctx};
+
+/// Breadth-first crawl of the web assets found by asset mapping.
+///
+/// Only hosts under the same registrable domain as an asset are followed, and
+/// the task's ignore rules apply. Links, form actions and endpoints in inline
+/// scripts are extracted from every HTML page; scripts and static files are
+/// recorded but not crawled. Args: `-depth <n>` link depth (3), `-max <n>`
+/// pages per target (500), `-t <n>` concurrent requests (10), `-timeout <s>`.
+pub struct WebCrawler;
+
+/// A URL waiting to be fetched: the asset it was reached from and the page
+/// that linked to it.
+#[derive(Debug, Clone)]
+struct Queued {
+    url: Url,
+    input: String,
+    source: String,
+}
+
+#[async_trait]
+impl ScanModule for WebCrawler {
+    fn name(&self) -> &'static str { "WebCrawler" }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()> {
+        let max_depth = params.flag_or("depth", 3usize);
+        let max_pages = params.flag_or("max", 500usize);
+        let limit = Arc::new(Semaphore::new(params.concurrency("t", 10)));
+        let client = http::client(Duration::from_secs(params.flag_or("timeout", 5)))?;
+
+        let seeds: Vec<Url> = state.assets.iter().filter_map(|a| Url::parse(&a.url).ok()).collect();
+        let scope = Arc::new(Scope::new(seeds.iter().filter_map(|u| u.host_str()), &tmpl.ignore).await);
+
+        let mut seen: HashSet<String> = HashSet::new();
+        let mut frontier: Vec<Queued> = vec![];
+        for url in seeds {
+            if seen.insert(crawl_key(&url)) {
+                frontier.push(Queued { input: url.to_string(), source: url.to_string(), url });
+            }
+        }
+
+        let mut fetched = 0;
+        for depth in 0..=max_depth {
+            if frontier.is_empty() || fetched >= max_pages { break; }
+            frontier.truncate(max_pages - fetched);
+            fetched += frontier.len();
+
+            let mut set = JoinSet::new();
+            for q in frontier.drain(..) {
+                let permit = limit.clone().acquire_owned().await?;
+                let (client, scope) = (client.clone(), scope.clone());
+                set.spawn(async move {
+                    let _permit = permit;
+                    let resp = http::fetch_in(&client, q.url.as_str(), &scope).await.ok();
+                    (q, resp)
+                });
+            }
+
+            let mut next = vec![];
+            while let Some(res) = set.join_next().await {
+                let Ok((q, Some(resp))) = res else { continue; };
+                let body = resp.text();
+                save_url(ctx, &tmpl.TaskName, &scope, &q, resp.status.as_u16(), resp.body.len() as i64).await.ok();
+                save_request(ctx, &tmpl.TaskName, "GET", q.url.as_str(), "").await.ok();
+
+                let html = resp.header("content-type").map(|c| c.contains("html")).unwrap_or_else(|| body.trim_start().starts_with('<'));
+                if html {
+                    let page = q.url.to_string();
+                    let mut found = extract::links(&resp.url, &body);
+                    for script in extract::inline_scripts(&body) {
+                        found.extend(extract::js_endpoints(script).iter().filter_map(|e| extract::resolve(&resp.url, e)));
+                    }
+                    for form in extract::forms(&resp.url, &body) {
+                        if !scope.contains(&form.action) { continue; }
+                        save_request(ctx, &tmpl.TaskName, &form.method, form.action.as_str(), &form.body()).await.ok();
+                        found.push(form.action);
+                    }
+
+                    for link in found {
+                        if !scope.contains(&link) { continue; }
+                        let s = link.to_string();
+                        if !state.links.contains(&s) { state.links.push(s); }
+                        if extract::is_static(&link) || extract::is_script(&link) { continue; }
+                        if depth < max_depth && seen.insert(crawl_key(&link)) {
+                            next.push(Queued { url: link, input: q.input.clone(), source: page.clone() });
+                        }
+                    }
+                }
+                state.pages.push(Page { url: q.url.to_string(), body });
+            }
+            frontier = next;
+        }
+        Ok(())
+    }
+}
+
+/// URLs that differ only in query values are crawled once.
+fn crawl_key(url: &Url) -> String {
+    let mut names: Vec<String> = url.query_pairs().map(|(k, _)| k.into_owned()).collect();
+    names.sort_unstable();
+    names.dedup();
+    let mut key = url.clone();
+    key.set_query(None);
+    format!("{}?{}", key, names.join("&"))
+}
+
+async fn save_url(ctx: &Ctx, task_name: &str, scope: &Scope, q: &Queued, status: u16, length: i64) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("UrlScan");
+    let output = q.url.as_str();
+    let root = q.url.host_str().map(|h| scope.root_domain(h)).unwrap_or_default();
+    let update = doc!{
+        "$set": {
+            "input": &q.input, "source": &q.source, "outputtype": "crawl", "output": output,
+            "status": status as i32, "length": length, "rootDomain": root, "time": now_string(), "taskName": task_name,
+        },
+        "$setOnInsert": {"tags": []},
+    };
+    coll.update_one(doc!{"output": output}, update).upsert(true).await?;
+    Ok(())
+}
+
+async fn save_request(ctx: &Ctx, task_name: &str, method: &str, url: &str, body: &str) -> anyhow::Result<()> {
+    let db = ctx.db();
+    let coll = db.collection::<Document>("crawler");
+    let filter = doc!{"url": url, "method": method, "body": body};
+    let update = doc!{
+        "$set": {"url": url, "method": method, "body": body, "time": now_string(), "taskName": task_name},
+        "$setOnInsert": {"tags": []},
+    };
+    coll.update_one(filter, update).upsert(true).await?;
+    Ok(())
+}
@@ -0,0 +1,430 @@
+use std::{fmt, sync::Arc, time::{Duration, Instant}};
+
+use redis::AsyncCommands;
+use tokio::{sync::Mutex, task::JoinHandle};
+
+use scopesentry_common::{models::{NodeCommand, NodeLimits, NodeState}, mongo, rds, settings::AppConfig, util::now_string};
+
+use crate::telemetry::{Sampler, SystemLoad};
+
+/// How often the node refreshes `updateTime`; the scheduler takes a node
+/// whose heartbeat is much older than this for dead.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Tasks a node runs at once; templates are handled one after another.
+const MAX_TASKS: usize = 1;
+
+/// This node's `node:{name}` hash and the [`NodeState`] it advertises, with
+/// the load figures each heartbeat reports and the settings the control
+/// channel changes at runtime.
+pub struct Node {
+    name: String,
+    state: std::sync::Mutex<NodeState>,
+    activity: std::sync::Mutex<Activity>,
+    stop: std::sync::Mutex<Option<Stop>>,
+    limits: std::sync::Mutex<NodeLimits>,
+    /// `RESOLVERS` as given, re-read on [`NodeCommand::ReloadConfig`]
+    resolver_spec: Option<String>,
+    resolvers: std::sync::RwLock<Arc<Vec<String>>>,
+    backend: std::sync::RwLock<Arc<Backend>>,
+    started: Instant,
+    con: Mutex<redis::aio::MultiplexedConnection>,
+}
+
+/// The config file and the MongoDB client built from it, replaced together
+/// on [`NodeCommand::ReloadConfig`].
+#[derive(Debug)]
+pub struct Backend {
+    pub cfg: Arc<AppConfig>,
+    pub mongo: mongodb::Client,
+}
+
+impl Backend {
+    pub async fn connect(cfg: AppConfig) -> anyhow::Result<Backend> {
+        let mongo = mongo::connect_mongo(&cfg).await?;
+        Ok(Backend { cfg: Arc::new(cfg), mongo })
+    }
+}
+
+/// Why the node is stopping; the worker winds down the same way for each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stop {
+    /// SIGTERM or Ctrl-C
+    Shutdown,
+    /// [`NodeCommand::Restart`]: start again in place
+    Restart,
+    /// [`NodeCommand::Uninstall`]: deregister and exit
+    Uninstall,
+}
+
+/// What the node is working on.
+#[derive(Debug, Clone, Default)]
+struct Activity {
+    running: usize,
+    finished: u64,
+    target: Option<String>,
+    module: Option<String>,
+}
+
+impl fmt::Debug for Node {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Node").field("name", &self.name).field("state", &self.state()).finish()
+    }
+}
+
+impl Node {
+    /// Register the node as online.
+    pub async fn register(backend: Backend, name: &str) -> anyhow::Result<Arc<Node>> {
+        let resolver_spec = std::env::var("RESOLVERS").ok();
+        let node = Arc::new(Node {
+            name: name.to_string(),
+            state: std::sync::Mutex::new(NodeState::Offline),
+            activity: std::sync::Mutex::new(Activity::default()),
+            stop: std::sync::Mutex::new(None),
+            limits: std::sync::Mutex::new(NodeLimits::default()),
+            resolvers: std::sync::RwLock::new(Arc::new(node_resolvers(resolver_spec.as_deref()))),
+            resolver_spec,
+            con: Mutex::new(rds::connect_redis(&backend.cfg).await?),
+            backend: std::sync::RwLock::new(Arc::new(backend)),
+            started: Instant::now(),
+        });
+        let _: () = node.con.lock().await.hset(node.key(), "name", name).await?;
+        node.set(NodeState::Online).await?;
+        Ok(node)
+    }
+
+    fn key(&self) -> String {
+        format!("node:{}", self.name)
+    }
+
+    pub fn state(&self) -> NodeState {
+        *self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn activity(&self) -> std::sync::MutexGuard<'_, Activity> {
+        self.activity.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    pub fn task_started(&self) {
+        self.activity().running += 1;
+    }
+
+    pub fn task_finished(&self) {
+        let mut a = self.activity();
+        a.running = a.running.saturating_sub(1);
+        a.finished += 1;
+        a.target = None;
+        a.module = None;
+    }
+
+    /// The target being scanned, `None` between targets.
+    pub fn set_target(&self, target: Option<&str>) {
+        let mut a = self.activity();
+        a.target = target.map(str::to_string);
+        a.module = None;
+    }
+
+    /// The scan module running on the current target.
+    pub fn set_module(&self, module: Option<&str>) {
+        self.activity().module = module.map(str::to_string);
+    }
+
+    /// Heartbeat fields besides the state: the Python UI's `running`,
+    /// `finished`, `maxTaskNum`, `cpuNum` and `memNum`, plus `loadAvg`,
+    /// `target`, `module`, `version` and `uptime` (seconds).
+    fn telemetry(&self, load: &SystemLoad) -> Vec<(&'static str, String)> {
+        let a = self.activity().clone();
+        let mut fields = vec![
+            ("running", a.running.to_string()),
+            ("finished", a.finished.to_string()),
+            ("maxTaskNum", MAX_TASKS.to_string()),
+            ("target", a.target.unwrap_or_default()),
+            ("module", a.module.unwrap_or_default()),
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("uptime", self.started.elapsed().as_secs().to_string()),
+        ];
+        if let Some(cpu) = load.cpu { fields.push(("cpuNum", format!("{:.2}", cpu))); }
+        if let Some(mem) = load.mem { fields.push(("memNum", format!("{:.2}", mem))); }
+        if let Some(l) = &load.load_avg { fields.push(("loadAvg", l.clone())); }
+        fields
+    }
+
+    /// Whether the node is winding down or paused and should not claim more
+    /// work.
+    pub fn draining(&self) -> bool {
+        matches!(self.state(), NodeState::Draining | NodeState::Offline)
+    }
+
+    /// Why the node is stopping, `None` while it keeps running (paused or not).
+    pub fn stopping(&self) -> Option<Stop> {
+        *self.stop.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Stop after the target in progress. A later reason replaces an earlier
+    /// one, so SIGTERM during a pending restart shuts the node down.
+    pub async fn request_stop(&self, why: Stop) {
+        *self.stop.lock().unwrap_or_else(|e| e.into_inner()) = Some(why);
+        self.set(NodeState::Draining).await.ok();
+    }
+
+    /// Wait out a pause. Returns false if the node is told to stop instead.
+    pub async fn wait_resumed(&self) -> bool {
+        loop {
+            if self.stopping().is_some() { return false; }
+            if !self.draining() { return true; }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+
+    pub fn limits(&self) -> NodeLimits {
+        *self.limits.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// The config and MongoDB client as of the last reload. Redis connections
+    /// made from an older config should be replaced once this changes.
+    pub fn backend(&self) -> Arc<Backend> {
+        self.backend.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// This node's DNS resolvers (`RESOLVERS`), used when a template names none.
+    pub fn resolvers(&self) -> Arc<Vec<String>> {
+        self.resolvers.read().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+
+    /// Delete the `node:{name}` hash, so the node no longer shows up.
+    pub async fn deregister(&self) -> redis::RedisResult<()> {
+        self.con.lock().await.del(self.key()).await
+    }
+
+    /// Move to `next` and publish it, unless the transition is not allowed
+    /// (a draining node does not become busy again, for one). Returns whether
+    /// the state changed.
+    pub async fn set(&self, next: NodeState) -> anyhow::Result<bool> {
+        self.transition(next, |_| true).await
+    }
+
+    /// Like [`Node::set`], but only while the node is in `current`; finishing
+    /// a task this way leaves a pause that came in meanwhile alone.
+    pub async fn set_if(&self, current: NodeState, next: NodeState) -> anyhow::Result<bool> {
+        self.transition(next, |state| state == current).await
+    }
+
+    async fn transition(&self, next: NodeState, when: impl Fn(NodeState) -> bool) -> anyhow::Result<bool> {
+        // the connection lock orders this write against the heartbeat's
+        let mut con = self.con.lock().await;
+        {
+            let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+            if !when(*state) || !state.can_become(next) { return Ok(false); }
+            *state = next;
+        }
+        write_state(&mut con, &self.key(), next).await?;
+        Ok(true)
+    }
+
+    /// Refresh `updateTime` and the telemetry and re-assert the current
+    /// state until the node goes offline, so a node the scheduler wrongly
+    /// timed out comes back.
+    pub fn spawn_heartbeat(self: &Arc<Self>) -> JoinHandle<()> {
+        let node = self.clone();
+        tokio::spawn(async move {
+            let mut sampler = Sampler::default();
+            loop {
+                let load = sampler.sample();
+                {
+                    let mut con = node.con.lock().await;
+                    let state = node.state();
+                    if state == NodeState::Offline { break; }
+                    let res: redis::RedisResult<()> = async {
+                        write_state(&mut con, &node.key(), state).await?;
+                        con.hset_multiple(node.key(), &node.telemetry(&load)).await
+                    }.await;
+                    if let Err(e) = res {
+                        tracing::warn!("heartbeat failed: {}", e);
+                    }
+                }
+                tokio::time::sleep(HEARTBEAT_INTERVAL).await;
+            }
+        })
+    }
+}
+
+impl Node {
+    /// Consume `NodeControl:{name}` until the node goes offline.
+    pub fn spawn_control(self: &Arc<Self>) -> JoinHandle<()> {
+        let node = self.clone();
+        tokio::spawn(async move {
+            let key = format!("NodeControl:{}", node.name);
+            let mut con = None;
+            let mut backend = node.backend();
+            while node.state() != NodeState::Offline {
+                // reconnect after a config reload
+                if !Arc::ptr_eq(&backend, &node.backend()) {
+                    backend = node.backend();
+                    con = None;
+                }
+                let c = match con.as_mut() {
+                    Some(c) => c,
+                    None => match rds::connect_redis(&backend.cfg).await {
+                        Ok(c) => con.insert(c),
+                        Err(e) => {
+                            tracing::warn!("control channel unavailable: {}", e);
+                            tokio::time::sleep(Duration::from_secs(5)).await;
+                            continue;
+                        }
+                    },
+                };
+                let popped: Option<(String, String)> = match c.blpop(&key, 5.0).await {
+                    Ok(popped) => popped,
+                    Err(e) => {
+                        tracing::warn!("{} read failed: {}", key, e);
+                        con = None;
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                        continue;
+                    }
+                };
+                let Some((_, payload)) = popped else { continue; };
+                match serde_json::from_str::<NodeCommand>(&payload) {
+                    Ok(cmd) => node.apply(cmd).await,
+                    Err(e) => tracing::warn!("invalid node command {}: {}", payload, e),
+                }
+            }
+        })
+    }
+
+    async fn apply(&self, cmd: NodeCommand) {
+        tracing::info!("node command: {:?}", cmd);
+        match cmd {
+            NodeCommand::Pause => { self.set(NodeState::Draining).await.ok(); }
+            NodeCommand::Resume => {
+                if self.stopping().is_none() { self.set(NodeState::Online).await.ok(); }
+            }
+            NodeCommand::Restart => self.request_stop(Stop::Restart).await,
+            NodeCommand::Uninstall => self.request_stop(Stop::Uninstall).await,
+            NodeCommand::SetLimits(limits) => {
+                *self.limits.lock().unwrap_or_else(|e| e.into_inner()) = limits;
+            }
+            NodeCommand::ReloadConfig => {
+                if let Err(e) = self.reload_backend().await {
+                    tracing::warn!("config reload failed, keeping the current config: {}", e);
+                }
+                let resolvers = node_resolvers(self.resolver_spec.as_deref());
+                *self.resolvers.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(resolvers);
+                crate::modules::invalidate_caches();
+            }
+        }
+    }
+
+    /// Re-read the config file and reconnect to MongoDB and Redis with it.
+    /// Nothing changes unless the file parses and Redis answers.
+    async fn reload_backend(&self) -> anyhow::Result<()> {
+        let backend = Backend::connect(AppConfig::load()?).await?;
+        let con = rds::connect_redis(&backend.cfg).await?;
+        *self.con.lock().await = con;
+        *self.backend.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(backend);
+        Ok(())
+    }
+}
+
+/// Resolvers from `RESOLVERS`: a comma separated list, or the path of a file
+/// with one resolver per line.
+fn node_resolvers(spec: Option<&str>) -> Vec<String> {
+    let Some(spec) = spec.map(str::trim).filter(|s| !s.is_empty()) else { return vec![]; };
+    let text = match std::fs::read_to_string(spec) {
+        Ok(text) => text,
+        Err(_) => spec.replace(',', "\n"),
+    };
+    text.lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.starts_with('#'))
+        .map(str::to_string)
+        .collect()
+}
+
+async fn write_state(con: &mut redis::aio::MultiplexedConnection, key: &str, state: NodeState) -> redis::RedisResult<()> {
+    let fields = [("state", state.code().to_string()), ("status", state.as_str().to_string()), ("updateTime", now_string())];
+    con.hset_multiple(key, &fields).await
+}
+
+/// Resolves on SIGTERM or Ctrl-C.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut term = match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(term) => term,
+            Err(_) => {
+                let _ = tokio::signal::ctrl_c().await;
+                return;
+            }
+        };
+        tokio::select! {
+            _ = term.recv() => {}
+            _ = tokio::signal::ctrl_c() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+#[cfg(test)]
+mod tests {
+    //! These run against the Redis server at `REDIS_URL` and pass without
+    //! checking anything when it is unset: `REDIS_URL=redis://127.0.0.1:6379 cargo test`.
+    use super::*;
+
+    /// A node named after `test` on `REDIS_URL`, or `None` when the variable is unset.
+    async fn node(test: &str) -> Option<Node> {
+        let url = std::env::var("REDIS_URL").ok()?;
+        let con = redis::Client::open(url).unwrap().get_multiplexed_tokio_connection().await.unwrap();
+        let cfg: AppConfig = serde_json::from_value(serde_json::json!({
+            "system": {"timezone": null},
+            "mongodb": {"ip": "127.0.0.1", "port": 27017, "mongodb_database": "test", "username": "", "password": ""},
+            "redis": {"ip": "127.0.0.1", "port": 6379, "password": ""},
+            "logs": null,
+        })).unwrap();
+        let mongo = mongodb::Client::with_uri_str("mongodb://127.0.0.1:27017").await.unwrap();
+        Some(Node {
+            name: format!("test-node-{}-{}", std::process::id(), test),
+            state: std::sync::Mutex::new(NodeState::Online),
+            activity: std::sync::Mutex::new(Activity::default()),
+            stop: std::sync::Mutex::new(None),
+            limits: std::sync::Mutex::new(NodeLimits::default()),
+            resolver_spec: None,
+            resolvers: std::sync::RwLock::new(Arc::new(vec![])),
+            backend: std::sync::RwLock::new(Arc::new(Backend { cfg: Arc::new(cfg), mongo })),
+            started: Instant::now(),
+            con: Mutex::new(con),
+        })
+    }
+
+    async fn published(node: &Node) -> String {
+        node.con.lock().await.hget(node.key(), "status").await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn finishing_a_task_keeps_a_pause() {
+        let Some(node) = node("pause").await else { return };
+        assert!(node.set(NodeState::Busy).await.unwrap());
+        // paused while busy
+        node.apply(NodeCommand::Pause).await;
+        assert!(!node.set_if(NodeState::Busy, NodeState::Online).await.unwrap());
+        assert_eq!(node.state(), NodeState::Draining);
+        assert_eq!(published(&node).await, NodeState::Draining.as_str());
+
+        node.apply(NodeCommand::Resume).await;
+        assert!(node.set(NodeState::Busy).await.unwrap());
+        assert!(node.set_if(NodeState::Busy, NodeState::Online).await.unwrap());
+        assert_eq!(node.state(), NodeState::Online);
+        assert_eq!(published(&node).await, NodeState::Online.as_str());
+        node.deregister().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn finishing_a_task_keeps_a_stop() {
+        let Some(node) = node("stop").await else { return };
+        node.set(NodeState::Busy).await.unwrap();
+        node.request_stop(Stop::Restart).await;
+        assert!(!node.set_if(NodeState::Busy, NodeState::Online).await.unwrap());
+        assert_eq!(node.state(), NodeState::Draining);
+        node.deregister().await.unwrap();
+    }
+}
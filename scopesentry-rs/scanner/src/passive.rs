@@ -0,0 +1,634 @@
+use std::{net::SocketAddr, path::PathBuf, time::Duration};
+
+use async_trait::async_trait;
+use reqwest::Client;
+use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::TcpStream, task::JoinSet};
+use trust_dns_resolver::{
+    proto::{op::{Message, MessageType, Query, ResponseCode}, rr::{Name, RData, RecordType}},
+    TokioAsyncResolver,
+};
+
+use crate::{dns, extract, http, pipeline::ModuleParams};
+
+/// Responses of HTTP providers are read up to this size; CT logs of large
+/// domains run to several megabytes.
+const MAX_RESPONSE: usize = 32 * 1024 * 1024;
+/// Budget for one zone transfer attempt, connect to last message.
+const AXFR_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A passive source of subdomain names.
+#[async_trait]
+pub trait SubdomainSource: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Names the source knows under `domain`. Sources may return unrelated
+    /// names too; [`enumerate`] keeps only subdomains of `domain`.
+    async fn subdomains(&self, domain: &str) -> anyhow::Result<Vec<String>>;
+}
+
+/// Hostnames in free-form text (JSON, CSV, URL lists, ...). Escaped
+/// newlines, as in crt.sh's `name_value`, separate names too.
+fn names_in(text: &str) -> Vec<String> {
+    extract::hostnames(&text.replace("\\n", "\n"))
+}
+
+/// Certificate transparency dump on disk, e.g. a crt.sh JSON export or a
+/// list of certificate names.
+pub struct CtDump {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl SubdomainSource for CtDump {
+    fn name(&self) -> &'static str { "ct-dump" }
+
+    async fn subdomains(&self, _domain: &str) -> anyhow::Result<Vec<String>> {
+        Ok(names_in(&tokio::fs::read_to_string(&self.path).await?))
+    }
+}
+
+/// JSON lines exported by other tools: subfinder/dnsx (`host`), amass
+/// (`name`) or any object with a `subdomain` field. Lines that are not JSON
+/// are read as plain names.
+pub struct JsonLines {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl SubdomainSource for JsonLines {
+    fn name(&self) -> &'static str { "jsonl" }
+
+    async fn subdomains(&self, _domain: &str) -> anyhow::Result<Vec<String>> {
+        let text = tokio::fs::read_to_string(&self.path).await?;
+        let mut out = vec![];
+        for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+            match serde_json::from_str::<serde_json::Value>(line) {
+                Ok(v) => {
+                    let field = ["host", "name", "subdomain"].iter().find_map(|k| v.get(*k).and_then(|x| x.as_str()));
+                    match field {
+                        Some(name) => out.push(name.to_string()),
+                        None => out.extend(names_in(line)),
+                    }
+                }
+                Err(_) => out.extend(names_in(line)),
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// Zone file on disk in master file format. Owner names are collected along
+/// with the targets of CNAME, NS, MX, SRV and PTR records.
+pub struct ZoneFile {
+    pub path: PathBuf,
+}
+
+#[async_trait]
+impl SubdomainSource for ZoneFile {
+    fn name(&self) -> &'static str { "zone-file" }
+
+    async fn subdomains(&self, domain: &str) -> anyhow::Result<Vec<String>> {
+        Ok(zone_names(&tokio::fs::read_to_string(&self.path).await?, domain))
+    }
+}
+
+fn zone_names(text: &str, domain: &str) -> Vec<String> {
+    fn absolute(name: &str, origin: &str) -> String {
+        match name {
+            "@" => origin.to_string(),
+            n if n.ends_with('.') => n.to_string(),
+            n => format!("{}.{}", n, origin),
+        }
+    }
+
+    let mut origin = dns::fqdn(domain);
+    let mut owner = origin.clone();
+    let mut names = vec![];
+    for raw in text.lines() {
+        let line = raw.split(';').next().unwrap_or_default();
+        if line.trim().is_empty() { continue; }
+        if let Some(rest) = line.trim_start().strip_prefix("$ORIGIN") {
+            origin = absolute(rest.trim(), &origin);
+            continue;
+        }
+        if line.starts_with('$') { continue; }
+
+        let mut tokens = line.split_whitespace();
+        // a line starting with blanks continues the previous owner
+        if !line.starts_with(char::is_whitespace) {
+            if let Some(name) = tokens.next() { owner = absolute(name, &origin); }
+        }
+        names.push(owner.clone());
+        let rest: Vec<&str> = tokens.collect();
+        let has_target = rest.iter().any(|t| matches!(t.to_ascii_uppercase().as_str(), "CNAME" | "NS" | "MX" | "SRV" | "PTR"));
+        if let (true, Some(target)) = (has_target, rest.last()) {
+            names.push(absolute(target, &origin));
+        }
+    }
+    names
+}
+
+/// Zone transfer (AXFR) attempts against each of the domain's nameservers;
+/// the first one that allows it answers for the whole zone.
+pub struct Axfr {
+    pub resolver: TokioAsyncResolver,
+}
+
+#[async_trait]
+impl SubdomainSource for Axfr {
+    fn name(&self) -> &'static str { "axfr" }
+
+    async fn subdomains(&self, domain: &str) -> anyhow::Result<Vec<String>> {
+        let ns = self.resolver.lookup(dns::fqdn(domain), RecordType::NS).await?;
+        let servers: Vec<String> = ns.record_iter()
+            .filter_map(|r| match r.data() {
+                Some(RData::NS(name)) => Some(name.to_utf8()),
+                _ => None,
+            })
+            .collect();
+        for server in servers {
+            let Ok(ips) = self.resolver.lookup_ip(server.as_str()).await else { continue; };
+            for ip in ips.iter() {
+                match tokio::time::timeout(AXFR_TIMEOUT, transfer(SocketAddr::new(ip, 53), domain)).await {
+                    Ok(Ok(names)) if !names.is_empty() => {
+                        tracing::warn!("{} allows zone transfer of {}", server, domain);
+                        return Ok(names);
+                    }
+                    Ok(Err(e)) => tracing::debug!("axfr {} from {} ({}): {}", domain, server, ip, e),
+                    _ => {}
+                }
+            }
+        }
+        Ok(vec![])
+    }
+}
+
+/// Run one AXFR over TCP and collect the names in it. The zone ends with
+/// its second SOA record. Messages must answer our query: same ID and, where
+/// the server repeats it, the same question.
+async fn transfer(server: SocketAddr, domain: &str) -> anyhow::Result<Vec<String>> {
+    let mut query = Message::new();
+    query.set_id(rand::random()).add_query(Query::query(Name::from_ascii(dns::fqdn(domain))?, RecordType::AXFR));
+    let bytes = query.to_vec()?;
+
+    let mut stream = TcpStream::connect(server).await?;
+    stream.write_all(&(bytes.len() as u16).to_be_bytes()).await?;
+    stream.write_all(&bytes).await?;
+
+    let mut names = vec![];
+    let mut soas = 0;
+    while soas < 2 {
+        let mut len = [0u8; 2];
+        stream.read_exact(&mut len).await?;
+        let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+        stream.read_exact(&mut buf).await?;
+        let msg = Message::from_vec(&buf)?;
+        if msg.id() != query.id() || msg.message_type() != MessageType::Response {
+            anyhow::bail!("unexpected message {} for query {}", msg.id(), query.id());
+        }
+        if !msg.queries().is_empty() && msg.queries() != query.queries() {
+            anyhow::bail!("answer for {:?}", msg.queries());
+        }
+        if msg.response_code() != ResponseCode::NoError {
+            anyhow::bail!("{}", msg.response_code());
+        }
+        if msg.answers().is_empty() { break; }
+        for rec in msg.answers() {
+            if rec.record_type() == RecordType::SOA { soas += 1; }
+            names.push(rec.name().to_utf8());
+            match rec.data() {
+                Some(RData::CNAME(n)) | Some(RData::NS(n)) | Some(RData::PTR(n)) => names.push(n.to_utf8()),
+                Some(RData::MX(mx)) => names.push(mx.exchange().to_utf8()),
+                Some(RData::SRV(srv)) => names.push(srv.target().to_utf8()),
+                _ => {}
+            }
+        }
+    }
+    Ok(names)
+}
+
+/// Passive DNS and CT services with an HTTP API.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    CrtSh,
+    Otx,
+    HackerTarget,
+    Anubis,
+    Wayback,
+}
+
+impl Provider {
+    pub const ALL: [Provider; 5] = [Provider::CrtSh, Provider::Otx, Provider::HackerTarget, Provider::Anubis, Provider::Wayback];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::CrtSh => "crtsh",
+            Provider::Otx => "otx",
+            Provider::HackerTarget => "hackertarget",
+            Provider::Anubis => "anubis",
+            Provider::Wayback => "wayback",
+        }
+    }
+
+    fn default_base(self) -> &'static str {
+        match self {
+            Provider::CrtSh => "https://crt.sh",
+            Provider::Otx => "https://otx.alienvault.com",
+            Provider::HackerTarget => "https://api.hackertarget.com",
+            Provider::Anubis => "https://jldc.me",
+            Provider::Wayback => "https://web.archive.org",
+        }
+    }
+
+    fn url(self, base: &str, domain: &str) -> String {
+        let base = base.trim_end_matches('/');
+        match self {
+            Provider::CrtSh => format!("{}/?q=%25.{}&output=json", base, domain),
+            Provider::Otx => format!("{}/api/v1/indicators/domain/{}/passive_dns", base, domain),
+            Provider::HackerTarget => format!("{}/hostsearch/?q={}", base, domain),
+            Provider::Anubis => format!("{}/anubis/subdomains/{}", base, domain),
+            Provider::Wayback => format!("{}/cdx/search/cdx?url=*.{}/*&output=txt&fl=original&collapse=urlkey", base, domain),
+        }
+    }
+}
+
+/// An HTTP provider queried at `base`, its public API unless overridden.
+pub struct HttpSource {
+    pub provider: Provider,
+    pub base: String,
+    pub client: Client,
+}
+
+#[async_trait]
+impl SubdomainSource for HttpSource {
+    fn name(&self) -> &'static str { self.provider.name() }
+
+    async fn subdomains(&self, domain: &str) -> anyhow::Result<Vec<String>> {
+        let resp = self.client.get(self.provider.url(&self.base, domain)).send().await?.error_for_status()?;
+        let body = http::read_body(resp, MAX_RESPONSE).await;
+        Ok(names_in(&String::from_utf8_lossy(&body)))
+    }
+}
+
+/// Sources enabled by a module's args: `-sources <list>` names HTTP
+/// providers (see [`Provider`]) and `axfr`; `-<provider>-url <base>`
+/// overrides a provider's base URL; `-ct <file>`, `-zone <file>` and
+/// `-import <file>` read local CT dumps, zone files and JSON lines.
+pub fn from_params(params: &ModuleParams<'_>, resolver: &TokioAsyncResolver, client: &Client) -> Vec<Box<dyn SubdomainSource>> {
+    let enabled: Vec<String> = params.flag("sources").unwrap_or_default()
+        .split(',')
+        .map(|s| s.trim().to_lowercase())
+        .filter(|s| !s.is_empty())
+        .collect();
+    let mut sources: Vec<Box<dyn SubdomainSource>> = vec![];
+    for provider in Provider::ALL {
+        if !enabled.iter().any(|s| s == provider.name()) { continue; }
+        let base = params.flag(&format!("{}-url", provider.name())).unwrap_or_else(|| provider.default_base().to_string());
+        sources.push(Box::new(HttpSource { provider, base, client: client.clone() }));
+    }
+    if enabled.iter().any(|s| s == "axfr") {
+        sources.push(Box::new(Axfr { resolver: resolver.clone() }));
+    }
+    if let Some(path) = params.flag("ct") { sources.push(Box::new(CtDump { path: path.into() })); }
+    if let Some(path) = params.flag("zone") { sources.push(Box::new(ZoneFile { path: path.into() })); }
+    if let Some(path) = params.flag("import") { sources.push(Box::new(JsonLines { path: path.into() })); }
+    sources
+}
+
+/// Query every source for `domain` at once and merge what they return into
+/// sorted, lower-cased subdomains of `domain` (wildcard labels dropped). A
+/// failing source is logged and skipped.
+pub async fn enumerate(sources: Vec<Box<dyn SubdomainSource>>, domain: &str) -> Vec<String> {
+    let domain = domain.trim_end_matches('.').to_lowercase();
+    let suffix = format!(".{}", domain);
+    let mut set = JoinSet::new();
+    for source in sources {
+        let domain = domain.clone();
+        set.spawn(async move { (source.name(), source.subdomains(&domain).await) });
+    }
+
+    let mut out: Vec<String> = vec![];
+    while let Some(res) = set.join_next().await {
+        let Ok((name, found)) = res else { continue; };
+        match found {
+            Ok(found) => {
+                tracing::debug!("{} returned {} names for {}", name, found.len(), domain);
+                for n in found {
+                    let n = n.trim().trim_end_matches('.').trim_start_matches("*.").to_lowercase();
+                    if n.ends_with(&suffix) { out.push(n); }
+                }
+            }
+            Err(e) => tracing::warn!("subdomain source {} failed for {}: {}", name, domain, e),
+        }
+    }
+    out.sort();
+    out.dedup();
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::{net::TcpListener, sync::mpsc};
+    use trust_dns_resolver::proto::rr::{rdata::SOA, Record};
+
+    use super::*;
+
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("scopesentry-{}-{}", std::process::id(), name));
+        std::fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn sorted(mut names: Vec<String>) -> Vec<String> {
+        names.sort();
+        names.dedup();
+        names
+    }
+
+    // ---- zone files ----
+
+    #[test]
+    fn zone_origin_and_at() {
+        let zone = "\
+$ORIGIN example.com.
+$TTL 3600
+@       IN SOA ns1 hostmaster 1 7200 900 1209600 300
+www     IN A 192.0.2.1 ; the site
+$ORIGIN dev.example.com.
+api     IN A 192.0.2.2
+@       IN A 192.0.2.3
+$ORIGIN staging
+app     IN A 192.0.2.4
+";
+        assert_eq!(sorted(zone_names(zone, "ignored.org")), [
+            "api.dev.example.com.",
+            "app.staging.dev.example.com.",
+            "dev.example.com.",
+            "example.com.",
+            "www.example.com.",
+        ]);
+    }
+
+    #[test]
+    fn zone_without_origin_uses_the_domain() {
+        let names = zone_names("www IN A 192.0.2.1\n@ IN A 192.0.2.2\n", "example.com");
+        assert_eq!(names, ["www.example.com.", "example.com."]);
+    }
+
+    #[test]
+    fn zone_continuation_lines() {
+        let zone = "\
+@   IN SOA ns1.example.com. hostmaster.example.com. (
+        2024010101 ; serial
+        3600 )
+    IN NS ns1
+www IN A 192.0.2.1
+    IN AAAA 2001:db8::1
+    IN CNAME edge
+mail IN A 192.0.2.2
+";
+        let names = zone_names(zone, "example.com");
+        // blank-led lines belong to the last owner, never to a name of their own
+        assert!(names[..4].iter().all(|n| n == "example.com."));
+        assert_eq!(names[4], "ns1.example.com.");
+        assert_eq!(sorted(names), [
+            "edge.example.com.",
+            "example.com.",
+            "mail.example.com.",
+            "ns1.example.com.",
+            "www.example.com.",
+        ]);
+    }
+
+    #[test]
+    fn zone_relative_and_absolute_targets() {
+        let zone = "\
+blog       IN CNAME www
+shop       IN CNAME shops.myshopify.com.
+@          IN MX 10 mx1
+@          IN NS ns.provider.net.
+_sip._tcp  IN SRV 10 5 5060 sip
+1          IN PTR host1
+txt        IN TXT \"ns\"
+";
+        assert_eq!(sorted(zone_names(zone, "example.com")), [
+            "1.example.com.",
+            "_sip._tcp.example.com.",
+            "blog.example.com.",
+            "example.com.",
+            "host1.example.com.",
+            "mx1.example.com.",
+            "ns.provider.net.",
+            "shop.example.com.",
+            "shops.myshopify.com.",
+            "sip.example.com.",
+            "txt.example.com.",
+            "www.example.com.",
+        ]);
+    }
+
+    // ---- files ----
+
+    #[tokio::test]
+    async fn json_lines_field_fallback() {
+        let path = temp_file("import.jsonl", r#"
+{"host":"a.example.com","input":"example.com","source":"crtsh"}
+{"name":"b.example.com","domain":"example.com","addresses":[{"ip":"192.0.2.1"}]}
+{"subdomain":"c.example.com"}
+{"host":"d.example.com","name":"not-this.example.com"}
+{"host":5,"name":"e.example.com"}
+{"ip":"192.0.2.1","seen":"f.example.com via g.example.com"}
+h.example.com
+not json: i.example.com, j.example.com
+"#);
+        let names = JsonLines { path: path.clone() }.subdomains("example.com").await.unwrap();
+        std::fs::remove_file(path).ok();
+        assert_eq!(names, [
+            "a.example.com", "b.example.com", "c.example.com", "d.example.com", "e.example.com",
+            "f.example.com", "g.example.com", "h.example.com", "i.example.com", "j.example.com",
+        ]);
+    }
+
+    #[tokio::test]
+    async fn ct_dump_reads_escaped_newlines() {
+        let path = temp_file("ct.json", r#"[{"name_value":"example.com\nwww.example.com"},{"name_value":"*.api.example.com"}]"#);
+        let names = CtDump { path: path.clone() }.subdomains("example.com").await.unwrap();
+        std::fs::remove_file(path).ok();
+        assert_eq!(names, ["example.com", "www.example.com", "api.example.com"]);
+    }
+
+    // ---- http providers ----
+
+    /// Serves `body` to every request and reports each request line.
+    async fn http_server(body: &'static str) -> (String, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}/", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = vec![0u8; 4096];
+                let n = stream.read(&mut buf).await.unwrap_or(0);
+                let head = String::from_utf8_lossy(&buf[..n]).into_owned();
+                tx.send(head.lines().next().unwrap_or_default().to_string()).ok();
+                let resp = format!("HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body);
+                stream.write_all(resp.as_bytes()).await.ok();
+            }
+        });
+        (base, rx)
+    }
+
+    #[tokio::test]
+    async fn http_source_queries_the_configured_base() {
+        let client = http::client(Duration::from_secs(5)).unwrap();
+        let expected = [
+            (Provider::CrtSh, "GET /?q=%25.example.com&output=json HTTP/1.1"),
+            (Provider::Otx, "GET /api/v1/indicators/domain/example.com/passive_dns HTTP/1.1"),
+            (Provider::HackerTarget, "GET /hostsearch/?q=example.com HTTP/1.1"),
+            (Provider::Anubis, "GET /anubis/subdomains/example.com HTTP/1.1"),
+            (Provider::Wayback, "GET /cdx/search/cdx?url=*.example.com/*&output=txt&fl=original&collapse=urlkey HTTP/1.1"),
+        ];
+        for (provider, request_line) in expected {
+            let (base, mut requests) = http_server(r#"[{"name_value":"a.example.com\nb.example.com"}]"#).await;
+            let source = HttpSource { provider, base, client: client.clone() };
+            assert_eq!(source.subdomains("example.com").await.unwrap(), ["a.example.com", "b.example.com"]);
+            assert_eq!(requests.recv().await.unwrap(), request_line);
+        }
+    }
+
+    #[tokio::test]
+    async fn http_source_reports_errors() {
+        let client = http::client(Duration::from_secs(5)).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let base = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut buf = [0u8; 1024];
+                let _ = stream.read(&mut buf).await;
+                stream.write_all(b"HTTP/1.1 429 Too Many Requests\r\nContent-Length: 0\r\nConnection: close\r\n\r\n").await.ok();
+            }
+        });
+        let source = HttpSource { provider: Provider::HackerTarget, base, client };
+        assert!(source.subdomains("example.com").await.is_err());
+    }
+
+    // ---- enumerate ----
+
+    struct Fixed(&'static [&'static str]);
+
+    #[async_trait]
+    impl SubdomainSource for Fixed {
+        fn name(&self) -> &'static str { "fixed" }
+
+        async fn subdomains(&self, _domain: &str) -> anyhow::Result<Vec<String>> {
+            Ok(self.0.iter().map(|s| s.to_string()).collect())
+        }
+    }
+
+    struct Failing;
+
+    #[async_trait]
+    impl SubdomainSource for Failing {
+        fn name(&self) -> &'static str { "failing" }
+
+        async fn subdomains(&self, _domain: &str) -> anyhow::Result<Vec<String>> {
+            anyhow::bail!("rate limited")
+        }
+    }
+
+    #[tokio::test]
+    async fn enumerate_filters_by_suffix_and_dedups() {
+        let sources: Vec<Box<dyn SubdomainSource>> = vec![
+            Box::new(Fixed(&["www.example.com", "WWW.Example.COM.", "*.api.example.com", "example.com", "notexample.com", "example.com.evil.net"])),
+            Box::new(Failing),
+            Box::new(Fixed(&[" mail.example.com ", "www.example.com", "api.example.com", "other.org"])),
+        ];
+        assert_eq!(enumerate(sources, "Example.com.").await, ["api.example.com", "mail.example.com", "www.example.com"]);
+    }
+
+    // ---- axfr ----
+
+    fn record(name: &str, rdata: RData) -> Record {
+        Record::from_rdata(Name::from_ascii(name).unwrap(), 300, rdata)
+    }
+
+    fn soa() -> Record {
+        let ns = Name::from_ascii("ns1.example.com.").unwrap();
+        let mbox = Name::from_ascii("hostmaster.example.com.").unwrap();
+        record("example.com.", RData::SOA(SOA::new(ns, mbox, 1, 7200, 900, 1209600, 300)))
+    }
+
+    /// A DNS server on TCP that answers the first query with `reply`, which
+    /// may rewrite the message's ID and question.
+    async fn axfr_server(reply: fn(&Message) -> Vec<Message>) -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await.unwrap();
+            let mut buf = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(&mut buf).await.unwrap();
+            let query = Message::from_vec(&buf).unwrap();
+            for msg in reply(&query) {
+                let bytes = msg.to_vec().unwrap();
+                stream.write_all(&(bytes.len() as u16).to_be_bytes()).await.unwrap();
+                stream.write_all(&bytes).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    fn response(query: &Message, answers: Vec<Record>) -> Message {
+        let mut msg = Message::new();
+        msg.set_id(query.id()).set_message_type(MessageType::Response);
+        msg.add_queries(query.queries().to_vec()).add_answers(answers);
+        msg
+    }
+
+    #[tokio::test]
+    async fn transfer_collects_names_across_messages() {
+        let server = axfr_server(|query| {
+            let www = record("www.example.com.", RData::A(Ipv4Addr::new(192, 0, 2, 1)));
+            let blog = record("blog.example.com.", RData::CNAME(Name::from_ascii("cdn.provider.net.").unwrap()));
+            let mut second = response(query, vec![blog, soa()]);
+            // later messages may leave the question out
+            second.take_queries();
+            vec![response(query, vec![soa(), www]), second]
+        }).await;
+        let names = transfer(server, "example.com").await.unwrap();
+        assert_eq!(names, ["example.com.", "www.example.com.", "blog.example.com.", "cdn.provider.net.", "example.com."]);
+    }
+
+    #[tokio::test]
+    async fn transfer_rejects_another_id() {
+        let server = axfr_server(|query| {
+            let mut msg = response(query, vec![soa(), soa()]);
+            msg.set_id(query.id().wrapping_add(1));
+            vec![msg]
+        }).await;
+        assert!(transfer(server, "example.com").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn transfer_rejects_another_question() {
+        let server = axfr_server(|query| {
+            let mut msg = response(query, vec![soa(), soa()]);
+            msg.take_queries();
+            msg.add_query(Query::query(Name::from_ascii("evil.net.").unwrap(), RecordType::AXFR));
+            vec![msg]
+        }).await;
+        assert!(transfer(server, "example.com").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn transfer_rejects_a_query() {
+        let server = axfr_server(|query| {
+            let mut msg = response(query, vec![soa(), soa()]);
+            msg.set_message_type(MessageType::Query);
+            vec![msg]
+        }).await;
+        assert!(transfer(server, "example.com").await.is_err());
+    }
+}
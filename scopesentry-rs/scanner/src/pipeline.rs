@@ -0,0 +1,208 @@
+use std::{collections::HashMap, net::IpAddr};
+
+use async_trait::async_trait;
+use redis::AsyncCommands;
+
+use scopesentry_common::{models::{DispatchTemplate, NodeLimits}, util::now_string};
+
+use crate::{dns::Records, http::TlsCert, Ctx};
+
+/// Canonical execution order of the scan stages. Modules are always run in
+/// this order regardless of the order they were registered in.
+pub const STAGE_ORDER: [&str; 13] = [
+    "TargetHandler",
+    "SubdomainScan",
+    "SubdomainSecurity",
+    "PortScanPreparation",
+    "PortScan",
+    "PortFingerprint",
+    "AssetMapping",
+    "AssetHandle",
+    "URLScan",
+    "WebCrawler",
+    "URLSecurity",
+    "DirScan",
+    "VulnerabilityScan",
+];
+
+/// A live web asset and what the asset mapping stage learned about it.
+#[derive(Debug, Clone, Default)]
+pub struct AssetRec {
+    pub url: String,
+    pub host: String,
+    pub port: i32,
+    pub service: String,
+    pub typ: String,
+    pub status: u16,
+    pub title: String,
+    /// status line plus response headers
+    pub raw_headers: String,
+    pub content_length: i64,
+    /// response body (truncated to `http::MAX_BODY`), kept for later stages
+    pub body: String,
+    pub body_sha256: String,
+    pub body_mmh3: i32,
+    pub favicon_url: String,
+    pub favicon_mmh3: Option<i32>,
+    /// base64 of the favicon, for display
+    pub favicon: String,
+    pub redirects: Vec<String>,
+    pub tls: Option<TlsCert>,
+    pub technologies: Vec<String>,
+    pub web_server: String,
+}
+
+/// An open TCP port found by the port scan; `service`/`version`/`banner` are
+/// filled in by the port fingerprint stage.
+#[derive(Debug, Clone, Default)]
+pub struct PortRec { pub host: String, pub ip: String, pub port: u16, pub service: String, pub version: String, pub banner: String }
+
+/// A fetched HTTP response body, kept for the content-inspection stages.
+#[derive(Debug, Clone, Default)]
+pub struct Page { pub url: String, pub body: String }
+
+/// Results accumulated while a single target moves through the pipeline.
+/// Every stage reads what earlier stages produced and appends its own output.
+#[derive(Debug, Clone, Default)]
+pub struct ScanState {
+    /// raw target as popped from `TaskInfo:{id}`
+    pub target: String,
+    /// set when the target is a bare domain
+    pub domain: Option<String>,
+    /// hosts (domains or IPs) in scope for this target, including discovered subdomains
+    pub hosts: Vec<String>,
+    /// explicit URL targets
+    pub urls: Vec<String>,
+    pub subdomains: Vec<String>,
+    /// DNS records of the subdomains, as resolved by the subdomain stages
+    pub records: Vec<Records>,
+    pub ports: Vec<PortRec>,
+    pub assets: Vec<AssetRec>,
+    /// every page body fetched so far (asset landing pages, crawled pages, ...)
+    pub pages: Vec<Page>,
+    /// in-scope URLs found by the crawler, including scripts and static files
+    pub links: Vec<String>,
+}
+
+impl ScanState {
+    pub fn new(target: &str) -> Self {
+        ScanState { target: target.to_string(), ..Default::default() }
+    }
+
+    pub fn add_host(&mut self, host: &str) {
+        if !self.hosts.iter().any(|h| h == host) { self.hosts.push(host.to_string()); }
+    }
+
+    /// Addresses `host` resolved to in an earlier stage.
+    pub fn resolved_ips(&self, host: &str) -> Vec<IpAddr> {
+        self.records.iter().find(|r| r.host == host).map(|r| r.ips.clone()).unwrap_or_default()
+    }
+}
+
+/// Arguments of one module, i.e. the `plugin -> args` map the template holds
+/// under the module's name. Args are CLI-style strings such as `-t 100 -port 80,443`.
+/// The node's [`NodeLimits`] cap what the args ask for.
+#[derive(Debug, Clone, Copy)]
+pub struct ModuleParams<'a> {
+    plugins: Option<&'a HashMap<String, String>>,
+    limits: NodeLimits,
+}
+
+impl<'a> ModuleParams<'a> {
+    pub fn new(tmpl: &'a DispatchTemplate, module: &str) -> Self {
+        ModuleParams { plugins: tmpl.Parameters.get(module), limits: NodeLimits::default() }
+    }
+
+    pub fn with_limits(mut self, limits: NodeLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.plugins.map(|p| !p.is_empty()).unwrap_or(false)
+    }
+
+    /// Value of `-key value` or `-key=value` in any plugin's args.
+    pub fn flag(&self, key: &str) -> Option<String> {
+        let dash = format!("-{}", key);
+        let prefixed = format!("-{}=", key);
+        for args in self.plugins?.values() {
+            let mut tokens = args.split_whitespace();
+            while let Some(tok) = tokens.next() {
+                if tok == dash { return tokens.next().map(|v| v.to_string()); }
+                if let Some(v) = tok.strip_prefix(&prefixed) { return Some(v.to_string()); }
+            }
+        }
+        None
+    }
+
+    pub fn flag_or<T: std::str::FromStr>(&self, key: &str, default: T) -> T {
+        self.flag(key).and_then(|v| v.parse().ok()).unwrap_or(default)
+    }
+
+    /// Concurrency flag `key`, at least 1 and at most the node's limit.
+    pub fn concurrency(&self, key: &str, default: usize) -> usize {
+        let n = self.flag_or(key, default);
+        self.limits.concurrency.filter(|cap| *cap > 0).map_or(n, |cap| n.min(cap)).max(1)
+    }
+
+    /// Packets per second from `-r`, 0 for unpaced; the node's limit paces
+    /// an unpaced scan too.
+    pub fn rate(&self, default: u64) -> u64 {
+        let rate = self.flag_or("r", default);
+        match self.limits.rate.filter(|cap| *cap > 0) {
+            Some(cap) if rate == 0 || rate > cap => cap,
+            _ => rate,
+        }
+    }
+}
+
+#[async_trait]
+pub trait ScanModule: Send + Sync {
+    /// Stage name as used in the template and in the progress hash.
+    fn name(&self) -> &'static str;
+
+    /// Modules that prepare input for the rest of the pipeline run even when
+    /// the template does not list them.
+    fn always_run(&self) -> bool { false }
+
+    async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, params: ModuleParams<'_>, state: &mut ScanState) -> anyhow::Result<()>;
+}
+
+#[derive(Default)]
+pub struct Pipeline {
+    modules: Vec<Box<dyn ScanModule>>,
+}
+
+impl Pipeline {
+    pub fn register(mut self, module: impl ScanModule + 'static) -> Self {
+        self.modules.push(Box::new(module));
+        self.modules.sort_by_key(|m| STAGE_ORDER.iter().position(|s| *s == m.name()).unwrap_or(STAGE_ORDER.len()));
+        self
+    }
+
+    /// Run every enabled module for one target, recording stage start/end
+    /// times in `TaskInfo:progress:{id}:{target}`. A failing module is logged
+    /// and the remaining stages still run.
+    pub async fn run(&self, ctx: &Ctx, tmpl: &DispatchTemplate, con: &mut redis::aio::MultiplexedConnection, target: &str) -> anyhow::Result<ScanState> {
+        let pkey = format!("TaskInfo:progress:{}:{}", tmpl.ID, target);
+        let _: () = con.hset(&pkey, "node", &ctx.node_name).await?;
+        let _: () = con.hset(&pkey, "scan_start", now_string()).await?;
+
+        let mut state = ScanState::new(target);
+        for module in &self.modules {
+            let params = ModuleParams::new(tmpl, module.name()).with_limits(ctx.node.limits());
+            if !params.enabled() && !module.always_run() { continue; }
+
+            let _: () = con.hset(&pkey, format!("{}_start", module.name()), now_string()).await?;
+            ctx.node.set_module(Some(module.name()));
+            if let Err(e) = module.run(ctx, tmpl, params, &mut state).await {
+                tracing::warn!("{} failed for {}: {}", module.name(), target, e);
+            }
+            let _: () = con.hset(&pkey, format!("{}_end", module.name()), now_string()).await?;
+        }
+
+        let _: () = con.hset(&pkey, "scan_end", now_string()).await?;
+        Ok(state)
+    }
+}
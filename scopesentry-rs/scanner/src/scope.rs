@@ -0,0 +1,107 @@
+use std::{collections::HashSet, net::IpAddr};
+
+use publicsuffix::{List, Psl};
+use regex::Regex;
+use tokio::sync::OnceCell;
+use url::Url;
+
+/// Public suffix list shipped by most distros (`publicsuffix` package).
+const SYSTEM_LIST: &str = "/usr/share/publicsuffix/public_suffix_list.dat";
+/// Copy bundled with the scanner, used when no other list is readable.
+const BUNDLED_LIST: &str = include_str!("../data/public_suffix_list.dat");
+
+static LIST: OnceCell<List> = OnceCell::const_new();
+
+/// The public suffix list: the file named by `PUBLIC_SUFFIX_LIST`, else the
+/// system copy, else the bundled one. Read once per process, never fetched.
+async fn suffix_list() -> &'static List {
+    LIST.get_or_init(|| async {
+        let configured = std::env::var("PUBLIC_SUFFIX_LIST").ok();
+        for path in configured.iter().map(String::as_str).chain([SYSTEM_LIST]) {
+            let parsed = match tokio::fs::read(path).await {
+                Ok(raw) => List::from_bytes(&raw).map_err(|e| e.to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+            match parsed {
+                Ok(list) => return list,
+                Err(e) if configured.as_deref() == Some(path) => tracing::warn!("public suffix list {}: {}", path, e),
+                Err(_) => {}
+            }
+        }
+        BUNDLED_LIST.parse().expect("bundled public suffix list")
+    }).await
+}
+
+/// Registrable domain of `host` (`a.b.example.co.uk` -> `example.co.uk`).
+/// IPs and names without a known suffix are returned unchanged.
+fn registrable(list: &List, host: &str) -> String {
+    let host = host.trim_end_matches('.').to_lowercase();
+    if host.parse::<IpAddr>().is_ok() { return host; }
+    match list.domain(host.as_bytes()) {
+        Some(d) => String::from_utf8_lossy(d.as_bytes()).into_owned(),
+        None => host,
+    }
+}
+
+/// Which hosts the crawler and URL scan may follow: anything under the same
+/// registrable domain as one of the seed hosts, minus the task's ignore rules.
+#[derive(Debug, Clone)]
+pub struct Scope {
+    list: &'static List,
+    roots: HashSet<String>,
+    ignore: HashSet<String>,
+    ignore_re: Vec<Regex>,
+}
+
+impl Scope {
+    pub async fn new<'a>(hosts: impl IntoIterator<Item = &'a str>, ignore: &str) -> Scope {
+        let list = suffix_list().await;
+        let roots = hosts.into_iter().map(|h| registrable(list, h)).collect();
+        let (ignore, ignore_re) = scopesentry_common::util::generate_ignore(ignore);
+        Scope { list, roots, ignore: ignore.into_iter().collect(), ignore_re }
+    }
+
+    pub fn root_domain(&self, host: &str) -> String {
+        registrable(self.list, host)
+    }
+
+    pub fn contains_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.').to_lowercase();
+        if self.ignore.contains(&host) || self.ignore_re.iter().any(|re| re.is_match(&host)) { return false; }
+        self.roots.contains(&registrable(self.list, &host))
+    }
+
+    pub fn contains(&self, url: &Url) -> bool {
+        matches!(url.scheme(), "http" | "https") && url.host_str().map(|h| self.contains_host(h)).unwrap_or(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_list_finds_registrable_domains() {
+        let list: List = BUNDLED_LIST.parse().unwrap();
+        assert_eq!(registrable(&list, "a.b.example.co.uk"), "example.co.uk");
+        assert_eq!(registrable(&list, "WWW.Example.com."), "example.com");
+        assert_eq!(registrable(&list, "user.github.io"), "user.github.io");
+        assert_eq!(registrable(&list, "10.0.0.1"), "10.0.0.1");
+        assert_eq!(registrable(&list, "localhost"), "localhost");
+    }
+
+    #[tokio::test]
+    async fn scope_by_registrable_domain() {
+        let scope = Scope::new(["www.example.co.uk", "10.0.0.1"], "admin.example.co.uk\n*.internal.example.co.uk").await;
+        assert!(scope.contains_host("api.example.co.uk"));
+        assert!(scope.contains_host("EXAMPLE.co.uk."));
+        assert!(scope.contains_host("10.0.0.1"));
+        assert!(!scope.contains_host("10.0.0.2"));
+        assert!(!scope.contains_host("other.co.uk"));
+        assert!(!scope.contains_host("admin.example.co.uk"));
+        assert!(!scope.contains_host("db.internal.example.co.uk"));
+        assert!(scope.contains(&Url::parse("https://shop.example.co.uk/x").unwrap()));
+        assert!(!scope.contains(&Url::parse("ftp://shop.example.co.uk/x").unwrap()));
+        assert_eq!(scope.root_domain("a.b.example.co.uk"), "example.co.uk");
+    }
+}
@@ -0,0 +1,71 @@
+use std::collections::HashSet;
+
+use mongodb::{bson::{doc, Document}, Collection};
+
+use scopesentry_common::{models::{DispatchTemplate, Duplicates}, util::now_string};
+
+use crate::{dns::Records, Ctx};
+
+/// Hosts looked up per query when checking which are already stored.
+const LOOKUP_CHUNK: usize = 1000;
+
+/// Store subdomains with their records as `type`, `value` and `ip` (extra
+/// record types go under `records`) and return the ones left to scan.
+///
+/// Hosts the task's [`Duplicates`] mode treats as known are dropped without
+/// being written; every other host is upserted on `host`, keeping its first
+/// `time`, `tags` and `taskName`, moving `lastScanTime` to this scan and
+/// adding the task's id to `tasks`. Ids rather than names, since every
+/// scheduled run is named apart while a retest keeps its task's name.
+pub async fn save_subdomains(ctx: &Ctx, tmpl: &DispatchTemplate, subs: Vec<Records>) -> anyhow::Result<Vec<Records>> {
+    if subs.is_empty() { return Ok(subs); }
+    let db = ctx.db();
+    let coll = db.collection::<Document>("subdomain");
+
+    let hosts: Vec<String> = subs.iter().map(|r| r.host.clone()).collect();
+    let known = match tmpl.duplicates {
+        Duplicates::None => HashSet::new(),
+        Duplicates::Subdomain => stored_hosts(&coll, &hosts, doc!{}).await?,
+        // documents written before `tasks` existed only have `taskName`
+        Duplicates::Task => {
+            let legacy = doc!{"tasks": {"$exists": false}, "taskName": &tmpl.TaskName};
+            stored_hosts(&coll, &hosts, doc!{"$or": [{"tasks": &tmpl.ID}, legacy]}).await?
+        }
+    };
+    let fresh: Vec<Records> = subs.into_iter().filter(|r| !known.contains(&r.host)).collect();
+    if !known.is_empty() {
+        tracing::info!("{}: skipped {} known subdomains", tmpl.TaskName, known.len());
+    }
+
+    let now = now_string();
+    let mut failed = 0;
+    for r in &fresh {
+        let mut set = doc!{"type": r.typ(), "value": r.values(), "ip": r.ip_strings(), "lastScanTime": &now};
+        if !r.other.is_empty() {
+            let other: Document = r.other.iter().map(|(k, v)| (k.clone(), v.clone().into())).collect();
+            set.insert("records", other);
+        }
+        let update = doc!{
+            "$set": set,
+            "$setOnInsert": {"time": &now, "tags": [], "taskName": &tmpl.TaskName},
+            "$addToSet": {"tasks": &tmpl.ID},
+        };
+        if coll.update_one(doc!{"host": &r.host}, update).upsert(true).await.is_err() { failed += 1; }
+    }
+    if failed > 0 { tracing::warn!("{}: {} subdomains could not be saved", tmpl.TaskName, failed); }
+    Ok(fresh)
+}
+
+/// Which of `hosts` have a `subdomain` document matching `filter`.
+async fn stored_hosts(coll: &Collection<Document>, hosts: &[String], filter: Document) -> anyhow::Result<HashSet<String>> {
+    let mut found = HashSet::new();
+    for chunk in hosts.chunks(LOOKUP_CHUNK) {
+        let mut f = filter.clone();
+        f.insert("host", doc!{"$in": chunk});
+        let mut cursor = coll.find(f).projection(doc!{"host": 1, "_id": 0}).await?;
+        while cursor.advance().await? {
+            if let Ok(host) = cursor.deserialize_current()?.get_str("host") { found.insert(host.to_string()); }
+        }
+    }
+    Ok(found)
+}
@@ -0,0 +1,67 @@
+use std::fs;
+
+/// Host load read from `/proc`; every figure is `None` where `/proc` is not
+/// available (non-Linux hosts).
+#[derive(Debug, Clone, Default)]
+pub struct SystemLoad {
+    /// busy CPU share since the previous sample, percent
+    pub cpu: Option<f64>,
+    /// used memory (total minus available), percent
+    pub mem: Option<f64>,
+    /// 1, 5 and 15 minute load averages
+    pub load_avg: Option<String>,
+}
+
+/// Aggregate CPU time counters from `/proc/stat`.
+#[derive(Debug, Clone, Copy)]
+struct CpuTimes {
+    total: u64,
+    idle: u64,
+}
+
+/// Samples [`SystemLoad`]; CPU usage is measured between consecutive calls.
+#[derive(Debug, Default)]
+pub struct Sampler {
+    last: Option<CpuTimes>,
+}
+
+impl Sampler {
+    pub fn sample(&mut self) -> SystemLoad {
+        let now = cpu_times();
+        let cpu = match (self.last, now) {
+            (Some(a), Some(b)) if b.total > a.total => {
+                let busy = (b.total - a.total).saturating_sub(b.idle.saturating_sub(a.idle));
+                Some(busy as f64 * 100.0 / (b.total - a.total) as f64)
+            }
+            _ => None,
+        };
+        self.last = now;
+        SystemLoad { cpu, mem: mem_used(), load_avg: load_avg() }
+    }
+}
+
+fn cpu_times() -> Option<CpuTimes> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let line = stat.lines().find(|l| l.starts_with("cpu "))?;
+    // user nice system idle iowait irq softirq steal; guest time is already in user
+    let v: Vec<u64> = line.split_whitespace().skip(1).take(8).filter_map(|n| n.parse().ok()).collect();
+    if v.len() < 4 { return None; }
+    Some(CpuTimes { total: v.iter().sum(), idle: v[3] + v.get(4).copied().unwrap_or(0) })
+}
+
+fn mem_used() -> Option<f64> {
+    let info = fs::read_to_string("/proc/meminfo").ok()?;
+    let field = |name: &str| -> Option<f64> {
+        info.lines().find_map(|l| l.strip_prefix(name)?.trim_start_matches(':').split_whitespace().next()?.parse().ok())
+    };
+    let total = field("MemTotal")?;
+    let available = field("MemAvailable")?;
+    if total <= 0.0 { return None; }
+    Some((total - available) * 100.0 / total)
+}
+
+fn load_avg() -> Option<String> {
+    let text = fs::read_to_string("/proc/loadavg").ok()?;
+    let parts: Vec<&str> = text.split_whitespace().take(3).collect();
+    (parts.len() == 3).then(|| parts.join(" "))
+}
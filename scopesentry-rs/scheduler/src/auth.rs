@@ -0,0 +1,95 @@
+use axum::{extract::{Request, State}, http::header::AUTHORIZATION, middleware::Next, response::{IntoResponse, Response}, Extension, Json};
+use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use mongodb::{bson::{doc, Document}, Collection};
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+
+use scopesentry_common::{models::{ChangePasswordRequest, LoginRequest}, settings::AppConfig};
+
+use crate::AppState;
+
+const TOKEN_TTL_SECS: i64 = 30 * 24 * 3600;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Claims {
+    pub sub: String,
+    pub exp: i64,
+}
+
+/// Secret used to sign API tokens: `system.secret_key` from the config, or a
+/// per-process random key (tokens then expire with the process, as in Python).
+pub fn load_secret(cfg: &AppConfig) -> String {
+    match cfg.system.secret_key.as_deref() {
+        Some(k) if !k.is_empty() => k.to_string(),
+        _ => rand::thread_rng().sample_iter(&Alphanumeric).take(32).map(char::from).collect(),
+    }
+}
+
+/// Passwords in the `user` collection are stored as hex SHA-256 digests.
+pub fn hash_password(password: &str) -> String {
+    hex::encode(Sha256::digest(password.as_bytes()))
+}
+
+pub fn issue_token(secret: &str, username: &str) -> anyhow::Result<String> {
+    let claims = Claims { sub: username.to_string(), exp: chrono::Utc::now().timestamp() + TOKEN_TTL_SECS };
+    Ok(encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_bytes()))?)
+}
+
+pub fn verify_token(secret: &str, token: &str) -> Option<Claims> {
+    decode::<Claims>(token, &DecodingKey::from_secret(secret.as_bytes()), &Validation::default())
+        .ok()
+        .map(|d| d.claims)
+}
+
+/// Reject requests without a valid `Authorization: Bearer <token>` header.
+/// Mirrors the Python API: HTTP 200 with `code: 401` in the body.
+pub async fn require_token(State(state): State<AppState>, mut req: Request, next: Next) -> Response {
+    let token = req.headers().get(AUTHORIZATION)
+        .and_then(|v| v.to_str().ok())
+        .map(|v| v.trim_start_matches("Bearer ").trim().to_string())
+        .unwrap_or_default();
+    match verify_token(&state.secret, &token) {
+        Some(claims) => {
+            req.extensions_mut().insert(claims);
+            next.run(req).await
+        }
+        None => Json(json!({"code":401, "message":"Could not validate credentials"})).into_response(),
+    }
+}
+
+pub async fn login(State(state): State<AppState>, Json(req): Json<LoginRequest>) -> Json<serde_json::Value> {
+    let coll = users(&state);
+    let user = coll.find_one(doc!{"username": &req.username}).await.ok().flatten();
+    let valid = user
+        .as_ref()
+        .and_then(|u| u.get_str("password").ok())
+        .map(|p| p == hash_password(&req.password))
+        .unwrap_or(false);
+    if !valid {
+        return Json(json!({"code":401, "message":"Incorrect username or password"}));
+    }
+    match issue_token(&state.secret, &req.username) {
+        Ok(token) => Json(json!({"code":200, "data": {"access_token": token}})),
+        Err(e) => {
+            tracing::error!("issue token failed: {}", e);
+            Json(json!({"code":500, "message":"error"}))
+        }
+    }
+}
+
+pub async fn change_password(State(state): State<AppState>, Extension(claims): Extension<Claims>, Json(req): Json<ChangePasswordRequest>) -> Json<serde_json::Value> {
+    if req.newPassword.is_empty() {
+        return Json(json!({"code":400, "message":"Password change failed"}));
+    }
+    let update = doc!{"$set": {"password": hash_password(&req.newPassword)}};
+    match users(&state).update_one(doc!{"username": &claims.sub}, update).await {
+        Ok(r) if r.matched_count > 0 => Json(json!({"code":200, "message":"success change password"})),
+        _ => Json(json!({"code":500, "message":"Password change failed"})),
+    }
+}
+
+fn users(state: &AppState) -> Collection<Document> {
+    scopesentry_common::mongo::db(&state.mongo, &state.cfg).collection("user")
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+
+use redis::AsyncCommands;
+
+use scopesentry_common::{models::Distribution, rds};
+
+use crate::node::node_capacities;
+
+/// Queue the targets of task `task_id` for `nodes` as `mode` asks: all in the
+/// shared `TaskInfo:{id}`, or dealt into per-node shards
+/// ([`rds::target_shard`]). Only nodes that are online get a shard; with
+/// none online everything goes to the shared queue.
+pub async fn enqueue(con: &mut redis::aio::MultiplexedConnection, task_id: &str, mode: Distribution, targets: Vec<String>, nodes: &[String]) -> anyhow::Result<()> {
+    if targets.is_empty() { return Ok(()); }
+    let slots: Vec<(String, f64)> = node_capacities(con).await.into_iter().filter(|(n, _)| nodes.contains(n)).collect();
+    if mode == Distribution::Shared || slots.is_empty() {
+        let _: i64 = con.rpush(format!("TaskInfo:{}", task_id), targets).await?;
+        return Ok(());
+    }
+    for (node, shard) in assign(mode, targets, &slots) {
+        if shard.is_empty() { continue; }
+        let _: i64 = con.rpush(rds::target_shard(task_id, &node), shard).await?;
+    }
+    Ok(())
+}
+
+/// Deal `targets` to `slots` (node, capacity), keeping their order within
+/// each node. Without slots nothing is dealt; [`enqueue`] uses the shared
+/// queue then.
+fn assign(mode: Distribution, targets: Vec<String>, slots: &[(String, f64)]) -> HashMap<String, Vec<String>> {
+    let mut shards: HashMap<String, Vec<String>> = HashMap::new();
+    if slots.is_empty() { return shards; }
+    match mode {
+        Distribution::Shared | Distribution::RoundRobin => {
+            for (i, t) in targets.into_iter().enumerate() {
+                shards.entry(slots[i % slots.len()].0.clone()).or_default().push(t);
+            }
+        }
+        Distribution::Weighted => {
+            // smooth weighted round robin: every node gains its weight each
+            // round, the richest takes the target and pays the total
+            let total: f64 = slots.iter().map(|(_, w)| w).sum();
+            let mut current = vec![0.0; slots.len()];
+            for t in targets {
+                for (c, (_, w)) in current.iter_mut().zip(slots) { *c += w; }
+                let best = (0..slots.len()).fold(0, |b, i| if current[i] > current[b] { i } else { b });
+                current[best] -= total;
+                shards.entry(slots[best].0.clone()).or_default().push(t);
+            }
+        }
+        Distribution::Sticky => {
+            // rendezvous hashing: only the targets of a node that leaves or
+            // joins move
+            for t in targets {
+                let host = host_of(&t).to_lowercase();
+                let node = slots.iter().max_by_key(|(n, _)| fnv1a(format!("{}\0{}", n, host).as_bytes())).map(|(n, _)| n.clone()).unwrap_or_default();
+                shards.entry(node).or_default().push(t);
+            }
+        }
+    }
+    shards
+}
+
+/// Host part of a target (`https://a.example.com:8443/x`, `a.example.com:80`,
+/// `10.0.0.1`, ...).
+fn host_of(target: &str) -> &str {
+    let rest = target.split_once("://").map(|(_, r)| r).unwrap_or(target);
+    let rest = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+    if let Some(v6) = rest.strip_prefix('[') {
+        return v6.split(']').next().unwrap_or(v6);
+    }
+    match rest.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') && port.chars().all(|c| c.is_ascii_digit()) => host,
+        _ => rest,
+    }
+}
+
+/// 64-bit FNV-1a; stable across builds, unlike std's hasher.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |h, b| (h ^ *b as u64).wrapping_mul(0x0000_0100_0000_01b3))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn targets(n: usize) -> Vec<String> {
+        (0..n).map(|i| format!("t{}.example.com", i)).collect()
+    }
+
+    fn slots(nodes: &[(&str, f64)]) -> Vec<(String, f64)> {
+        nodes.iter().map(|(n, w)| (n.to_string(), *w)).collect()
+    }
+
+    fn counts(shards: &HashMap<String, Vec<String>>, nodes: &[&str]) -> Vec<usize> {
+        nodes.iter().map(|n| shards.get(*n).map_or(0, Vec::len)).collect()
+    }
+
+    /// Node each target landed on.
+    fn owners(shards: &HashMap<String, Vec<String>>) -> HashMap<String, String> {
+        shards.iter().flat_map(|(n, ts)| ts.iter().map(move |t| (t.clone(), n.clone()))).collect()
+    }
+
+    #[test]
+    fn round_robin_deals_evenly_in_order() {
+        let shards = assign(Distribution::RoundRobin, targets(10), &slots(&[("a", 1.0), ("b", 9.0), ("c", 1.0)]));
+        assert_eq!(counts(&shards, &["a", "b", "c"]), [4, 3, 3]);
+        assert_eq!(shards["a"], ["t0.example.com", "t3.example.com", "t6.example.com", "t9.example.com"]);
+        assert_eq!(shards["b"], ["t1.example.com", "t4.example.com", "t7.example.com"]);
+    }
+
+    #[test]
+    fn weighted_follows_capacity_smoothly() {
+        let nodes = slots(&[("a", 5.0), ("b", 1.0), ("c", 1.0)]);
+        let shards = assign(Distribution::Weighted, targets(7), &nodes);
+        assert_eq!(counts(&shards, &["a", "b", "c"]), [5, 1, 1]);
+        // interleaved rather than five in a row for `a`
+        let owner = owners(&shards);
+        let order: String = targets(7).iter().map(|t| owner[t].as_str()).collect();
+        assert_eq!(order, "aabacaa");
+
+        let shards = assign(Distribution::Weighted, targets(400), &slots(&[("a", 3.0), ("b", 1.0)]));
+        assert_eq!(counts(&shards, &["a", "b"]), [300, 100]);
+        let shards = assign(Distribution::Weighted, targets(96), &slots(&[("a", 0.5), ("b", 1.5), ("c", 2.0)]));
+        assert_eq!(counts(&shards, &["a", "b", "c"]), [12, 36, 48]);
+    }
+
+    #[test]
+    fn sticky_keeps_a_host_on_one_node() {
+        let nodes = slots(&[("a", 1.0), ("b", 1.0), ("c", 1.0)]);
+        let same_host = vec![
+            "https://Shop.example.com/login".to_string(),
+            "shop.example.com:8443".to_string(),
+            "http://shop.example.com:8080/?q=1".to_string(),
+            "shop.example.com".to_string(),
+        ];
+        let shards = assign(Distribution::Sticky, same_host.clone(), &nodes);
+        assert_eq!(shards.len(), 1);
+        assert_eq!(shards.values().next().unwrap(), &same_host);
+        // and on the same node whatever order the nodes come in
+        let reversed: Vec<_> = nodes.iter().rev().cloned().collect();
+        assert_eq!(owners(&shards), owners(&assign(Distribution::Sticky, same_host, &reversed)));
+    }
+
+    #[test]
+    fn sticky_moves_only_what_it_must() {
+        let three = slots(&[("a", 1.0), ("b", 1.0), ("c", 1.0)]);
+        let before = owners(&assign(Distribution::Sticky, targets(300), &three));
+        assert!(["a", "b", "c"].iter().all(|n| before.values().filter(|o| o == n).count() > 50));
+
+        // a node joins: targets move only to it
+        let four = slots(&[("a", 1.0), ("b", 1.0), ("c", 1.0), ("d", 1.0)]);
+        let joined = owners(&assign(Distribution::Sticky, targets(300), &four));
+        let moved: Vec<_> = joined.iter().filter(|(t, n)| before[*t] != **n).collect();
+        assert!(!moved.is_empty());
+        assert!(moved.iter().all(|(_, n)| *n == "d"));
+
+        // a node leaves: only its targets move
+        let two = slots(&[("a", 1.0), ("c", 1.0)]);
+        let left = owners(&assign(Distribution::Sticky, targets(300), &two));
+        for (t, n) in &before {
+            if n != "b" { assert_eq!(&left[t], n); }
+        }
+    }
+
+    #[test]
+    fn no_nodes_no_shards() {
+        for mode in [Distribution::Shared, Distribution::RoundRobin, Distribution::Weighted, Distribution::Sticky] {
+            assert!(assign(mode, targets(5), &[]).is_empty());
+        }
+    }
+
+    #[test]
+    fn host_of_targets() {
+        assert_eq!(host_of("https://a.example.com:8443/x?y=1#z"), "a.example.com");
+        assert_eq!(host_of("http://a.example.com?x=1"), "a.example.com");
+        assert_eq!(host_of("a.example.com:80"), "a.example.com");
+        assert_eq!(host_of("a.example.com"), "a.example.com");
+        assert_eq!(host_of("10.0.0.1"), "10.0.0.1");
+        assert_eq!(host_of("10.0.0.1:22"), "10.0.0.1");
+        assert_eq!(host_of("10.0.0.0/24"), "10.0.0.0");
+        assert_eq!(host_of("http://[2001:db8::1]:8080/"), "2001:db8::1");
+        assert_eq!(host_of("[2001:db8::1]:443"), "2001:db8::1");
+        assert_eq!(host_of("2001:db8::1"), "2001:db8::1");
+        // a non-numeric suffix is not a port
+        assert_eq!(host_of("a.example.com:http"), "a.example.com:http");
+    }
+
+    #[test]
+    fn fnv1a_reference_values() {
+        assert_eq!(fnv1a(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv1a(b"a"), 0xaf63_dc4c_8601_ec8c);
+        assert_eq!(fnv1a(b"foobar"), 0x85944171f73967e8);
+    }
+}
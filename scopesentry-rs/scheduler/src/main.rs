@@ -1,18 +1,23 @@
-use axum::{routing::{get, post}, Json, Router};
-use axum::extract::State;
-use serde_json::json;
+use axum::{middleware, routing::{get, post}, Router};
 use std::sync::Arc;
 use tracing_subscriber::{EnvFilter, fmt};
 use tracing_subscriber::prelude::*;
 
-use scopesentry_common::{settings::AppConfig, mongo, rds, models::{TaskAddRequest, TemplateDoc, DispatchTemplate}, util::{now_string, expand_targets}};
-use mongodb::{bson::{self, doc, oid::ObjectId}, Collection};
-use redis::AsyncCommands;
+use scopesentry_common::{settings::AppConfig, mongo};
+use mongodb::bson::{Bson, Document};
+
+mod auth;
+mod distribute;
+mod node;
+mod progress;
+mod scheduled;
+mod task;
 
 #[derive(Clone)]
 struct AppState {
     cfg: Arc<AppConfig>,
     mongo: mongodb::Client,
+    secret: Arc<String>,
 }
 
 #[tokio::main]
@@ -25,11 +30,31 @@ async fn main() -> anyhow::Result<()> {
     let cfg = Arc::new(AppConfig::load()?);
     let mongo = mongo::connect_mongo(&cfg).await?;
 
-    let state = AppState { cfg: cfg.clone(), mongo };
+    let secret = Arc::new(auth::load_secret(&cfg));
+
+    let state = AppState { cfg: cfg.clone(), mongo, secret };
+    tokio::spawn(progress::reconcile_loop(state.clone()));
+    tokio::spawn(node::sweep_loop(state.clone()));
+    tokio::spawn(scheduled::schedule_loop(state.clone()));
 
     let app = Router::new()
-        .route("/api/node/data/online", get(node_online))
-        .route("/api/task/add", post(add_task))
+        .route("/api/node/data", get(node::node_data))
+        .route("/api/node/data/online", get(node::node_online))
+        .route("/api/node/control", post(node::node_control))
+        .route("/api/node/restart", post(node::node_restart))
+        .route("/api/task/add", post(task::add_task))
+        .route("/api/task/data", post(task::task_data))
+        .route("/api/task/progress/info", post(task::progress_info))
+        .route("/api/task/delete", post(task::delete_task))
+        .route("/api/task/retest", post(task::retest_task))
+        .route("/api/task/scheduled/data", post(scheduled::scheduled_data))
+        .route("/api/task/scheduled/pause", post(scheduled::pause_scheduled))
+        .route("/api/task/scheduled/resume", post(scheduled::resume_scheduled))
+        .route("/api/task/scheduled/run", post(scheduled::run_scheduled))
+        .route("/api/user/changePassword", post(auth::change_password))
+        // everything above requires a token
+        .route_layer(middleware::from_fn_with_state(state.clone(), auth::require_token))
+        .route("/api/user/login", post(auth::login))
         .with_state(state);
 
     let port: u16 = std::env::var("SCHEDULER_PORT").ok().and_then(|s| s.parse().ok()).unwrap_or(8083);
@@ -39,89 +64,15 @@ async fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
-async fn node_online(State(state): State<AppState>) -> Json<serde_json::Value> {
-    let mut con = rds::connect_redis(&state.cfg).await.expect("redis");
-    let keys: Vec<String> = con.keys("node:*").await.unwrap_or_default();
-    let mut result = vec![];
-    for key in keys {
-        let name = key.split(':').nth(1).unwrap_or("").to_string();
-        let hash: std::collections::HashMap<String, String> = con.hgetall(&key).await.unwrap_or_default();
-        if hash.get("state").map(|s| s == "1").unwrap_or(false) {
-            result.push(name);
-        }
-    }
-    Json(json!({"code":200, "data": {"list": result}}))
+pub(crate) fn field(d: &Document, key: &str) -> serde_json::Value {
+    d.get(key).cloned().unwrap_or(Bson::Null).into_relaxed_extjson()
 }
 
-async fn add_task(State(state): State<AppState>, Json(mut req): Json<TaskAddRequest>) -> Json<serde_json::Value> {
-    // validate
-    if req.name.trim().is_empty() || (req.node.is_empty() && !req.allNode) {
-        return Json(json!({"code":400, "message":"invalid args"}));
-    }
-
-    // expand targets
-    let targets = expand_targets(&req.target, &req.ignore);
-    let task_num = targets.len() as i32;
-
-    // resolve all online nodes if allNode
-    if req.allNode {
-        let mut con = rds::connect_redis(&state.cfg).await.expect("redis");
-        let keys: Vec<String> = con.keys("node:*").await.unwrap_or_default();
-        for key in keys {
-            let name = key.split(':').nth(1).unwrap_or("");
-            let h: std::collections::HashMap<String, String> = con.hgetall(&key).await.unwrap_or_default();
-            if h.get("state").map(|s| s == "1").unwrap_or(false) {
-                if !req.node.contains(&name.to_string()) { req.node.push(name.to_string()); }
-            }
-        }
-    }
-
-    // insert task doc
-    let db = scopesentry_common::mongo::db(&state.mongo, &state.cfg);
-    let task_coll: Collection<bson::Document> = db.collection("task");
-    let now = now_string();
-    let doc = doc!{
-        "name": &req.name,
-        "target": targets.join("\n"),
-        "ignore": &req.ignore,
-        "node": bson::to_bson(&req.node).unwrap(),
-        "allNode": req.allNode,
-        "scheduledTasks": req.scheduledTasks,
-        "template": &req.template,
-        "duplicates": req.duplicates,
-        "taskNum": task_num,
-        "progress": 0.0_f64,
-        "creatTime": &now,
-        "endTime": "",
-        "status": 1_i32,
-        "type": "scan",
-    };
-    let ins = task_coll.insert_one(doc).await;
-    let Ok(ins_res) = ins else { return Json(json!({"code":500, "message":"db insert failed"})); };
-    let task_id = ins_res.inserted_id.as_object_id().unwrap_or(ObjectId::new());
-    let task_id_str = task_id.to_hex();
-
-    // enqueue targets to redis
-    let mut con = rds::connect_redis(&state.cfg).await.expect("redis");
-    if !targets.is_empty() {
-        let key = format!("TaskInfo:{}", task_id_str);
-        let _: i64 = con.lpush(key, targets).await.unwrap_or(0);
+pub(crate) fn number(v: Option<&Bson>) -> f64 {
+    match v {
+        Some(Bson::Int32(n)) => *n as f64,
+        Some(Bson::Int64(n)) => *n as f64,
+        Some(Bson::Double(n)) => *n,
+        _ => 0.0,
     }
-
-    // load template and resolve
-    let tmpl_coll: Collection<TemplateDoc> = db.collection("ScanTemplates");
-    let tmpl_oid = ObjectId::parse_str(&req.template).unwrap_or_else(|_| task_id.clone());
-    let mut dispatch = if let Ok(Some(tmpl)) = tmpl_coll.find_one(doc!{"_id": tmpl_oid}).await {
-        DispatchTemplate{ Parameters: tmpl.Parameters, TaskName: req.name.clone(), ignore: req.ignore.clone(), duplicates: req.duplicates, ID: task_id_str.clone(), r#type: "scan".to_string(), IsStart: false }
-    } else {
-        DispatchTemplate{ Parameters: Default::default(), TaskName: req.name.clone(), ignore: req.ignore.clone(), duplicates: req.duplicates, ID: task_id_str.clone(), r#type: "scan".to_string(), IsStart: false }
-    };
-
-    // dispatch to each node
-    for name in &req.node {
-        let key = format!("NodeTask:{}", name);
-        let _ = scopesentry_common::rds::rpush_json(&mut con, &key, &dispatch).await;
-    }
-
-    Json(json!({"code":200, "message":"Task added successfully"}))
-}
\ No newline at end of file
+}
@@ -0,0 +1,181 @@
+use std::{collections::HashMap, time::Duration};
+
+use axum::{extract::State, Json};
+use chrono::{Local, NaiveDateTime};
+use redis::AsyncCommands;
+use serde_json::json;
+
+use scopesentry_common::{models::{NodeCommand, NodeControlRequest, NodeNameRequest, NodeState}, rds};
+
+use crate::AppState;
+
+const SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+/// A node whose heartbeat is older than this is offline (the Python
+/// backend's `NODE_TIMEOUT`).
+const NODE_TIMEOUT: i64 = 50;
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+/// Free-capacity floor, so a loaded node still gets some targets.
+const MIN_SHARE: f64 = 0.05;
+
+/// A `node:{name}` hash as the scheduler sees it.
+struct NodeEntry {
+    name: String,
+    state: NodeState,
+    fields: HashMap<String, String>,
+}
+
+impl NodeEntry {
+    /// Seconds since the last heartbeat, `None` when it never sent one.
+    fn silence(&self) -> Option<i64> {
+        let at = NaiveDateTime::parse_from_str(self.fields.get("updateTime")?, TIME_FORMAT).ok()?;
+        Some((Local::now().naive_local() - at).num_seconds())
+    }
+
+    /// Claims to be up but has not been heard from in time.
+    fn stale(&self) -> bool {
+        self.state != NodeState::Offline && self.silence().map(|s| s > NODE_TIMEOUT).unwrap_or(true)
+    }
+
+    /// Up, heard from recently and taking tasks.
+    fn available(&self) -> bool {
+        self.state.accepts_tasks() && !self.stale()
+    }
+
+    /// Share of work the node can take: its task slots scaled by the free
+    /// CPU or memory, whichever is scarcer. Nodes that report no load count
+    /// as idle; busy ones keep a small share.
+    fn capacity(&self) -> f64 {
+        let num = |k: &str| self.fields.get(k).and_then(|v| v.parse::<f64>().ok());
+        let slots = num("maxTaskNum").unwrap_or(1.0).max(1.0);
+        let free = |k: &str| num(k).map(|used| (100.0 - used) / 100.0).unwrap_or(1.0);
+        slots * free("cpuNum").min(free("memNum")).max(MIN_SHARE)
+    }
+}
+
+async fn load_nodes(con: &mut redis::aio::MultiplexedConnection) -> Vec<NodeEntry> {
+    let keys = rds::scan_keys(con, "node:*").await.unwrap_or_default();
+    let mut nodes = vec![];
+    for key in keys {
+        let name = key.split(':').nth(1).unwrap_or("").to_string();
+        let fields: HashMap<String, String> = con.hgetall(&key).await.unwrap_or_default();
+        let state = NodeState::from_fields(fields.get("status").map(String::as_str), fields.get("state").map(String::as_str));
+        nodes.push(NodeEntry { name, state, fields });
+    }
+    nodes.sort_by(|a, b| a.name.cmp(&b.name));
+    nodes
+}
+
+/// Nodes that can be given tasks.
+pub(crate) async fn online_nodes(con: &mut redis::aio::MultiplexedConnection) -> Vec<String> {
+    load_nodes(con).await.into_iter().filter(NodeEntry::available).map(|n| n.name).collect()
+}
+
+/// [`online_nodes`] with the capacity each reports.
+pub(crate) async fn node_capacities(con: &mut redis::aio::MultiplexedConnection) -> Vec<(String, f64)> {
+    load_nodes(con).await.into_iter().filter(NodeEntry::available).map(|n| { let c = n.capacity(); (n.name, c) }).collect()
+}
+
+/// Nodes still running, draining ones included.
+pub(crate) async fn live_nodes(con: &mut redis::aio::MultiplexedConnection) -> Vec<String> {
+    load_nodes(con).await.into_iter().filter(|n| n.state != NodeState::Offline && !n.stale()).map(|n| n.name).collect()
+}
+
+/// Periodically mark nodes whose heartbeat went stale as offline, so they
+/// drop out of dispatch and their claimed targets get requeued.
+pub async fn sweep_loop(state: AppState) {
+    loop {
+        if let Err(e) = sweep(&state).await {
+            tracing::warn!("node sweep failed: {}", e);
+        }
+        tokio::time::sleep(SWEEP_INTERVAL).await;
+    }
+}
+
+async fn sweep(state: &AppState) -> anyhow::Result<()> {
+    let mut con = rds::connect_redis(&state.cfg).await?;
+    for node in load_nodes(&mut con).await.into_iter().filter(NodeEntry::stale) {
+        tracing::info!("node {} missed its heartbeat, marking offline", node.name);
+        let offline = NodeState::Offline;
+        let _: () = con.hset_multiple(format!("node:{}", node.name), &[("state", offline.code()), ("status", offline.as_str())]).await?;
+    }
+    Ok(())
+}
+
+pub async fn node_online(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let Ok(mut con) = rds::connect_redis(&state.cfg).await else { return Json(json!({"code":500, "message":"error"})); };
+    let result = online_nodes(&mut con).await;
+    Json(json!({"code":200, "data": {"list": result}}))
+}
+
+/// Every known node with its hash fields: state and heartbeat telemetry
+/// (`cpuNum`, `memNum`, `loadAvg`, `running`, `finished`, `target`,
+/// `module`, `version`, `uptime`, ...). `status` is the node's [`NodeState`],
+/// reported offline once its heartbeat is stale even before the sweeper
+/// records it; `heartbeatAge` is the seconds since that heartbeat and
+/// `pendingTasks` the templates waiting in the node's queue.
+pub async fn node_data(State(state): State<AppState>) -> Json<serde_json::Value> {
+    let Ok(mut con) = rds::connect_redis(&state.cfg).await else { return Json(json!({"code":500, "message":"error"})); };
+    let mut list = vec![];
+    for n in load_nodes(&mut con).await {
+        let current = if n.stale() { NodeState::Offline } else { n.state };
+        let pending: i64 = con.llen(format!("NodeTask:{}", n.name)).await.unwrap_or(0);
+        let mut entry = serde_json::Map::new();
+        for (k, v) in &n.fields {
+            entry.insert(k.clone(), json!(v));
+        }
+        entry.insert("name".to_string(), json!(n.name));
+        entry.insert("state".to_string(), json!(current.code()));
+        entry.insert("status".to_string(), json!(current.as_str()));
+        entry.insert("heartbeatAge".to_string(), json!(n.silence()));
+        entry.insert("pendingTasks".to_string(), json!(pending));
+        list.push(serde_json::Value::Object(entry));
+    }
+    Json(json!({"code":200, "data": {"list": list}}))
+}
+
+/// Queue `command` on `NodeControl:{name}` of each named node; `all` stands
+/// for every known node. Returns how many nodes it was sent to.
+async fn send_command(con: &mut redis::aio::MultiplexedConnection, names: &[String], command: &NodeCommand) -> redis::RedisResult<usize> {
+    let targets: Vec<String> = if names.iter().any(|n| n == "all") {
+        load_nodes(con).await.into_iter().map(|n| n.name).collect()
+    } else {
+        names.to_vec()
+    };
+    for name in &targets {
+        rds::rpush_json(con, &format!("NodeControl:{}", name), command).await?;
+    }
+    Ok(targets.len())
+}
+
+/// Send a [`NodeCommand`] to nodes. Nodes pick it up within seconds; the
+/// result shows in their `status` (pause, restart) or takes effect on the
+/// next target (limits, reload).
+pub async fn node_control(State(state): State<AppState>, Json(req): Json<NodeControlRequest>) -> Json<serde_json::Value> {
+    if req.names.is_empty() {
+        return Json(json!({"code":400, "message":"Node name is required"}));
+    }
+    let Ok(mut con) = rds::connect_redis(&state.cfg).await else { return Json(json!({"code":500, "message":"error"})); };
+    match send_command(&mut con, &req.names, &req.command).await {
+        Ok(0) => Json(json!({"code":404, "message":"Node not found"})),
+        Ok(_) => Json(json!({"code":200, "message":"success"})),
+        Err(e) => {
+            tracing::warn!("node command failed: {}", e);
+            Json(json!({"code":500, "message":"error"}))
+        }
+    }
+}
+
+/// The Python backend's `/api/node/restart`: a graceful [`NodeCommand::Restart`].
+pub async fn node_restart(State(state): State<AppState>, Json(req): Json<NodeNameRequest>) -> Json<serde_json::Value> {
+    if req.name.is_empty() {
+        return Json(json!({"code":400, "message":"Node name is required"}));
+    }
+    let Ok(mut con) = rds::connect_redis(&state.cfg).await else { return Json(json!({"code":500, "message":"error"})); };
+    match send_command(&mut con, &[req.name], &NodeCommand::Restart).await {
+        Ok(_) => Json(json!({"code":200, "message":"Node restart successfully"})),
+        Err(e) => {
+            tracing::warn!("node restart failed: {}", e);
+            Json(json!({"code":500, "message":"error"}))
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use std::time::Duration;
+
+use mongodb::{bson::{doc, Document}, Collection};
+use redis::AsyncCommands;
+
+use scopesentry_common::{models::TaskAddRequest, rds::{self, WorkQueue}, util::now_string};
+
+use crate::{node::live_nodes, task, AppState, number};
+
+const RECONCILE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Periodically fold the scanner's `TaskInfo:tmp:{id}` sets into the `progress`
+/// of running tasks and mark them finished once every target has been handled.
+/// Targets claimed by nodes that went offline or stopped renewing their lease
+/// are requeued on the way.
+pub async fn reconcile_loop(state: AppState) {
+    loop {
+        if let Err(e) = reconcile(&state).await {
+            tracing::warn!("task progress reconcile failed: {}", e);
+        }
+        tokio::time::sleep(RECONCILE_INTERVAL).await;
+    }
+}
+
+async fn reconcile(state: &AppState) -> anyhow::Result<()> {
+    let db = scopesentry_common::mongo::db(&state.mongo, &state.cfg);
+    let coll: Collection<Document> = db.collection("task");
+    let mut cursor = coll.find(doc!{"progress": {"$ne": 100}, "status": 1}).await?;
+    let mut running = vec![];
+    while cursor.advance().await? {
+        running.push(cursor.deserialize_current()?);
+    }
+    if running.is_empty() { return Ok(()); }
+
+    let mut con = rds::connect_redis(&state.cfg).await?;
+    let live = live_nodes(&mut con).await;
+    for task in running {
+        let Ok(oid) = task.get_object_id("_id") else { continue; };
+        let id = oid.to_hex();
+        if let Err(e) = recover_targets(state, &mut con, &task, &id, &live).await {
+            tracing::warn!("target recovery for task {} failed: {}", id, e);
+        }
+        let task_num = number(task.get("taskNum"));
+        let tmp_key = format!("TaskInfo:tmp:{}", id);
+        let time_key = format!("TaskInfo:time:{}", id);
+
+        let exists: bool = con.exists(&tmp_key).await?;
+        if !exists && task_num > 0.0 {
+            coll.update_one(doc!{"_id": oid}, doc!{"$set": {"progress": 0.0_f64}}).await?;
+            continue;
+        }
+        let done: u64 = con.scard(&tmp_key).await?;
+        let progress = if task_num > 0.0 {
+            ((done as f64 / task_num * 1000.0).round() / 10.0).min(100.0)
+        } else {
+            100.0
+        };
+
+        if progress >= 100.0 {
+            let end: Option<String> = con.get(&time_key).await?;
+            let end = end.unwrap_or_else(now_string);
+            coll.update_one(doc!{"_id": oid}, doc!{"$set": {"progress": 100.0_f64, "endTime": end, "status": 3_i32}}).await?;
+            // task finished, drop the counting keys
+            let mut keys = vec![tmp_key, time_key, format!("TaskInfo:{}", id)];
+            // target shards, claims and leases
+            let queue_keys = rds::scan_keys(&mut con, &format!("TaskInfo:{}:*", id)).await?;
+            keys.extend(queue_keys);
+            let _: i64 = con.del(keys).await?;
+        } else {
+            coll.update_one(doc!{"_id": oid}, doc!{"$set": {"progress": progress}}).await?;
+        }
+    }
+    Ok(())
+}
+
+/// Requeue the targets of `task` held by nodes that are no longer live or
+/// whose lease expired, move the shards of nodes that are gone to the shared
+/// queue, and hand the template out again so those targets are scanned.
+async fn recover_targets(state: &AppState, con: &mut redis::aio::MultiplexedConnection, task: &Document, id: &str, live: &[String]) -> anyhow::Result<()> {
+    let shared = WorkQueue::new(format!("TaskInfo:{}", id));
+    let mut queues = vec![(None, shared.clone())];
+    let shard_prefix = format!("TaskInfo:{}:node:", id);
+    let shard_keys = rds::scan_keys(con, &format!("{}*", shard_prefix)).await?;
+    for key in shard_keys {
+        // skip the shards' own processing lists and leases
+        let Some(owner) = key.strip_prefix(&shard_prefix).filter(|o| !o.contains(':')) else { continue; };
+        queues.push((Some(owner.to_string()), WorkQueue::new(key.clone())));
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    let mut requeued = 0;
+    for (owner, queue) in &queues {
+        let n = queue.recover(con, live, now).await?;
+        if n > 0 { tracing::info!("requeued {} targets of {} held by dead nodes or expired leases", n, queue.key()); }
+        requeued += n;
+        if let Some(owner) = owner.as_ref().filter(|o| !live.contains(o)) {
+            let n = queue.drain_into(con, &shared).await?;
+            if n > 0 { tracing::info!("moved {} targets of task {} from {} to the shared queue", n, id, owner); }
+            requeued += n;
+        }
+    }
+    if requeued > 0 {
+        let req: TaskAddRequest = bson::from_document(task.clone())?;
+        task::redispatch(state, con, &req, id).await?;
+    }
+    Ok(())
+}
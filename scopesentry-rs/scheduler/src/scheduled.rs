@@ -0,0 +1,303 @@
+use std::{str::FromStr, time::Duration};
+
+use axum::{extract::State, Json};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone};
+use cron::Schedule;
+use mongodb::{bson::{self, doc, oid::ObjectId, Document}, Collection};
+use serde_json::json;
+
+use scopesentry_common::{models::{TaskAddRequest, TaskDataRequest, TaskIdRequest, TaskIdsRequest}, util::now_string};
+
+use crate::{AppState, field, number, task::{insert_task, template_params}};
+
+const TICK_INTERVAL: Duration = Duration::from_secs(30);
+const TIME_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+// APScheduler numbering used by the Python backend: 0 = Monday
+const WEEKDAYS: [&str; 7] = ["MON", "TUE", "WED", "THU", "FRI", "SAT", "SUN"];
+
+enum Cycle {
+    Cron(Box<Schedule>),
+    Every(chrono::Duration),
+}
+
+/// Fire due entries of `ScheduledTasks`. Each run is claimed by moving
+/// `nextTime` forward with a compare-and-set before the task is created, so a
+/// restart (or a second scheduler) never fires the same slot twice.
+pub async fn schedule_loop(state: AppState) {
+    loop {
+        if let Err(e) = tick(&state).await {
+            tracing::warn!("scheduled task tick failed: {}", e);
+        }
+        tokio::time::sleep(TICK_INTERVAL).await;
+    }
+}
+
+async fn tick(state: &AppState) -> anyhow::Result<()> {
+    let coll = schedules(state);
+    let now = Local::now();
+    let now_s = now.format(TIME_FORMAT).to_string();
+    let mut cursor = coll.find(doc!{"scheduledTasks": true, "nextTime": {"$gt": "", "$lte": &now_s}}).await?;
+    let mut due = vec![];
+    while cursor.advance().await? {
+        due.push(cursor.deserialize_current()?);
+    }
+
+    for d in due {
+        let id = d.get_str("id").unwrap_or_default().to_string();
+        let next = match next_run(&d, now) {
+            Ok(t) => t.format(TIME_FORMAT).to_string(),
+            Err(e) => {
+                tracing::warn!("schedule {} disabled: {}", id, e);
+                let _ = coll.update_one(doc!{"_id": d.get("_id")}, doc!{"$set": {"scheduledTasks": false}}).await;
+                continue;
+            }
+        };
+        let filter = doc!{"_id": d.get("_id"), "nextTime": d.get_str("nextTime").unwrap_or_default()};
+        let update = doc!{"$set": {"nextTime": &next, "lastTime": &now_s}};
+        if coll.find_one_and_update(filter, update).await?.is_none() {
+            // claimed elsewhere
+            continue;
+        }
+        tracing::info!("scheduler scan {}", id);
+        if let Err(e) = fire(state, &d).await {
+            tracing::error!("scheduled task {} failed: {}", id, e);
+        }
+    }
+    Ok(())
+}
+
+async fn fire(state: &AppState, d: &Document) -> anyhow::Result<()> {
+    let mut req: TaskAddRequest = bson::from_document(d.clone())?;
+    req.name = format!("{}-{}", req.name, now_string());
+    req.scheduledTasks = false;
+    let (params, vullist) = template_params(state, &req.template).await?;
+    insert_task(state, &req, params, vullist).await?;
+    Ok(())
+}
+
+/// Check that the cycle settings of a request describe a valid schedule.
+pub fn validate(req: &TaskAddRequest) -> anyhow::Result<()> {
+    cycle(&bson::to_document(req)?).map(|_| ())
+}
+
+/// Persist the schedule of a freshly created task. The schedule shares the
+/// task's `_id`, which is how task deletion finds it again.
+pub async fn insert_schedule(state: &AppState, req: &TaskAddRequest, task_id: ObjectId) -> anyhow::Result<()> {
+    let mut d = bson::to_document(req)?;
+    d.insert("_id", task_id);
+    d.insert("id", task_id.to_hex());
+    d.insert("type", "scan");
+    d.insert("lastTime", "");
+    let next = next_run(&d, Local::now())?;
+    d.insert("nextTime", next.format(TIME_FORMAT).to_string());
+    schedules(state).insert_one(d).await?;
+    Ok(())
+}
+
+pub async fn scheduled_data(State(state): State<AppState>, Json(req): Json<TaskDataRequest>) -> Json<serde_json::Value> {
+    let Some((skip, limit)) = req.page() else { return Json(json!({"code":400, "message":"invalid pageIndex"})); };
+    let coll = schedules(&state);
+    let filter = doc!{"name": {"$regex": regex::escape(&req.search), "$options": "i"}};
+    let total = coll.count_documents(filter.clone()).await.unwrap_or(0);
+
+    let Ok(mut cursor) = coll.find(filter)
+        .skip(skip)
+        .limit(limit as i64)
+        .await else { return Json(json!({"code":500, "message":"error"})); };
+
+    let mut list = vec![];
+    while let Ok(true) = cursor.advance().await {
+        let Ok(d) = cursor.deserialize_current() else { continue; };
+        list.push(json!({
+            "id": field(&d, "id"),
+            "name": field(&d, "name"),
+            "type": field(&d, "type"),
+            "lastTime": field(&d, "lastTime"),
+            "nextTime": field(&d, "nextTime"),
+            "state": d.get_bool("scheduledTasks").unwrap_or(false),
+            "node": field(&d, "node"),
+            "allNode": field(&d, "allNode"),
+            "cycle": describe(&d),
+            "cycleType": field(&d, "cycleType"),
+            "hour": field(&d, "hour"),
+            "minute": field(&d, "minute"),
+            "day": field(&d, "day"),
+            "week": field(&d, "week"),
+            "cron": field(&d, "cron"),
+        }));
+    }
+    Json(json!({"code":200, "data": {"list": list, "total": total}}))
+}
+
+pub async fn pause_scheduled(State(state): State<AppState>, Json(req): Json<TaskIdsRequest>) -> Json<serde_json::Value> {
+    let res = schedules(&state)
+        .update_many(doc!{"id": {"$in": &req.ids}}, doc!{"$set": {"scheduledTasks": false}})
+        .await;
+    match res {
+        Ok(r) if r.matched_count > 0 => Json(json!({"code":200, "message":"success"})),
+        _ => Json(json!({"code":404, "message":"Scheduled Task not found"})),
+    }
+}
+
+pub async fn resume_scheduled(State(state): State<AppState>, Json(req): Json<TaskIdsRequest>) -> Json<serde_json::Value> {
+    let coll = schedules(&state);
+    let mut resumed = 0;
+    for id in &req.ids {
+        let Ok(Some(d)) = coll.find_one(doc!{"id": id}).await else { continue; };
+        let Ok(next) = next_run(&d, Local::now()) else { continue; };
+        let update = doc!{"$set": {"scheduledTasks": true, "nextTime": next.format(TIME_FORMAT).to_string()}};
+        if coll.update_one(doc!{"id": id}, update).await.is_ok() { resumed += 1; }
+    }
+    if resumed == 0 {
+        return Json(json!({"code":404, "message":"Scheduled Task not found"}));
+    }
+    Json(json!({"code":200, "message":"success"}))
+}
+
+pub async fn run_scheduled(State(state): State<AppState>, Json(req): Json<TaskIdRequest>) -> Json<serde_json::Value> {
+    let coll = schedules(&state);
+    let Ok(Some(d)) = coll.find_one(doc!{"id": &req.id}).await else {
+        return Json(json!({"code":404, "message":"Not Found Task"}));
+    };
+    if let Err(e) = fire(&state, &d).await {
+        tracing::error!("run scheduled {} failed: {}", req.id, e);
+        return Json(json!({"code":500, "message":"error"}));
+    }
+    let _ = coll.update_one(doc!{"id": &req.id}, doc!{"$set": {"lastTime": now_string()}}).await;
+    Json(json!({"code":200, "message":"task run success"}))
+}
+
+fn schedules(state: &AppState) -> Collection<Document> {
+    scopesentry_common::mongo::db(&state.mongo, &state.cfg).collection("ScheduledTasks")
+}
+
+fn cycle(d: &Document) -> anyhow::Result<Cycle> {
+    let int = |k: &str| number(d.get(k)) as i64;
+    let (hour, minute, day, week) = (int("hour"), int("minute"), int("day"), int("week"));
+    let expr = match d.get_str("cycleType").unwrap_or("") {
+        "daily" => format!("0 {} {} * * *", minute, hour),
+        "weekly" => format!("0 {} {} * * {}", minute, hour, WEEKDAYS[week.rem_euclid(7) as usize]),
+        "monthly" => format!("0 {} {} {} * *", minute, hour, day),
+        "nhours" => return every(chrono::Duration::hours(hour) + chrono::Duration::minutes(minute)),
+        "ndays" => return every(chrono::Duration::days(day) + chrono::Duration::hours(hour) + chrono::Duration::minutes(minute)),
+        "cron" => {
+            // accept the classic five-field form as well as cron's seconds-first form
+            let raw = d.get_str("cron").unwrap_or("").trim();
+            if raw.split_whitespace().count() == 5 { format!("0 {}", raw) } else { raw.to_string() }
+        }
+        other => anyhow::bail!("unknown cycleType '{}'", other),
+    };
+    Ok(Cycle::Cron(Box::new(Schedule::from_str(&expr)?)))
+}
+
+fn every(step: chrono::Duration) -> anyhow::Result<Cycle> {
+    if step <= chrono::Duration::zero() {
+        anyhow::bail!("interval must be positive");
+    }
+    Ok(Cycle::Every(step))
+}
+
+fn next_run(d: &Document, now: DateTime<Local>) -> anyhow::Result<DateTime<Local>> {
+    match cycle(d)? {
+        Cycle::Cron(s) => s.after(&now).next().ok_or_else(|| anyhow::anyhow!("schedule never fires")),
+        Cycle::Every(step) => {
+            // keep the interval anchored to the previous slot unless runs were missed
+            let prev = d.get_str("nextTime").ok()
+                .and_then(|s| NaiveDateTime::parse_from_str(s, TIME_FORMAT).ok())
+                .and_then(|n| Local.from_local_datetime(&n).single());
+            Ok(prev.map(|p| p + step).filter(|t| *t > now).unwrap_or(now + step))
+        }
+    }
+}
+
+fn describe(d: &Document) -> String {
+    let int = |k: &str| number(d.get(k)) as i64;
+    let (hour, minute, day, week) = (int("hour"), int("minute"), int("day"), int("week"));
+    match d.get_str("cycleType").unwrap_or("") {
+        "daily" => format!("Every day at {}:{}", hour, minute),
+        "ndays" => format!("Every {} days at {}:{}", day, hour, minute),
+        "nhours" => format!("Every {}h {}m", hour, minute),
+        "weekly" => format!("Every week on day {} at {}:{}", week, hour, minute),
+        "monthly" => format!("Every month on day {} at {}:{}", day, hour, minute),
+        "cron" => d.get_str("cron").unwrap_or("").to_string(),
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Wednesday, 2026-06-10 at `h:m:s`, local time.
+    fn wed(h: u32, m: u32, s: u32) -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 6, 10, h, m, s).unwrap()
+    }
+
+    fn at(s: &str) -> DateTime<Local> {
+        Local.from_local_datetime(&NaiveDateTime::parse_from_str(s, TIME_FORMAT).unwrap()).unwrap()
+    }
+
+    fn next(d: Document, now: DateTime<Local>) -> String {
+        next_run(&d, now).unwrap().format(TIME_FORMAT).to_string()
+    }
+
+    #[test]
+    fn daily_fires_at_the_next_hour_and_minute() {
+        let d = doc!{"cycleType": "daily", "hour": 3, "minute": 30};
+        assert_eq!(next(d.clone(), wed(2, 0, 0)), "2026-06-10 03:30:00");
+        assert_eq!(next(d, wed(12, 0, 0)), "2026-06-11 03:30:00");
+    }
+
+    #[test]
+    fn weekly_counts_weekdays_from_monday() {
+        assert_eq!(next(doc!{"cycleType": "weekly", "week": 0, "hour": 9, "minute": 0}, wed(12, 0, 0)), "2026-06-15 09:00:00");
+        assert_eq!(next(doc!{"cycleType": "weekly", "week": 2, "hour": 18, "minute": 5}, wed(12, 0, 0)), "2026-06-10 18:05:00");
+        assert_eq!(next(doc!{"cycleType": "weekly", "week": 6, "hour": 0, "minute": 0}, wed(12, 0, 0)), "2026-06-14 00:00:00");
+    }
+
+    #[test]
+    fn monthly_fires_on_the_day_of_month() {
+        let d = doc!{"cycleType": "monthly", "day": 1, "hour": 0, "minute": 0};
+        assert_eq!(next(d, wed(12, 0, 0)), "2026-07-01 00:00:00");
+        let d = doc!{"cycleType": "monthly", "day": 15, "hour": 8, "minute": 0};
+        assert_eq!(next(d, wed(12, 0, 0)), "2026-06-15 08:00:00");
+    }
+
+    #[test]
+    fn intervals_start_from_now_without_a_previous_slot() {
+        assert_eq!(next(doc!{"cycleType": "nhours", "hour": 2, "minute": 30}, wed(12, 0, 0)), "2026-06-10 14:30:00");
+        assert_eq!(next(doc!{"cycleType": "ndays", "day": 1, "hour": 6, "minute": 0}, wed(12, 0, 0)), "2026-06-11 18:00:00");
+        assert!(next_run(&doc!{"cycleType": "nhours", "hour": 0, "minute": 0}, wed(12, 0, 0)).is_err());
+        assert!(next_run(&doc!{"cycleType": "ndays", "day": -1}, wed(12, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn intervals_stay_anchored_to_the_previous_slot() {
+        let d = doc!{"cycleType": "nhours", "hour": 1, "minute": 0, "nextTime": "2026-06-10 11:30:00"};
+        // fired a little late: the next slot keeps the :30 rhythm
+        assert_eq!(next(d, wed(12, 0, 5)), "2026-06-10 12:30:00");
+        // runs were missed: start over from now
+        let d = doc!{"cycleType": "nhours", "hour": 1, "minute": 0, "nextTime": "2026-06-10 08:00:00"};
+        assert_eq!(next(d, wed(12, 0, 5)), "2026-06-10 13:00:05");
+        // an unreadable slot counts as none
+        let d = doc!{"cycleType": "ndays", "day": 2, "nextTime": "soon"};
+        assert_eq!(next_run(&d, wed(12, 0, 0)).unwrap(), at("2026-06-12 12:00:00"));
+    }
+
+    #[test]
+    fn cron_takes_five_or_six_fields() {
+        assert_eq!(next(doc!{"cycleType": "cron", "cron": "*/15 * * * *"}, wed(12, 0, 0)), "2026-06-10 12:15:00");
+        assert_eq!(next(doc!{"cycleType": "cron", "cron": " 30 0 12 * * * "}, wed(12, 0, 0)), "2026-06-10 12:00:30");
+        // the slot at `now` has already fired
+        assert_eq!(next(doc!{"cycleType": "cron", "cron": "0 12 * * *"}, wed(12, 0, 0)), "2026-06-11 12:00:00");
+        assert!(next_run(&doc!{"cycleType": "cron", "cron": "every tuesday"}, wed(12, 0, 0)).is_err());
+    }
+
+    #[test]
+    fn unknown_cycle_is_rejected() {
+        assert!(cycle(&doc!{"cycleType": "yearly"}).is_err());
+        assert!(cycle(&doc!{}).is_err());
+        assert!(matches!(cycle(&doc!{"cycleType": "daily", "hour": 1}), Ok(Cycle::Cron(_))));
+        assert!(matches!(cycle(&doc!{"cycleType": "nhours", "hour": 1}), Ok(Cycle::Every(_))));
+    }
+}
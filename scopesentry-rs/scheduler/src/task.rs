@@ -0,0 +1,424 @@
+use std::collections::HashMap;
+
+use axum::{extract::State, Json};
+use mongodb::{bson::{self, doc, oid::ObjectId, Document}, Collection};
+use redis::AsyncCommands;
+use serde_json::json;
+
+use scopesentry_common::{rds::{self, WorkQueue}, models::{TaskAddRequest, TaskDataRequest, TaskIdRequest, TaskDeleteRequest, TemplateDoc, DispatchTemplate}, template::{resolve_parameters, ParamTables}, util::{now_string, expand_targets}};
+
+use crate::{distribute, node::online_nodes, AppState, field, scheduled};
+
+// scan stages reported by the scanner in `TaskInfo:progress:{id}:{target}`
+const STAGES: [&str; 13] = [
+    "TargetHandler",
+    "SubdomainScan",
+    "SubdomainSecurity",
+    "PortScanPreparation",
+    "PortScan",
+    "PortFingerprint",
+    "AssetMapping",
+    "AssetHandle",
+    "URLScan",
+    "WebCrawler",
+    "URLSecurity",
+    "DirScan",
+    "VulnerabilityScan",
+];
+
+// collections holding scan results keyed by `taskName`
+const RESULT_COLLECTIONS: [&str; 8] = [
+    "asset",
+    "SubdoaminTakerResult",
+    "UrlScan",
+    "crawler",
+    "SensitiveResult",
+    "DirScanResult",
+    "vulnerability",
+    "PageMonitoring",
+];
+
+// plugin the vulnerability scan runs under when a template selects POCs
+const POC_PLUGIN: &str = "ed93b8af6b72fe54a60efdb932cf6fbc";
+
+pub async fn task_data(State(state): State<AppState>, Json(req): Json<TaskDataRequest>) -> Json<serde_json::Value> {
+    let Some((skip, limit)) = req.page() else { return Json(json!({"code":400, "message":"invalid pageIndex"})); };
+    let db = scopesentry_common::mongo::db(&state.mongo, &state.cfg);
+    let coll: Collection<Document> = db.collection("task");
+    let filter = doc!{"name": {"$regex": regex::escape(&req.search), "$options": "i"}};
+    let total = coll.count_documents(filter.clone()).await.unwrap_or(0);
+
+    let Ok(mut cursor) = coll.find(filter)
+        .sort(doc!{"creatTime": -1})
+        .skip(skip)
+        .limit(limit as i64)
+        .await else { return Json(json!({"code":500, "message":"error"})); };
+
+    let mut list = vec![];
+    while let Ok(true) = cursor.advance().await {
+        let Ok(d) = cursor.deserialize_current() else { continue; };
+        list.push(json!({
+            "id": d.get_object_id("_id").map(|o| o.to_hex()).unwrap_or_default(),
+            "status": field(&d, "status"),
+            "name": field(&d, "name"),
+            "taskNum": field(&d, "taskNum"),
+            "progress": field(&d, "progress"),
+            "creatTime": field(&d, "creatTime"),
+            "endTime": field(&d, "endTime"),
+        }));
+    }
+    Json(json!({"code":200, "data": {"list": list, "total": total}}))
+}
+
+pub async fn progress_info(State(state): State<AppState>, Json(req): Json<TaskIdRequest>) -> Json<serde_json::Value> {
+    let Ok(oid) = ObjectId::parse_str(&req.id) else { return Json(json!({"code":400, "message":"ID is missing in the request data"})); };
+    let Some((skip, limit)) = req.page() else { return Json(json!({"code":400, "message":"invalid pageIndex"})); };
+    let db = scopesentry_common::mongo::db(&state.mongo, &state.cfg);
+    let coll: Collection<Document> = db.collection("task");
+    let Ok(Some(task)) = coll.find_one(doc!{"_id": oid}).await else {
+        return Json(json!({"code":404, "message":"Content not found for the provided ID"}));
+    };
+
+    let targets = expand_targets(task.get_str("target").unwrap_or(""), task.get_str("ignore").unwrap_or(""));
+    let page: Vec<&String> = targets.iter().skip(skip as usize).take(limit as usize).collect();
+
+    let Ok(mut con) = rds::connect_redis(&state.cfg).await else { return Json(json!({"code":500, "message":"error"})); };
+    let mut list = vec![];
+    for t in page {
+        let key = format!("TaskInfo:progress:{}:{}", req.id, t);
+        let h: std::collections::HashMap<String, String> = con.hgetall(&key).await.unwrap_or_default();
+        let get = |k: String| h.get(&k).cloned().unwrap_or_default();
+        let mut entry = serde_json::Map::new();
+        entry.insert("_id".into(), json!(t));
+        entry.insert("target".into(), json!(t));
+        entry.insert("node".into(), json!(get("node".into())));
+        for stage in STAGES {
+            entry.insert(stage.into(), json!([get(format!("{}_start", stage)), get(format!("{}_end", stage))]));
+        }
+        entry.insert("All".into(), json!([get("scan_start".into()), get("scan_end".into())]));
+        list.push(serde_json::Value::Object(entry));
+    }
+    Json(json!({"code":200, "data": {"list": list, "total": field(&task, "taskNum")}}))
+}
+
+pub async fn delete_task(State(state): State<AppState>, Json(req): Json<TaskDeleteRequest>) -> Json<serde_json::Value> {
+    let oids: Vec<ObjectId> = req.ids.iter().filter_map(|id| ObjectId::parse_str(id).ok()).collect();
+    if oids.is_empty() {
+        return Json(json!({"code":404, "message":"Task not found"}));
+    }
+
+    let Ok(mut con) = rds::connect_redis(&state.cfg).await else { return Json(json!({"code":500, "message":"error"})); };
+    for id in &req.ids {
+        clear_task_keys(&mut con, id).await;
+    }
+
+    let db = scopesentry_common::mongo::db(&state.mongo, &state.cfg);
+    let _ = db.collection::<Document>("ScheduledTasks").delete_many(doc!{"id": {"$in": &req.ids}}).await;
+
+    let coll: Collection<Document> = db.collection("task");
+    if req.delA {
+        // cascade: remove results produced by these tasks in the background
+        let mut names = vec![];
+        if let Ok(mut cursor) = coll.find(doc!{"_id": {"$in": &oids}}).projection(doc!{"name": 1}).await {
+            while let Ok(true) = cursor.advance().await {
+                if let Ok(d) = cursor.deserialize_current() {
+                    if let Ok(n) = d.get_str("name") { names.push(n.to_string()); }
+                }
+            }
+        }
+        let (db, ids) = (db.clone(), req.ids.clone());
+        tokio::spawn(async move {
+            for name in RESULT_COLLECTIONS {
+                match db.collection::<Document>(name).delete_many(doc!{"taskName": {"$in": &names}}).await {
+                    Ok(r) => tracing::info!("deleted {} {} documents", name, r.deleted_count),
+                    Err(e) => tracing::warn!("delete {} failed: {}", name, e),
+                }
+            }
+            if let Err(e) = forget_subdomains(&db, &ids, &names).await {
+                tracing::warn!("delete subdomain failed: {}", e);
+            }
+        });
+    }
+
+    match coll.delete_many(doc!{"_id": {"$in": &oids}}).await {
+        Ok(r) if r.deleted_count > 0 => Json(json!({"code":200, "message":"Task deleted successfully"})),
+        _ => Json(json!({"code":404, "message":"Task not found"})),
+    }
+}
+
+/// Take deleted tasks (`ids`, named `names`) out of `subdomain.tasks`. A
+/// host still found by another task stays, its `taskName` moved to one of
+/// those; hosts no other task found are deleted.
+async fn forget_subdomains(db: &mongodb::Database, ids: &[String], names: &[String]) -> mongodb::error::Result<()> {
+    let coll = db.collection::<Document>("subdomain");
+    coll.update_many(doc!{"tasks": {"$in": ids}}, doc!{"$pull": {"tasks": {"$in": ids}}}).await?;
+    let stale = doc!{"taskName": {"$in": names}, "tasks.0": {"$exists": true}};
+    let survivors: Vec<ObjectId> = coll.distinct("tasks", stale.clone()).await?
+        .iter()
+        .filter_map(|id| ObjectId::parse_str(id.as_str()?).ok())
+        .collect();
+    let mut cursor = db.collection::<Document>("task").find(doc!{"_id": {"$in": survivors}}).projection(doc!{"name": 1}).await?;
+    while cursor.advance().await? {
+        let d = cursor.deserialize_current()?;
+        let (Ok(id), Ok(name)) = (d.get_object_id("_id"), d.get_str("name")) else { continue; };
+        let mut filter = stale.clone();
+        filter.insert("tasks", id.to_hex());
+        coll.update_many(filter, doc!{"$set": {"taskName": name}}).await?;
+    }
+    // documents from before `tasks` existed belong to their `taskName` alone
+    let orphaned = doc!{"$or": [{"tasks": {"$size": 0}}, {"tasks": {"$exists": false}, "taskName": {"$in": names}}]};
+    let r = coll.delete_many(orphaned).await?;
+    tracing::info!("deleted {} subdomain documents", r.deleted_count);
+    Ok(())
+}
+
+pub async fn retest_task(State(state): State<AppState>, Json(req): Json<TaskIdRequest>) -> Json<serde_json::Value> {
+    let Ok(oid) = ObjectId::parse_str(&req.id) else { return Json(json!({"code":400, "message":"ID is missing in the request data"})); };
+    let db = scopesentry_common::mongo::db(&state.mongo, &state.cfg);
+    let coll: Collection<Document> = db.collection("task");
+    let Ok(Some(task)) = coll.find_one(doc!{"_id": oid}).await else {
+        return Json(json!({"code":404, "message":"Content not found for the provided ID"}));
+    };
+    let Ok(task_req) = bson::from_document::<TaskAddRequest>(task) else {
+        return Json(json!({"code":500, "message":"error"}));
+    };
+
+    if let Err(e) = create_scan_task(&state, &task_req, &req.id).await {
+        tracing::error!("retest {} failed: {}", req.id, e);
+        return Json(json!({"code":400, "message":"Failed to add Task"}));
+    }
+    let update = doc!{"$set": {"progress": 0.0_f64, "creatTime": now_string(), "endTime": "", "status": 1_i32}};
+    let _ = coll.update_one(doc!{"_id": oid}, update).await;
+    Json(json!({"code":200, "message":"Task added successfully"}))
+}
+
+pub async fn add_task(State(state): State<AppState>, Json(req): Json<TaskAddRequest>) -> Json<serde_json::Value> {
+    // validate
+    if req.name.trim().is_empty() || (req.node.is_empty() && !req.allNode) {
+        return Json(json!({"code":400, "message":"invalid args"}));
+    }
+    if req.scheduledTasks && scheduled::validate(&req).is_err() {
+        return Json(json!({"code":400, "message":"invalid schedule"}));
+    }
+    let (params, vullist) = match template_params(&state, &req.template).await {
+        Ok(resolved) => resolved,
+        Err(e) => return Json(json!({"code":400, "message": e.to_string()})),
+    };
+
+    let task_id = match insert_task(&state, &req, params, vullist).await {
+        Ok(id) => id,
+        Err(e) => {
+            tracing::error!("add task {} failed: {}", req.name, e);
+            return Json(json!({"code":400, "message":"Failed to add Task"}));
+        }
+    };
+    if req.scheduledTasks {
+        if let Err(e) = scheduled::insert_schedule(&state, &req, task_id).await {
+            tracing::error!("schedule {} failed: {}", req.name, e);
+            return Json(json!({"code":500, "message":"Task added, but its schedule could not be saved"}));
+        }
+    }
+    Json(json!({"code":200, "message":"Task added successfully"}))
+}
+
+/// Store a new `task` document for the request and dispatch it right away
+/// with the template's resolved `params` and `vullist`. The document is
+/// removed again when the dispatch fails.
+pub async fn insert_task(
+    state: &AppState,
+    req: &TaskAddRequest,
+    params: HashMap<String, HashMap<String, String>>,
+    vullist: Vec<String>,
+) -> anyhow::Result<ObjectId> {
+    // expand targets
+    let targets = expand_targets(&req.target, &req.ignore);
+    let task_num = targets.len() as i32;
+
+    // insert task doc
+    let db = scopesentry_common::mongo::db(&state.mongo, &state.cfg);
+    let task_coll: Collection<Document> = db.collection("task");
+    let now = now_string();
+    let doc = doc!{
+        "name": &req.name,
+        "target": targets.join("\n"),
+        "ignore": &req.ignore,
+        "node": bson::to_bson(&req.node)?,
+        "allNode": req.allNode,
+        "scheduledTasks": req.scheduledTasks,
+        "template": &req.template,
+        "duplicates": String::from(req.duplicates),
+        "distribution": bson::to_bson(&req.distribution)?,
+        "taskNum": task_num,
+        "progress": 0.0_f64,
+        "creatTime": &now,
+        "endTime": "",
+        "status": 1_i32,
+        "type": "scan",
+    };
+    let ins_res = task_coll.insert_one(doc).await?;
+    let task_id = ins_res.inserted_id.as_object_id().unwrap_or_default();
+
+    if let Err(e) = start_scan_task(state, req, &task_id.to_hex(), params, vullist).await {
+        if let Ok(mut con) = rds::connect_redis(&state.cfg).await {
+            clear_task_keys(&mut con, &task_id.to_hex()).await;
+        }
+        let _ = task_coll.delete_one(doc!{"_id": task_id}).await;
+        return Err(e);
+    }
+    Ok(task_id)
+}
+
+/// Enqueue the targets of a task and dispatch its resolved template to every
+/// selected node, clearing progress left over from a previous run first.
+pub async fn create_scan_task(state: &AppState, req: &TaskAddRequest, task_id: &str) -> anyhow::Result<()> {
+    // load template and resolve placeholders before touching the queues
+    let (params, vullist) = template_params(state, &req.template).await?;
+    start_scan_task(state, req, task_id, params, vullist).await
+}
+
+/// [`create_scan_task`] with the template already resolved.
+async fn start_scan_task(
+    state: &AppState,
+    req: &TaskAddRequest,
+    task_id: &str,
+    params: HashMap<String, HashMap<String, String>>,
+    vullist: Vec<String>,
+) -> anyhow::Result<()> {
+    let mut con = rds::connect_redis(&state.cfg).await?;
+    clear_task_keys(&mut con, task_id).await;
+
+    // resolve all online nodes if allNode
+    let mut nodes = req.node.clone();
+    if req.allNode {
+        for name in online_nodes(&mut con).await {
+            if !nodes.contains(&name) { nodes.push(name); }
+        }
+    }
+
+    // enqueue targets to redis, consumed from the head in order
+    let targets = expand_targets(&req.target, &req.ignore);
+    distribute::enqueue(&mut con, task_id, req.distribution, targets, &nodes).await?;
+    dispatch(&mut con, req, task_id, params, vullist, &nodes).await
+}
+
+/// Send the task's template again to its nodes that are online, so targets
+/// requeued after a node was lost are picked up even when the other nodes
+/// had already run out of work. Nodes that still have the template queued or
+/// are working on it pick those targets up anyway and are skipped.
+pub async fn redispatch(state: &AppState, con: &mut redis::aio::MultiplexedConnection, req: &TaskAddRequest, task_id: &str) -> anyhow::Result<()> {
+    let (params, vullist) = template_params(state, &req.template).await?;
+    let online = online_nodes(con).await;
+    let candidates: Vec<String> = if req.allNode {
+        online
+    } else {
+        req.node.iter().filter(|n| online.contains(n)).cloned().collect()
+    };
+    let mut nodes = vec![];
+    for name in candidates {
+        if !holds_task(con, &name, task_id).await? { nodes.push(name); }
+    }
+    dispatch(con, req, task_id, params, vullist, &nodes).await
+}
+
+/// Whether a template of task `task_id` waits in `node`'s `NodeTask:{node}`
+/// queue or is claimed from it.
+async fn holds_task(con: &mut redis::aio::MultiplexedConnection, node: &str, task_id: &str) -> redis::RedisResult<bool> {
+    let items = WorkQueue::new(format!("NodeTask:{}", node)).pending_and_held(con, node).await?;
+    Ok(items.iter()
+        .filter_map(|item| serde_json::from_str::<serde_json::Value>(item).ok())
+        .any(|t| t.get("ID").and_then(|id| id.as_str()) == Some(task_id)))
+}
+
+async fn dispatch(
+    con: &mut redis::aio::MultiplexedConnection,
+    req: &TaskAddRequest,
+    task_id: &str,
+    params: HashMap<String, HashMap<String, String>>,
+    vullist: Vec<String>,
+    nodes: &[String],
+) -> anyhow::Result<()> {
+    let dispatch = DispatchTemplate{
+        Parameters: params,
+        TaskName: req.name.clone(),
+        ignore: req.ignore.clone(),
+        duplicates: req.duplicates,
+        ID: task_id.to_string(),
+        r#type: "scan".to_string(),
+        IsStart: false,
+        vullist,
+    };
+
+    // dispatch to each node
+    for name in nodes {
+        let key = format!("NodeTask:{}", name);
+        rds::rpush_json(con, &key, &dispatch).await?;
+    }
+    Ok(())
+}
+
+/// Load a scan template, resolve its `{dict.*}`/`{port.*}` placeholders and
+/// return the parameters with the template's `vullist`. Selecting POCs
+/// enables the vulnerability scan even when the template has no plugin for it.
+/// An unknown template id dispatches with no parameters.
+pub async fn template_params(state: &AppState, template: &str) -> anyhow::Result<(HashMap<String, HashMap<String, String>>, Vec<String>)> {
+    let db = scopesentry_common::mongo::db(&state.mongo, &state.cfg);
+    let tmpl_coll: Collection<TemplateDoc> = db.collection("ScanTemplates");
+    let tmpl = match ObjectId::parse_str(template) {
+        Ok(oid) => tmpl_coll.find_one(doc!{"_id": oid}).await?,
+        Err(_) => None,
+    };
+    let Some(tmpl) = tmpl else { return Ok((HashMap::new(), vec![])); };
+    let tables = ParamTables::load(&db).await?;
+    let mut params = resolve_parameters(&tmpl.Parameters, &tables).map_err(|errors| {
+        let msgs: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        anyhow::anyhow!("parameter error: {}", msgs.join("; "))
+    })?;
+    if !tmpl.vullist.is_empty() {
+        let plugins = params.entry("VulnerabilityScan".to_string()).or_default();
+        if plugins.is_empty() { plugins.insert(POC_PLUGIN.to_string(), String::new()); }
+    }
+    Ok((params, tmpl.vullist))
+}
+
+async fn clear_task_keys(con: &mut redis::aio::MultiplexedConnection, task_id: &str) {
+    let mut keys = vec![
+        format!("TaskInfo:{}", task_id),
+        format!("TaskInfo:tmp:{}", task_id),
+        format!("TaskInfo:time:{}", task_id),
+    ];
+    // `TaskInfo:{id}:*` holds the target queue's claims and leases
+    for pattern in [format!("TaskInfo:progress:{}:*", task_id), format!("TaskInfo:{}:*", task_id), format!("duplicates:{}:*", task_id)] {
+        let found = rds::scan_keys(con, &pattern).await.unwrap_or_default();
+        keys.extend(found);
+    }
+    let _: redis::RedisResult<i64> = con.del(keys).await;
+}
+
+#[cfg(test)]
+mod tests {
+    //! These run against the Redis server at `REDIS_URL` and pass without
+    //! checking anything when it is unset: `REDIS_URL=redis://127.0.0.1:6379 cargo test`.
+    use super::*;
+
+    #[tokio::test]
+    async fn holds_task_sees_queued_and_claimed_templates() {
+        let Ok(url) = std::env::var("REDIS_URL") else { return };
+        let mut con = redis::Client::open(url).unwrap().get_multiplexed_tokio_connection().await.unwrap();
+        let node = format!("test-holds-{}", std::process::id());
+        let queue = WorkQueue::new(format!("NodeTask:{}", node));
+        let template = |id: &str| json!({"ID": id, "TaskName": "t", "type": "scan"}).to_string();
+        let _: i64 = con.rpush(queue.key(), template("claimed")).await.unwrap();
+        let _: i64 = con.rpush(queue.key(), template("queued")).await.unwrap();
+        let _: i64 = con.rpush(queue.key(), "not json").await.unwrap();
+        queue.claim(&mut con, &node, std::time::Duration::from_secs(60)).await.unwrap();
+
+        assert!(holds_task(&mut con, &node, "claimed").await.unwrap());
+        assert!(holds_task(&mut con, &node, "queued").await.unwrap());
+        assert!(!holds_task(&mut con, &node, "other").await.unwrap());
+        assert!(!holds_task(&mut con, "no-such-node", "queued").await.unwrap());
+
+        let mut keys = rds::scan_keys(&mut con, &format!("{}:*", queue.key())).await.unwrap();
+        keys.push(queue.key().to_string());
+        let _: i64 = con.del(keys).await.unwrap();
+    }
+}
@@ -1,27 +1,103 @@
-import { API_BASE } from './config'
+import { API_BASE, LOGIN_PATH, TOKEN_KEY } from './config'
 
 export type ApiResponse<T> = { code: number; message?: string; data?: T }
 
+const LOGIN_API = '/api/user/login'
+
+// Drop the token and send the user to the login page, back here afterwards.
+function onUnauthorized() {
+  localStorage.removeItem(TOKEN_KEY)
+  if (window.location.pathname === LOGIN_PATH) return
+  const next = window.location.pathname + window.location.search
+  window.location.assign(`${LOGIN_PATH}?next=${encodeURIComponent(next)}`)
+}
+
 async function http<T>(path: string, init?: RequestInit): Promise<T> {
+  const token = localStorage.getItem(TOKEN_KEY)
   const res = await fetch(`${API_BASE}${path}`, {
-    headers: { 'Content-Type': 'application/json' },
+    headers: {
+      'Content-Type': 'application/json',
+      ...(token ? { Authorization: `Bearer ${token}` } : {}),
+    },
     ...init,
   })
+  // the scheduler answers an invalid token with `code: 401` like the Python
+  // API; a failed login is reported the same way and stays on the page
+  if (res.status === 401 && path !== LOGIN_API) {
+    onUnauthorized()
+    throw new Error('登录已过期')
+  }
   if (!res.ok) {
     throw new Error(`HTTP ${res.status}`)
   }
   const json = (await res.json()) as ApiResponse<T>
+  if (json.code === 401 && path !== LOGIN_API) {
+    onUnauthorized()
+    throw new Error(json.message || '登录已过期')
+  }
   if (json.code !== 200) {
     throw new Error(json.message || 'Request failed')
   }
   return json.data as T
 }
 
+export function isLoggedIn() {
+  return !!localStorage.getItem(TOKEN_KEY)
+}
+
+export function logout() {
+  localStorage.removeItem(TOKEN_KEY)
+}
+
+export function login(username: string, password: string) {
+  return http<{ access_token: string }>(LOGIN_API, {
+    method: 'POST',
+    body: JSON.stringify({ username, password }),
+  }).then((data) => {
+    localStorage.setItem(TOKEN_KEY, data.access_token)
+    return data
+  })
+}
+
 export type NodesOnline = { list: string[] }
 export function getNodesOnline() {
   return http<NodesOnline>('/api/node/data/online')
 }
 
+export type NodeStatus = 'online' | 'busy' | 'draining' | 'offline'
+export type NodeInfo = {
+  name: string
+  state: string
+  status: NodeStatus
+  updateTime?: string
+  heartbeatAge?: number | null
+  pendingTasks?: number
+  cpuNum?: string
+  memNum?: string
+  loadAvg?: string
+  running?: string
+  finished?: string
+  target?: string
+  module?: string
+  version?: string
+  uptime?: string
+}
+export function getNodes() {
+  return http<{ list: NodeInfo[] }>('/api/node/data')
+}
+
+export type NodeLimits = { concurrency?: number | null; rate?: number | null }
+export type NodeCommand =
+  | { type: 'pause' | 'resume' | 'restart' | 'uninstall' | 'reload_config' }
+  | ({ type: 'set_limits' } & NodeLimits)
+
+export function controlNodes(names: string[], command: NodeCommand) {
+  return http<unknown>('/api/node/control', {
+    method: 'POST',
+    body: JSON.stringify({ names, command }),
+  })
+}
+
 export type TaskAddRequest = {
   name: string
   target: string
@@ -30,9 +106,13 @@ export type TaskAddRequest = {
   allNode: boolean
   scheduledTasks: boolean
   template: string
-  duplicates: boolean
+  duplicates: Duplicates
+  distribution?: Distribution
 }
 
+export type Duplicates = 'None' | 'subdomain' | 'task'
+export type Distribution = 'shared' | 'round_robin' | 'weighted' | 'sticky'
+
 export function createTask(body: TaskAddRequest) {
   return http<unknown>('/api/task/add', {
     method: 'POST',
@@ -1,2 +1,8 @@
 // Prefer same-origin during dev with Vite proxy; fallback to explicit env
 export const API_BASE = (import.meta.env.VITE_API_BASE as string) || ''
+
+// localStorage key holding the scheduler API token
+export const TOKEN_KEY = 'scopesentry-token'
+
+// route of the login page, where requests without a valid token end up
+export const LOGIN_PATH = '/login'
@@ -4,10 +4,12 @@ import { createBrowserRouter, RouterProvider } from 'react-router-dom'
 import './index.css'
 import { AppLayout } from './pages/_layout'
 import { Dashboard } from './pages/dashboard'
+import { LoginPage } from './pages/login'
 import { NodesPage } from './pages/nodes'
 import { CreateTaskPage } from './pages/task-create'
 
 const router = createBrowserRouter([
+  { path: '/login', element: <LoginPage /> },
   {
     path: '/',
     element: <AppLayout />,
@@ -1,9 +1,15 @@
-import { Outlet, Link, NavLink, useLocation } from 'react-router-dom'
+import { Outlet, Link, NavLink, Navigate, useLocation, useNavigate } from 'react-router-dom'
 import { Button } from '@/components/ui/button'
+import { isLoggedIn, logout } from '@/lib/api'
+import { LOGIN_PATH } from '@/lib/config'
 import { cn } from '@/lib/utils'
 
 export function AppLayout() {
-  const { pathname } = useLocation()
+  const { pathname, search } = useLocation()
+  const navigate = useNavigate()
+  if (!isLoggedIn()) {
+    return <Navigate to={`${LOGIN_PATH}?next=${encodeURIComponent(pathname + search)}`} replace />
+  }
   return (
     <div className="min-h-screen grid grid-rows-[auto_1fr]">
       <header className="border-b">
@@ -18,6 +24,7 @@ export function AppLayout() {
           </div>
           <div className="flex items-center gap-2">
             <Button variant="outline" size="sm" onClick={() => document.documentElement.classList.toggle('dark')}>主题</Button>
+            <Button variant="ghost" size="sm" onClick={() => { logout(); navigate(LOGIN_PATH) }}>退出</Button>
           </div>
         </div>
       </header>
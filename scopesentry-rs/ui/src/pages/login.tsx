@@ -0,0 +1,49 @@
+import { useState } from 'react'
+import { useNavigate, useSearchParams } from 'react-router-dom'
+import { Button } from '@/components/ui/button'
+import { Input } from '@/components/ui/input'
+import { Label } from '@/components/ui/label'
+import { login } from '@/lib/api'
+
+export function LoginPage() {
+  const [username, setUsername] = useState('')
+  const [password, setPassword] = useState('')
+  const [submitting, setSubmitting] = useState(false)
+  const [message, setMessage] = useState<string | null>(null)
+  const navigate = useNavigate()
+  const [params] = useSearchParams()
+
+  async function onSubmit(e: React.FormEvent) {
+    e.preventDefault()
+    setSubmitting(true)
+    setMessage(null)
+    try {
+      await login(username, password)
+      // only return to pages of this app
+      const next = params.get('next') ?? '/'
+      navigate(next.startsWith('/') && !next.startsWith('//') ? next : '/', { replace: true })
+    } catch (e) {
+      setMessage(String(e))
+    } finally {
+      setSubmitting(false)
+    }
+  }
+
+  return (
+    <div className="min-h-screen grid place-items-center">
+      <form className="w-80 grid gap-4 rounded-lg border p-6" onSubmit={onSubmit}>
+        <h2 className="text-lg font-semibold">ScopeSentry RS</h2>
+        <div className="grid gap-2">
+          <Label>用户名</Label>
+          <Input value={username} onChange={(e) => setUsername(e.target.value)} autoComplete="username" autoFocus />
+        </div>
+        <div className="grid gap-2">
+          <Label>密码</Label>
+          <Input type="password" value={password} onChange={(e) => setPassword(e.target.value)} autoComplete="current-password" />
+        </div>
+        <Button type="submit" disabled={!username || !password || submitting}>{submitting ? '登录中...' : '登录'}</Button>
+        {message && <div className="text-sm text-destructive">{message}</div>}
+      </form>
+    </div>
+  )
+}
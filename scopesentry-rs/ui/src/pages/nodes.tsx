@@ -1,33 +1,75 @@
-import { useEffect, useState } from 'react'
-import { getNodesOnline } from '@/lib/api'
+import { useCallback, useEffect, useState } from 'react'
+import { Button } from '@/components/ui/button'
+import { controlNodes, getNodes, type NodeCommand, type NodeInfo, type NodeStatus } from '@/lib/api'
+
+const STATUS_STYLE: Record<NodeStatus, string> = {
+  online: 'bg-green-100 text-green-700 dark:bg-green-900/40 dark:text-green-300',
+  busy: 'bg-blue-100 text-blue-700 dark:bg-blue-900/40 dark:text-blue-300',
+  draining: 'bg-yellow-100 text-yellow-700 dark:bg-yellow-900/40 dark:text-yellow-300',
+  offline: 'bg-red-100 text-red-700 dark:bg-red-900/40 dark:text-red-300',
+}
 
 export function NodesPage() {
-  const [nodes, setNodes] = useState<string[]>([])
+  const [nodes, setNodes] = useState<NodeInfo[]>([])
   const [loading, setLoading] = useState(true)
   const [error, setError] = useState<string | null>(null)
 
+  const [sending, setSending] = useState<string | null>(null)
+
+  const load = useCallback(() => getNodes().then((data) => setNodes(data.list)), [])
+
   useEffect(() => {
     let cancelled = false
     setLoading(true)
-    getNodesOnline()
+    getNodes()
       .then((data) => { if (!cancelled) setNodes(data.list) })
       .catch((e) => { if (!cancelled) setError(String(e)) })
       .finally(() => { if (!cancelled) setLoading(false) })
     return () => { cancelled = true }
   }, [])
 
+  // nodes pick commands up within seconds; refresh once they had the chance
+  const send = (name: string, command: NodeCommand) => {
+    if (command.type === 'uninstall' && !window.confirm(`卸载节点 ${name}？`)) return
+    setSending(name)
+    controlNodes([name], command)
+      .then(() => new Promise((r) => setTimeout(r, 3000)))
+      .then(load)
+      .catch((e) => setError(String(e)))
+      .finally(() => setSending(null))
+  }
+
   if (loading) return <div>加载中...</div>
   if (error) return <div className="text-destructive">{error}</div>
 
   return (
     <div>
-      <h2 className="text-lg font-semibold mb-4">在线节点</h2>
+      <h2 className="text-lg font-semibold mb-4">节点</h2>
       <div className="grid gap-3">
-        {nodes.length === 0 && <div className="text-muted-foreground">暂无在线节点</div>}
+        {nodes.length === 0 && <div className="text-muted-foreground">暂无节点</div>}
         {nodes.map((n) => (
-          <div key={n} className="border rounded-md p-3 flex items-center justify-between">
-            <div className="font-mono">{n}</div>
-            <span className="text-xs rounded bg-green-100 text-green-700 px-2 py-1 dark:bg-green-900/40 dark:text-green-300">online</span>
+          <div key={n.name} className="border rounded-md p-3 flex items-center justify-between">
+            <div>
+              <div className="font-mono">{n.name}</div>
+              {n.updateTime && <div className="text-xs text-muted-foreground">{n.updateTime}{n.version && ` · v${n.version}`}</div>}
+              <div className="text-xs text-muted-foreground">
+                CPU {n.cpuNum ?? '-'}% · 内存 {n.memNum ?? '-'}% · 负载 {n.loadAvg ?? '-'} · 运行 {n.running ?? 0} · 完成 {n.finished ?? 0} · 排队 {n.pendingTasks ?? 0}
+              </div>
+              {n.target && <div className="text-xs font-mono">{n.target}{n.module && ` (${n.module})`}</div>}
+            </div>
+            <div className="flex items-center gap-2">
+              {n.status !== 'offline' && (
+                <>
+                  {n.status === 'draining'
+                    ? <Button size="sm" variant="outline" disabled={sending === n.name} onClick={() => send(n.name, { type: 'resume' })}>恢复</Button>
+                    : <Button size="sm" variant="outline" disabled={sending === n.name} onClick={() => send(n.name, { type: 'pause' })}>暂停</Button>}
+                  <Button size="sm" variant="outline" disabled={sending === n.name} onClick={() => send(n.name, { type: 'reload_config' })}>重载配置</Button>
+                  <Button size="sm" variant="outline" disabled={sending === n.name} onClick={() => send(n.name, { type: 'restart' })}>重启</Button>
+                  <Button size="sm" variant="ghost" disabled={sending === n.name} onClick={() => send(n.name, { type: 'uninstall' })}>卸载</Button>
+                </>
+              )}
+              <span className={`text-xs rounded px-2 py-1 ${STATUS_STYLE[n.status] ?? STATUS_STYLE.offline}`}>{n.status}</span>
+            </div>
           </div>
         ))}
       </div>
@@ -4,7 +4,7 @@ import { Input } from '@/components/ui/input'
 import { Label } from '@/components/ui/label'
 import { Textarea } from '@/components/ui/textarea'
 import { Select } from '@/components/ui/select'
-import { createTask, getNodesOnline } from '@/lib/api'
+import { createTask, getNodesOnline, type Distribution, type Duplicates } from '@/lib/api'
 
 export function CreateTaskPage() {
   const [name, setName] = useState('')
@@ -13,7 +13,8 @@ export function CreateTaskPage() {
   const [allNode, setAllNode] = useState(true)
   const [node, setNode] = useState<string[]>([])
   const [template, setTemplate] = useState('')
-  const [duplicates, setDuplicates] = useState(false)
+  const [duplicates, setDuplicates] = useState<Duplicates>('None')
+  const [distribution, setDistribution] = useState<Distribution>('shared')
   const [scheduledTasks, setScheduledTasks] = useState(false)
 
   const [nodeOptions, setNodeOptions] = useState<string[]>([])
@@ -31,7 +32,7 @@ export function CreateTaskPage() {
     setSubmitting(true)
     setMessage(null)
     try {
-      await createTask({ name, target, ignore, node, allNode, scheduledTasks, template, duplicates })
+      await createTask({ name, target, ignore, node, allNode, scheduledTasks, template, duplicates, distribution })
       setMessage('创建成功')
     } catch (e) {
       setMessage(String(e))
@@ -66,6 +67,25 @@ export function CreateTaskPage() {
         <Input value={template} onChange={(e) => setTemplate(e.target.value)} placeholder="Mongo ObjectId" />
       </div>
 
+      <div className="grid gap-2">
+        <Label>去重</Label>
+        <Select value={duplicates} onChange={(e) => setDuplicates(e.target.value as Duplicates)}>
+          <option value="None">不去重（更新已有子域名）</option>
+          <option value="subdomain">跳过历史已发现的子域名</option>
+          <option value="task">跳过本任务已发现的子域名</option>
+        </Select>
+      </div>
+
+      <div className="grid gap-2">
+        <Label>目标分配</Label>
+        <Select value={distribution} onChange={(e) => setDistribution(e.target.value as Distribution)}>
+          <option value="shared">共享队列（节点自行拉取）</option>
+          <option value="round_robin">轮询分片</option>
+          <option value="weighted">按节点负载加权</option>
+          <option value="sticky">按主机固定节点</option>
+        </Select>
+      </div>
+
       <div className="grid gap-2">
         <Label>节点选择</Label>
         <div className="flex items-center gap-3">
@@ -73,10 +93,6 @@ export function CreateTaskPage() {
             <input type="checkbox" checked={allNode} onChange={(e) => setAllNode(e.target.checked)} />
             全部在线节点
           </label>
-          <label className="flex items-center gap-2 text-sm">
-            <input type="checkbox" checked={duplicates} onChange={(e) => setDuplicates(e.target.checked)} />
-            去重
-          </label>
           <label className="flex items-center gap-2 text-sm">
             <input type="checkbox" checked={scheduledTasks} onChange={(e) => setScheduledTasks(e.target.checked)} />
             定时任务